@@ -0,0 +1,40 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use poeng::{
+    encoder::PngEncoder,
+    png_parser::{BitDepth, ColourType, InterlaceMethod, PngFile},
+};
+
+const WIDTH: u32 = 2048;
+const HEIGHT: u32 = 2048;
+
+fn encode(interlace_method: InterlaceMethod) -> Vec<u8> {
+    let data = (0..WIDTH as usize * HEIGHT as usize * 4)
+        .map(|i| ((i % 4096) ^ (i / 8192)) as u8)
+        .collect::<Vec<_>>();
+
+    let mut encoder =
+        PngEncoder::new(WIDTH, HEIGHT, ColourType::TruecolourWithAlpha, BitDepth::B8).unwrap();
+    encoder.set_interlace_method(interlace_method);
+    encoder.encode(&data).unwrap()
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Bytes(WIDTH as u64 * HEIGHT as u64 * 4));
+    group.sample_size(20);
+
+    for (name, interlace_method) in [
+        ("non-interlaced", InterlaceMethod::None),
+        ("adam7", InterlaceMethod::Adam7),
+    ] {
+        let encoded = encode(interlace_method);
+        let png = PngFile::from_reader(&mut encoded.as_slice()).unwrap();
+
+        group.bench_function(name, |b| b.iter(|| png.decode_data().unwrap()));
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
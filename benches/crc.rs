@@ -0,0 +1,134 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use poeng::{
+    encoder::PngEncoder,
+    png_parser::{BitDepth, ChunkType, ColourType, PngFile},
+};
+
+const POLYNOMIAL: u32 = 0xedb8_8320;
+
+/// The usual byte-at-a-time CRC32 table.
+static TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+
+    while n < 256 {
+        let mut crc = n as u32;
+        let mut k = 0;
+
+        while k < 8 {
+            crc = if crc & 1 != 0 {
+                POLYNOMIAL ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            k += 1;
+        }
+
+        table[n] = crc;
+        n += 1;
+    }
+
+    table
+}
+
+/// The CRC32 computation the crate used before switching to lookup tables, for comparison.
+fn update_bitwise(mut crc: u32, bytes: &[u8]) -> u32 {
+    for &byte in bytes {
+        crc ^= byte as u32;
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                POLYNOMIAL ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    crc
+}
+
+/// A single 256-entry table, one byte per lookup.
+fn update_table(mut crc: u32, bytes: &[u8]) -> u32 {
+    for &byte in bytes {
+        crc = TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+
+    crc
+}
+
+/// The CRC of an IDAT chunk with `data`, covering the chunk type like [`PngChunk::is_crc_valid`] does.
+///
+/// [`PngChunk::is_crc_valid`]: poeng::png_parser::PngChunk::is_crc_valid
+fn idat_crc(update: fn(u32, &[u8]) -> u32, data: &[u8]) -> u32 {
+    !update(update(!0, b"IDAT"), data)
+}
+
+fn crc(c: &mut Criterion) {
+    // Noise barely compresses, so the IDAT payload is about as large as the image.
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let data = (0..1024 * 1024 * 4)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect::<Vec<_>>();
+
+    let encoder =
+        PngEncoder::new(1024, 1024, ColourType::TruecolourWithAlpha, BitDepth::B8).unwrap();
+    let encoded = encoder.encode(&data).unwrap();
+    let png = PngFile::from_bytes(&encoded).unwrap();
+    let idats = png
+        .chunks
+        .iter()
+        .filter(|chunk| chunk.chunk_type == ChunkType::IDAT)
+        .collect::<Vec<_>>();
+    // The data of each IDAT chunk, which starts after its length and type.
+    let idat_data = idats
+        .iter()
+        .map(|chunk| {
+            let start = chunk.offset().unwrap() as usize + 8;
+            &encoded[start..start + chunk.length() as usize]
+        })
+        .collect::<Vec<_>>();
+
+    for (chunk, data) in idats.iter().zip(&idat_data) {
+        assert!(chunk.is_crc_valid());
+        assert_eq!(idat_crc(update_bitwise, data), chunk.crc());
+        assert_eq!(idat_crc(update_table, data), chunk.crc());
+    }
+
+    let mut group = c.benchmark_group("crc");
+    group.throughput(Throughput::Bytes(
+        idat_data.iter().map(|data| data.len() as u64).sum(),
+    ));
+
+    group.bench_function("bitwise", |b| {
+        b.iter(|| {
+            idat_data
+                .iter()
+                .map(|data| idat_crc(update_bitwise, data))
+                .collect::<Vec<_>>()
+        })
+    });
+    group.bench_function("table", |b| {
+        b.iter(|| {
+            idat_data
+                .iter()
+                .map(|data| idat_crc(update_table, data))
+                .collect::<Vec<_>>()
+        })
+    });
+    group.bench_function("slice-by-8", |b| {
+        b.iter(|| idats.iter().all(|chunk| chunk.is_crc_valid()))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, crc);
+criterion_main!(benches);
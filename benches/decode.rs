@@ -0,0 +1,44 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use poeng::png_parser::PngFile;
+
+const FIXTURES: [(&str, &str); 5] = [
+    ("paeth-photo", "benches/fixtures/photo-paeth.png"),
+    ("palette", "benches/fixtures/palette.png"),
+    ("grey16", "benches/fixtures/grey16.png"),
+    ("interlaced", "benches/fixtures/interlaced.png"),
+    // Every scanline uses filter type None.
+    ("unfiltered", "FL.png"),
+];
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+
+    for (name, path) in FIXTURES {
+        let png = PngFile::from_bytes(&std::fs::read(path).unwrap()).unwrap();
+        let decoded_length = png.decode_data().unwrap().len();
+
+        group.throughput(Throughput::Bytes(decoded_length as u64));
+        group.bench_function(name, |b| b.iter(|| png.decode_data().unwrap()));
+    }
+
+    group.finish();
+}
+
+fn to_rgba8(c: &mut Criterion) {
+    let mut group = c.benchmark_group("to_rgba8");
+
+    for (name, path) in FIXTURES {
+        let png = PngFile::from_bytes(&std::fs::read(path).unwrap()).unwrap();
+        let header = png.try_parse_header().unwrap();
+
+        group.throughput(Throughput::Bytes(
+            header.width as u64 * header.height as u64 * 4,
+        ));
+        group.bench_function(name, |b| b.iter(|| png.to_rgba8().unwrap()));
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, decode, to_rgba8);
+criterion_main!(benches);
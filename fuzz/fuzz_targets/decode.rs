@@ -0,0 +1,19 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use poeng::{decoder::DecodeOptions, png_parser::PngFile};
+
+fuzz_target!(|data: &[u8]| {
+    let png = match PngFile::from_bytes(data) {
+        Ok(png) => png,
+        Err(_) => return,
+    };
+
+    // Without a limit a tiny file can legitimately ask for gigabytes, which the fuzzer would report as an OOM.
+    let options = DecodeOptions {
+        max_decompressed_bytes: Some(64 * 1024 * 1024),
+        ..Default::default()
+    };
+
+    let _ = png.decode_with(&options);
+});
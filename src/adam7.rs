@@ -0,0 +1,83 @@
+/// One of the seven reduced images of an Adam7-interlaced PNG.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Pass {
+    pub x_start: usize,
+    pub y_start: usize,
+    pub x_step: usize,
+    pub y_step: usize,
+}
+
+pub(crate) const PASSES: [Pass; 7] = [
+    Pass::new(0, 0, 8, 8),
+    Pass::new(4, 0, 8, 8),
+    Pass::new(0, 4, 4, 8),
+    Pass::new(2, 0, 4, 4),
+    Pass::new(0, 2, 2, 4),
+    Pass::new(1, 0, 2, 2),
+    Pass::new(0, 1, 1, 2),
+];
+
+impl Pass {
+    const fn new(x_start: usize, y_start: usize, x_step: usize, y_step: usize) -> Self {
+        Pass {
+            x_start,
+            y_start,
+            x_step,
+            y_step,
+        }
+    }
+
+    /// Width of the reduced image in pixels. A pass can be empty for small images.
+    pub fn width(&self, image_width: u32) -> usize {
+        (image_width as usize)
+            .saturating_sub(self.x_start)
+            .div_ceil(self.x_step)
+    }
+
+    pub fn height(&self, image_height: u32) -> usize {
+        (image_height as usize)
+            .saturating_sub(self.y_start)
+            .div_ceil(self.y_step)
+    }
+
+    /// Maps a row of the full image to the corresponding row of this pass, if the pass covers it.
+    pub fn row_for(&self, y: usize) -> Option<usize> {
+        if y >= self.y_start && (y - self.y_start).is_multiple_of(self.y_step) {
+            Some((y - self.y_start) / self.y_step)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{vec, vec::Vec};
+
+    use super::*;
+
+    #[test]
+    fn pass_sizes_cover_every_pixel() {
+        for (width, height) in [(1, 1), (3, 5), (8, 8), (13, 7), (33, 17)] {
+            let pixels = PASSES
+                .iter()
+                .map(|pass| pass.width(width) * pass.height(height))
+                .sum::<usize>();
+
+            assert_eq!(pixels, (width * height) as usize, "{}x{}", width, height);
+        }
+    }
+
+    #[test]
+    fn pass_sizes_of_small_image() {
+        let sizes = PASSES
+            .iter()
+            .map(|pass| (pass.width(3), pass.height(3)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            sizes,
+            vec![(1, 1), (0, 1), (1, 0), (1, 1), (2, 1), (1, 2), (3, 1)]
+        );
+    }
+}
@@ -0,0 +1,675 @@
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::{
+    convert::{to_rgba8, Layout},
+    decoder::{decode_stream, image_too_large, DecodeOptions, DecodeWarning},
+    png_parser::{ChunkType, PngChunk, PngError, PngFile, PngHeader},
+    reader::SliceReader,
+};
+
+pub(crate) const ACTL: ChunkType = ChunkType::Unknown(*b"acTL");
+pub(crate) const FCTL: ChunkType = ChunkType::Unknown(*b"fcTL");
+pub(crate) const FDAT: ChunkType = ChunkType::Unknown(*b"fdAT");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationControl {
+    pub num_frames: u32,
+    /// Number of times to loop the animation, 0 meaning forever.
+    pub num_plays: u32,
+}
+
+impl<'a> TryFrom<&'a PngChunk> for AnimationControl {
+    type Error = PngError;
+
+    fn try_from(value: &'a PngChunk) -> Result<Self, Self::Error> {
+        if value.chunk_type != ACTL {
+            return Err(PngError::UnexpectedChunkType {
+                expected: ACTL,
+                was: value.chunk_type,
+            });
+        }
+
+        let mut reader = SliceReader::new(&value.data);
+
+        Ok(AnimationControl {
+            num_frames: reader.read_u32()?,
+            num_plays: reader.read_u32()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisposeOp {
+    None,
+    Background,
+    Previous,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendOp {
+    Source,
+    Over,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameControl {
+    pub sequence_number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub delay_num: u16,
+    pub delay_den: u16,
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp,
+}
+
+impl<'a> TryFrom<&'a PngChunk> for FrameControl {
+    type Error = PngError;
+
+    fn try_from(value: &'a PngChunk) -> Result<Self, Self::Error> {
+        if value.chunk_type != FCTL {
+            return Err(PngError::UnexpectedChunkType {
+                expected: FCTL,
+                was: value.chunk_type,
+            });
+        }
+
+        let mut reader = SliceReader::new(&value.data);
+
+        let sequence_number = reader.read_u32()?;
+        let width = reader.read_u32()?;
+        let height = reader.read_u32()?;
+        let x_offset = reader.read_u32()?;
+        let y_offset = reader.read_u32()?;
+        let delay_num = reader.read_u16()?;
+        let delay_den = reader.read_u16()?;
+
+        let dispose_op = match reader.read_u8()? {
+            0 => DisposeOp::None,
+            1 => DisposeOp::Background,
+            2 => DisposeOp::Previous,
+            _ => return Err(PngError::MalformedChunk(FCTL)),
+        };
+
+        let blend_op = match reader.read_u8()? {
+            0 => BlendOp::Source,
+            1 => BlendOp::Over,
+            _ => return Err(PngError::MalformedChunk(FCTL)),
+        };
+
+        if width == 0 || height == 0 {
+            return Err(PngError::MalformedChunk(FCTL));
+        }
+
+        Ok(FrameControl {
+            sequence_number,
+            width,
+            height,
+            x_offset,
+            y_offset,
+            delay_num,
+            delay_den,
+            dispose_op,
+            blend_op,
+        })
+    }
+}
+
+impl FrameControl {
+    /// The frame delay, treating a zero denominator as 1/100 of a second as the APNG spec requires.
+    pub fn delay(&self) -> Duration {
+        let denominator = if self.delay_den == 0 {
+            100
+        } else {
+            self.delay_den
+        };
+
+        Duration::from_secs_f64(self.delay_num as f64 / denominator as f64)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ApngFrame {
+    pub control: FrameControl,
+    /// RGBA8 pixels of the frame region, `control.width * control.height * 4` bytes long, or fewer rows if
+    /// [`DecodeOptions::allow_truncated`] let truncated frame data through.
+    pub pixels: Vec<u8>,
+}
+
+impl PngFile {
+    pub fn animation_control(&self) -> Result<Option<AnimationControl>, PngError> {
+        self.find_chunk(ACTL)
+            .map(AnimationControl::try_from)
+            .transpose()
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.find_chunk(ACTL).is_some()
+    }
+}
+
+/// Iterates over the frames of an animated PNG in sequence order.
+pub struct ApngDecoder<'a> {
+    png: &'a PngFile,
+    header: PngHeader,
+    animation_control: AnimationControl,
+    options: DecodeOptions,
+    position: usize,
+}
+
+impl<'a> ApngDecoder<'a> {
+    pub fn new(png: &'a PngFile) -> Result<Self, PngError> {
+        ApngDecoder::new_with(png, &DecodeOptions::default())
+    }
+
+    /// Like [`new`](Self::new), decoding every frame with `options`.
+    pub fn new_with(png: &'a PngFile, options: &DecodeOptions) -> Result<Self, PngError> {
+        let header = png.try_parse_header()?;
+        let animation_control = png
+            .animation_control()?
+            .ok_or(PngError::MissingChunk(ACTL))?;
+
+        Ok(ApngDecoder {
+            png,
+            header,
+            animation_control,
+            options: options.clone(),
+            position: 0,
+        })
+    }
+
+    pub fn header(&self) -> &PngHeader {
+        &self.header
+    }
+
+    pub fn animation_control(&self) -> AnimationControl {
+        self.animation_control
+    }
+
+    /// Whether the default image (the one described by IDAT) is the first frame of the animation.
+    pub fn default_image_is_first_frame(&self) -> bool {
+        self.png
+            .chunks
+            .iter()
+            .take_while(|chunk| chunk.chunk_type != ChunkType::IDAT)
+            .any(|chunk| chunk.chunk_type == FCTL)
+    }
+
+    fn decode_frame(
+        &self,
+        control: FrameControl,
+        frame_data: &[&'a PngChunk],
+    ) -> Result<ApngFrame, PngError> {
+        let fits_horizontally = control
+            .x_offset
+            .checked_add(control.width)
+            .is_some_and(|right| right <= self.header.width);
+        let fits_vertically = control
+            .y_offset
+            .checked_add(control.height)
+            .is_some_and(|bottom| bottom <= self.header.height);
+
+        if !fits_horizontally || !fits_vertically {
+            return Err(PngError::MalformedChunk(FCTL));
+        }
+
+        if frame_data.is_empty() {
+            return Err(PngError::MissingChunk(FDAT));
+        }
+
+        let mut frame_header = PngHeader {
+            width: control.width,
+            height: control.height,
+            ..self.header
+        };
+
+        let data = frame_data
+            .iter()
+            .map(|chunk| match chunk.chunk_type {
+                // fdAT payloads are IDAT payloads prefixed with a sequence number.
+                FDAT if chunk.data.len() >= 4 => Ok(&chunk.data[4..]),
+                FDAT => Err(PngError::MalformedChunk(FDAT)),
+                _ => Ok(chunk.data.as_slice()),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut decoded = Vec::new();
+        let summary = decode_stream(&frame_header, data.into_iter(), &self.options, &mut decoded)?;
+
+        if let Some(rows) = summary.warnings.iter().find_map(|warning| match warning {
+            DecodeWarning::TruncatedImageData { rows } => Some(*rows),
+            _ => None,
+        }) {
+            frame_header.height = rows;
+        }
+
+        let pixels = to_rgba8(
+            &frame_header,
+            &decoded,
+            self.png,
+            None,
+            self.options.palette_fallback,
+            Layout::Interleaved,
+        )?;
+
+        Ok(ApngFrame { control, pixels })
+    }
+}
+
+impl<'a> Iterator for ApngDecoder<'a> {
+    type Item = Result<ApngFrame, PngError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let chunks = &self.png.chunks;
+
+        let control_index = chunks[self.position..]
+            .iter()
+            .position(|chunk| chunk.chunk_type == FCTL)?
+            + self.position;
+
+        let frame_data = chunks[control_index + 1..]
+            .iter()
+            .take_while(|chunk| chunk.chunk_type != FCTL)
+            .filter(|chunk| matches!(chunk.chunk_type, ChunkType::IDAT | FDAT))
+            .collect::<Vec<_>>();
+
+        self.position = control_index + 1;
+
+        let frame = FrameControl::try_from(&chunks[control_index])
+            .and_then(|control| self.decode_frame(control, &frame_data));
+
+        if frame.is_err() {
+            self.position = chunks.len();
+        }
+
+        Some(frame)
+    }
+}
+
+/// A frame composited onto the full animation canvas.
+#[derive(Debug, Clone)]
+pub struct ComposedFrame {
+    /// RGBA8 pixels of the whole canvas, `width * height * 4` bytes long.
+    pub pixels: Vec<u8>,
+    pub delay: Duration,
+}
+
+impl<'a> ApngDecoder<'a> {
+    /// Decodes every frame and composites it onto a running canvas according to its dispose and blend
+    /// operations.
+    ///
+    /// Only frames described by an fcTL chunk take part in the animation, so a default image without a
+    /// preceding fcTL is not included.
+    pub fn compose(self) -> Result<Vec<ComposedFrame>, PngError> {
+        let options = self.options.clone();
+        self.compose_with(&options)
+    }
+
+    /// Like [`compose`](Self::compose), decoding the frames with `options`. Every composed frame is a copy of
+    /// the whole canvas, so [`DecodeOptions::max_decompressed_bytes`] bounds their total size too.
+    pub fn compose_with(mut self, options: &DecodeOptions) -> Result<Vec<ComposedFrame>, PngError> {
+        self.options = options.clone();
+
+        let header = self.header;
+        let canvas_width = header.width as usize;
+        let expected_frames = self.animation_control.num_frames as usize;
+
+        let frames = self.collect::<Result<Vec<_>, _>>()?;
+
+        if frames.len() != expected_frames {
+            return Err(PngError::MalformedChunk(ACTL));
+        }
+
+        // Frames only have to fit inside the canvas, so a tiny file can still declare a huge one.
+        let canvas_len = canvas_width
+            .checked_mul(header.height as usize)
+            .and_then(|pixels| pixels.checked_mul(4))
+            .ok_or(image_too_large(&header))?;
+        let total_len = canvas_len
+            .checked_mul(frames.len())
+            .ok_or(image_too_large(&header))?;
+
+        if let Some(limit) = options.max_decompressed_bytes {
+            if canvas_len > limit || total_len > limit {
+                return Err(PngError::DecompressedTooLarge { limit });
+            }
+        }
+
+        let mut canvas = try_alloc(canvas_len, &header)?;
+        canvas.resize(canvas_len, 0);
+        let mut composed = Vec::with_capacity(frames.len());
+
+        for (index, frame) in frames.into_iter().enumerate() {
+            let control = frame.control;
+
+            // DISPOSE_OP_PREVIOUS on the first frame is treated as DISPOSE_OP_BACKGROUND.
+            let dispose_op = match control.dispose_op {
+                DisposeOp::Previous if index == 0 => DisposeOp::Background,
+                dispose_op => dispose_op,
+            };
+
+            let previous = match dispose_op {
+                DisposeOp::Previous => Some(try_copy(&canvas, &header)?),
+                _ => None,
+            };
+
+            let frame_width = control.width as usize;
+            let x = control.x_offset as usize;
+            let y = control.y_offset as usize;
+
+            for (row, source_row) in frame.pixels.chunks_exact(frame_width * 4).enumerate() {
+                let start = ((y + row) * canvas_width + x) * 4;
+                let target_row = &mut canvas[start..start + frame_width * 4];
+
+                match control.blend_op {
+                    BlendOp::Source => target_row.copy_from_slice(source_row),
+                    BlendOp::Over => {
+                        for (target, source) in target_row
+                            .chunks_exact_mut(4)
+                            .zip(source_row.chunks_exact(4))
+                        {
+                            blend_over(target, source);
+                        }
+                    }
+                }
+            }
+
+            composed.push(ComposedFrame {
+                pixels: try_copy(&canvas, &header)?,
+                delay: control.delay(),
+            });
+
+            match dispose_op {
+                DisposeOp::None => (),
+                DisposeOp::Background => {
+                    for row in 0..control.height as usize {
+                        let start = ((y + row) * canvas_width + x) * 4;
+                        canvas[start..start + frame_width * 4].fill(0);
+                    }
+                }
+                DisposeOp::Previous => canvas = previous.unwrap(),
+            }
+        }
+
+        Ok(composed)
+    }
+}
+
+/// Reserves `len` bytes, failing instead of aborting when there isn't that much memory.
+fn try_alloc(len: usize, header: &PngHeader) -> Result<Vec<u8>, PngError> {
+    let mut buffer = Vec::new();
+    buffer
+        .try_reserve_exact(len)
+        .map_err(|_| image_too_large(header))?;
+    Ok(buffer)
+}
+
+fn try_copy(canvas: &[u8], header: &PngHeader) -> Result<Vec<u8>, PngError> {
+    let mut copy = try_alloc(canvas.len(), header)?;
+    copy.extend_from_slice(canvas);
+    Ok(copy)
+}
+
+/// Alpha-composites a non-premultiplied RGBA8 `source` pixel over `target`.
+fn blend_over(target: &mut [u8], source: &[u8]) {
+    let source_alpha = source[3] as u32;
+
+    if source_alpha == 255 {
+        target.copy_from_slice(source);
+        return;
+    }
+
+    if source_alpha == 0 {
+        return;
+    }
+
+    let source_weight = source_alpha * 255;
+    let target_weight = (255 - source_alpha) * target[3] as u32;
+    let total = source_weight + target_weight;
+
+    for channel in 0..3 {
+        target[channel] = ((source[channel] as u32 * source_weight
+            + target[channel] as u32 * target_weight)
+            / total) as u8;
+    }
+
+    target[3] = (total / 255) as u8;
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::test_util::{ihdr, parse, zlib};
+
+    fn fctl(sequence_number: u32, width: u32, height: u32, x: u32, y: u32) -> Vec<u8> {
+        fctl_with_ops(sequence_number, width, height, x, y, 0, 1)
+    }
+
+    fn fctl_with_ops(
+        sequence_number: u32,
+        width: u32,
+        height: u32,
+        x: u32,
+        y: u32,
+        dispose_op: u8,
+        blend_op: u8,
+    ) -> Vec<u8> {
+        let mut data = Vec::new();
+        for value in [sequence_number, width, height, x, y] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data.extend_from_slice(&[0, 1, 0, 10, dispose_op, blend_op]);
+        data
+    }
+
+    fn fdat(sequence_number: u32, scanlines: &[u8]) -> Vec<u8> {
+        let mut data = sequence_number.to_be_bytes().to_vec();
+        data.extend_from_slice(&zlib(scanlines));
+        data
+    }
+
+    #[test]
+    fn enumerates_frames() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(2, 2, 8, 2)),
+            (b"acTL", &[0, 0, 0, 2, 0, 0, 0, 0]),
+            (b"fcTL", &fctl(0, 2, 2, 0, 0)),
+            (
+                b"IDAT",
+                &zlib(&[0, 255, 0, 0, 255, 0, 0, 0, 255, 0, 0, 255, 0, 0]),
+            ),
+            (b"fcTL", &fctl(1, 1, 1, 1, 1)),
+            (b"fdAT", &fdat(2, &[0, 0, 0, 255])),
+            (b"IEND", &[]),
+        ]);
+
+        let decoder = ApngDecoder::new(&png).unwrap();
+        assert!(decoder.default_image_is_first_frame());
+        assert_eq!(decoder.animation_control().num_frames, 2);
+
+        let frames = decoder.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(frames.len(), 2);
+
+        assert_eq!(frames[0].control.width, 2);
+        assert_eq!(
+            frames[0].pixels,
+            vec![255, 0, 0, 255, 255, 0, 0, 255, 255, 0, 0, 255, 255, 0, 0, 255]
+        );
+
+        assert_eq!(frames[1].control.x_offset, 1);
+        assert_eq!(frames[1].control.delay_den, 10);
+        assert_eq!(frames[1].control.blend_op, BlendOp::Over);
+        assert_eq!(frames[1].pixels, vec![0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn decodes_frames_with_options() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(4, 2, 8, 0)),
+            (b"acTL", &[0, 0, 0, 1, 0, 0, 0, 0]),
+            (b"fcTL", &fctl(0, 4, 2, 0, 0)),
+            // One and a half scanlines of two.
+            (b"IDAT", &zlib(&[0, 1, 2, 3, 4, 0, 5])),
+            (b"IEND", &[]),
+        ]);
+
+        assert!(matches!(
+            ApngDecoder::new(&png).unwrap().next(),
+            Some(Err(PngError::WrongDataLength { .. }))
+        ));
+
+        let mut options = DecodeOptions::new();
+        options.set_allow_truncated(true);
+        let frame = ApngDecoder::new_with(&png, &options)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.pixels.len(), 4 * 4);
+        assert_eq!(frame.pixels[4..8], [2, 2, 2, 255]);
+
+        options.max_decompressed_bytes = Some(4);
+        assert!(matches!(
+            ApngDecoder::new_with(&png, &options).unwrap().next(),
+            Some(Err(PngError::DecompressedTooLarge { limit: 4 }))
+        ));
+    }
+
+    #[test]
+    fn rejects_frames_outside_canvas() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"acTL", &[0, 0, 0, 1, 0, 0, 0, 0]),
+            (b"IDAT", &zlib(&[0, 0])),
+            (b"fcTL", &fctl(0, 1, 1, 1, 0)),
+            (b"fdAT", &fdat(1, &[0, 0])),
+            (b"IEND", &[]),
+        ]);
+
+        let mut decoder = ApngDecoder::new(&png).unwrap();
+        assert!(!decoder.default_image_is_first_frame());
+        assert!(matches!(
+            decoder.next(),
+            Some(Err(PngError::MalformedChunk(FCTL)))
+        ));
+        assert!(decoder.next().is_none());
+    }
+
+    #[test]
+    fn static_png_is_not_animated() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"IDAT", &zlib(&[0, 0])),
+            (b"IEND", &[]),
+        ]);
+
+        assert!(!png.is_animated());
+        assert!(matches!(
+            ApngDecoder::new(&png),
+            Err(PngError::MissingChunk(ACTL))
+        ));
+        assert_eq!(png.decode_data().unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn composes_frames_onto_canvas() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(2, 1, 8, 6)),
+            (b"acTL", &[0, 0, 0, 3, 0, 0, 0, 0]),
+            // Opaque red canvas, kept after display.
+            (b"fcTL", &fctl_with_ops(0, 2, 1, 0, 0, 0, 0)),
+            (b"IDAT", &zlib(&[0, 255, 0, 0, 255, 255, 0, 0, 255])),
+            // Half-transparent blue over the right pixel, reverted afterwards.
+            (b"fcTL", &fctl_with_ops(1, 1, 1, 1, 0, 2, 1)),
+            (b"fdAT", &fdat(2, &[0, 0, 0, 255, 128])),
+            // Transparent green on the left pixel without blending, cleared afterwards.
+            (b"fcTL", &fctl_with_ops(3, 1, 1, 0, 0, 1, 0)),
+            (b"fdAT", &fdat(4, &[0, 0, 255, 0, 0])),
+            (b"IEND", &[]),
+        ]);
+
+        let frames = ApngDecoder::new(&png).unwrap().compose().unwrap();
+        assert_eq!(frames.len(), 3);
+
+        assert_eq!(frames[0].pixels, vec![255, 0, 0, 255, 255, 0, 0, 255]);
+        assert_eq!(frames[1].pixels, vec![255, 0, 0, 255, 127, 0, 128, 255]);
+        assert_eq!(frames[2].pixels, vec![0, 255, 0, 0, 255, 0, 0, 255]);
+        assert_eq!(frames[2].delay, Duration::from_millis(100));
+    }
+
+    #[test]
+    fn excludes_default_image_without_frame_control() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"acTL", &[0, 0, 0, 1, 0, 0, 0, 0]),
+            (b"IDAT", &zlib(&[0, 255])),
+            (b"fcTL", &fctl(0, 1, 1, 0, 0)),
+            (b"fdAT", &fdat(1, &[0, 7])),
+            (b"IEND", &[]),
+        ]);
+
+        let frames = ApngDecoder::new(&png).unwrap().compose().unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].pixels, vec![7, 7, 7, 255]);
+    }
+
+    #[test]
+    fn rejects_frame_count_mismatch() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"acTL", &[0, 0, 0, 2, 0, 0, 0, 0]),
+            (b"fcTL", &fctl(0, 1, 1, 0, 0)),
+            (b"IDAT", &zlib(&[0, 255])),
+            (b"IEND", &[]),
+        ]);
+
+        assert!(matches!(
+            ApngDecoder::new(&png).unwrap().compose(),
+            Err(PngError::MalformedChunk(ACTL))
+        ));
+    }
+
+    #[test]
+    fn limits_canvas_size() {
+        let huge = parse(&[
+            (b"IHDR", &ihdr(0x7fff_ffff, 0x7fff_ffff, 8, 0)),
+            (b"acTL", &[0, 0, 0, 1, 0, 0, 0, 0]),
+            (b"IDAT", &zlib(&[0, 0])),
+            (b"fcTL", &fctl(0, 1, 1, 0, 0)),
+            (b"fdAT", &fdat(1, &[0, 255])),
+            (b"IEND", &[]),
+        ]);
+        assert!(matches!(
+            ApngDecoder::new(&huge).unwrap().compose(),
+            Err(PngError::ImageTooLarge { .. })
+        ));
+
+        // Each of the three frames is a copy of the 8-byte canvas.
+        let png = parse(&[
+            (b"IHDR", &ihdr(2, 1, 8, 0)),
+            (b"acTL", &[0, 0, 0, 3, 0, 0, 0, 0]),
+            (b"fcTL", &fctl(0, 2, 1, 0, 0)),
+            (b"IDAT", &zlib(&[0, 1, 2])),
+            (b"fcTL", &fctl(1, 1, 1, 0, 0)),
+            (b"fdAT", &fdat(2, &[0, 3])),
+            (b"fcTL", &fctl(3, 1, 1, 1, 0)),
+            (b"fdAT", &fdat(4, &[0, 4])),
+            (b"IEND", &[]),
+        ]);
+        let mut options = DecodeOptions::new();
+        options.max_decompressed_bytes = Some(16);
+        assert!(matches!(
+            ApngDecoder::new(&png).unwrap().compose_with(&options),
+            Err(PngError::DecompressedTooLarge { limit: 16 })
+        ));
+
+        options.max_decompressed_bytes = Some(24);
+        let frames = ApngDecoder::new(&png)
+            .unwrap()
+            .compose_with(&options)
+            .unwrap();
+        assert_eq!(frames[2].pixels, [3, 3, 3, 255, 4, 4, 4, 255]);
+    }
+}
@@ -0,0 +1,178 @@
+//! Parsing that borrows chunk data from the input instead of copying it, for files that are already in memory.
+
+use alloc::vec::Vec;
+
+use crate::{
+    crc::chunk_crc,
+    decoder::{decode_stream, DecodeOptions},
+    png_parser::{
+        image_data_run, parse_png, ChunkType, HasChunkType, ParseOptions, PngChunk, PngError,
+        PngFile, PngHeader, RawChunk,
+    },
+    reader::SliceReader,
+};
+
+/// A chunk whose data points into the parsed input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkRef<'a> {
+    pub chunk_type: ChunkType,
+    pub data: &'a [u8],
+    crc: [u8; 4],
+    offset: u64,
+}
+
+impl<'a> ChunkRef<'a> {
+    /// Byte offset of the chunk's length field from the start of the input, including the PNG signature.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The CRC stored in the file, which is not verified while parsing.
+    pub fn crc(&self) -> u32 {
+        u32::from_be_bytes(self.crc)
+    }
+
+    pub fn is_crc_valid(&self) -> bool {
+        self.crc() == chunk_crc(self.chunk_type.into(), self.data)
+    }
+}
+
+impl<'a> From<RawChunk<&'a [u8]>> for ChunkRef<'a> {
+    fn from(chunk: RawChunk<&'a [u8]>) -> Self {
+        ChunkRef {
+            chunk_type: chunk.chunk_type,
+            data: chunk.data,
+            crc: chunk.crc,
+            offset: chunk.offset,
+        }
+    }
+}
+
+impl<'a> HasChunkType for ChunkRef<'a> {
+    fn chunk_type(&self) -> ChunkType {
+        self.chunk_type
+    }
+}
+
+/// Like [`PngFile`], but borrows every chunk's data from the input, so even large IDAT payloads are never
+/// copied. Decoding inflates straight from the borrowed slices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PngFileRef<'a> {
+    pub chunks: Vec<ChunkRef<'a>>,
+}
+
+impl<'a> PngFileRef<'a> {
+    /// Parses the PNG at the start of `bytes`. Anything after IEND is ignored, as in
+    /// [`PngFile::from_bytes`].
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, PngError> {
+        PngFileRef::from_bytes_with(bytes, &ParseOptions::default())
+    }
+
+    pub fn from_bytes_with(bytes: &'a [u8], options: &ParseOptions) -> Result<Self, PngError> {
+        let chunks = parse_png(&mut SliceReader::new(bytes), options)?;
+        Ok(PngFileRef { chunks })
+    }
+
+    pub fn find_chunk(&self, chunk_type: ChunkType) -> Option<&ChunkRef<'a>> {
+        self.chunks
+            .iter()
+            .find(|chunk| chunk.chunk_type == chunk_type)
+    }
+
+    pub fn try_parse_header(&self) -> Result<PngHeader, PngError> {
+        match self.chunks.first() {
+            Some(chunk) if chunk.chunk_type == ChunkType::IHDR => PngHeader::from_data(chunk.data),
+            Some(chunk) => Err(PngError::UnexpectedChunkType {
+                expected: ChunkType::IHDR,
+                was: chunk.chunk_type,
+            }),
+            None => Err(PngError::MissingChunk(ChunkType::IHDR)),
+        }
+    }
+
+    /// Returns the run of consecutive IDAT chunks, which must not be interrupted by other chunks.
+    fn image_data_chunks(&self) -> Result<&[ChunkRef<'a>], PngError> {
+        image_data_run(&self.chunks)
+    }
+
+    pub fn decode_data(&self) -> Result<Vec<u8>, PngError> {
+        self.decode_with(&DecodeOptions::default())
+    }
+
+    pub fn decode_with(&self, options: &DecodeOptions) -> Result<Vec<u8>, PngError> {
+        let mut buffer = Vec::new();
+        self.decode_with_to(options, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    pub fn decode_with_to(
+        &self,
+        options: &DecodeOptions,
+        out: &mut Vec<u8>,
+    ) -> Result<(), PngError> {
+        let header = self.try_parse_header()?;
+        let data = self.image_data_chunks()?.iter().map(|chunk| chunk.data);
+
+        decode_stream(&header, data, options, out)?;
+        Ok(())
+    }
+
+    /// Copies every chunk into an owned [`PngFile`], for the APIs that only exist there.
+    pub fn to_png_file(&self) -> PngFile {
+        PngFile {
+            chunks: self.chunks.iter().map(PngChunk::from).collect(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::test_util::{ihdr, png_bytes, zlib};
+
+    #[test]
+    fn borrows_chunk_data_from_the_input() {
+        for path in ["./code.png", "./indexed-4bit-interlaced.png"] {
+            let bytes = std::fs::read(path).unwrap();
+            let borrowed = PngFileRef::from_bytes(&bytes).unwrap();
+            let owned = PngFile::from_bytes(&bytes).unwrap();
+
+            assert_eq!(borrowed.to_png_file(), owned, "{}", path);
+            assert_eq!(
+                borrowed.decode_data().unwrap(),
+                owned.decode_data().unwrap(),
+                "{}",
+                path
+            );
+
+            for (chunk, owned_chunk) in borrowed.chunks.iter().zip(&owned.chunks) {
+                assert!(
+                    bytes.as_ptr_range().contains(&chunk.data.as_ptr()) || chunk.data.is_empty()
+                );
+                assert_eq!(Some(chunk.offset()), owned_chunk.offset());
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_chunks() {
+        let bytes = png_bytes(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"IDAT", &zlib(&[0, 0])),
+            (b"IEND", &[]),
+        ]);
+        let idat_end = bytes.len() - 12 - 4;
+
+        assert!(matches!(
+            PngFileRef::from_bytes(&bytes[..idat_end - 1]),
+            Err(PngError::TruncatedChunk {
+                chunk_type: ChunkType::IDAT,
+                ..
+            })
+        ));
+        assert!(matches!(
+            PngFileRef::from_bytes(&bytes[..idat_end + 2]),
+            Err(PngError::UnexpectedEof)
+        ));
+    }
+}
@@ -0,0 +1,642 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    decoder::packed_sample,
+    png_parser::{BitDepth, ChunkType, ColourType, PngError, PngFile, PngHeader},
+};
+
+/// Converts unfiltered image data described by `header` into 8-bit RGBA.
+///
+/// The palette and transparency chunks are looked up from `png`, which lets APNG frames share them with the
+/// default image. `palette_override` replaces the PLTE palette of indexed images, and `palette_fallback` is used
+/// for indices past its end.
+pub(crate) fn to_rgba8(
+    header: &PngHeader,
+    data: &[u8],
+    png: &PngFile,
+    palette_override: Option<&[[u8; 3]]>,
+    palette_fallback: Option<[u8; 3]>,
+    layout: Layout,
+) -> Result<Vec<u8>, PngError> {
+    let mut out = RgbaWriter::new(header.width as usize * header.height as usize, layout);
+
+    // Samples of 8 bits or fewer were replicated into 16 bits, so scaling them back is exact.
+    expand_rgba16(
+        header,
+        data,
+        png,
+        palette_override,
+        palette_fallback,
+        |rgba| out.push(rgba.map(scale_16_to_8)),
+    )?;
+
+    Ok(out.out)
+}
+
+/// Converts unfiltered image data described by `header` into 16-bit RGBA. Shallower samples are scaled up by
+/// bit replication, so that the maximum value of any depth becomes 65535.
+pub(crate) fn to_rgba16(
+    header: &PngHeader,
+    data: &[u8],
+    png: &PngFile,
+) -> Result<Vec<u16>, PngError> {
+    let mut out = Vec::with_capacity(header.width as usize * header.height as usize * 4);
+    expand_rgba16(header, data, png, None, None, |rgba| {
+        out.extend_from_slice(&rgba)
+    })?;
+
+    Ok(out)
+}
+
+/// Extracts the alpha of every pixel as one byte, whether it comes from an alpha channel or from tRNS.
+pub(crate) fn alpha_mask(
+    header: &PngHeader,
+    data: &[u8],
+    png: &PngFile,
+) -> Result<Vec<u8>, PngError> {
+    let mut out = Vec::with_capacity(header.width as usize * header.height as usize);
+    expand_rgba16(header, data, png, None, None, |rgba| {
+        out.push(scale_16_to_8(rgba[3]))
+    })?;
+
+    Ok(out)
+}
+
+/// Expands every pixel to 16-bit RGBA and passes it to `push`. See [`to_rgba8`] for the palette arguments.
+fn expand_rgba16(
+    header: &PngHeader,
+    data: &[u8],
+    png: &PngFile,
+    palette_override: Option<&[[u8; 3]]>,
+    palette_fallback: Option<[u8; 3]>,
+    mut push: impl FnMut([u16; 4]),
+) -> Result<(), PngError> {
+    let sixteen_bit = header.bit_depth == BitDepth::B16;
+    let bits = header.bit_depth.bits();
+
+    // Sub-byte samples are unpacked to one byte each, so the rest of the conversion only deals with 8 and
+    // 16 bits.
+    let unpacked;
+    let data = if bits < 8 {
+        unpacked = unpack_samples(header, data);
+        unpacked.as_slice()
+    } else {
+        data
+    };
+
+    let bytes_per_pixel = header.colour_type.channel_count() * if sixteen_bit { 2 } else { 1 };
+    // 65535 is divisible by the maximum of every shallower depth, and multiplying by the quotient repeats the
+    // sample's bits.
+    let replication = 65535 / ((1u32 << bits.min(8)) - 1) as u16;
+
+    let transparency = png
+        .find_chunk(ChunkType::tRNS)
+        .map(|chunk| chunk.data.as_slice());
+    let embedded_palette;
+    let palette: &[[u8; 3]] = match (header.colour_type, palette_override) {
+        (ColourType::IndexedColour, Some(palette)) => palette,
+        (ColourType::IndexedColour, None) => {
+            embedded_palette = png
+                .palette()?
+                .ok_or(PngError::MissingChunk(ChunkType::PLTE))?;
+            &embedded_palette.entries
+        }
+        _ => &[],
+    };
+
+    let sample = |pixel: &[u8], channel: usize| -> u16 {
+        if sixteen_bit {
+            u16::from_be_bytes([pixel[channel * 2], pixel[channel * 2 + 1]])
+        } else {
+            pixel[channel] as u16
+        }
+    };
+
+    let to_u16 = |sample: u16| -> u16 {
+        if sixteen_bit {
+            sample
+        } else {
+            sample * replication
+        }
+    };
+
+    // Colour keys are compared at the sample depth of the image, before any scaling.
+    let colour_key = |channels: usize| -> Option<[u16; 3]> {
+        let key = transparency?;
+
+        if key.len() < channels * 2 {
+            return None;
+        }
+
+        let mut samples = [0u16; 3];
+        for (channel, sample) in samples.iter_mut().enumerate().take(channels) {
+            *sample = u16::from_be_bytes([key[channel * 2], key[channel * 2 + 1]]);
+        }
+
+        Some(samples)
+    };
+
+    match header.colour_type {
+        ColourType::Greyscale => {
+            let key = colour_key(1);
+
+            for pixel in data.chunks_exact(bytes_per_pixel) {
+                let grey = sample(pixel, 0);
+                let alpha = if key == Some([grey, 0, 0]) { 0 } else { 65535 };
+                let grey = to_u16(grey);
+                push([grey, grey, grey, alpha]);
+            }
+        }
+        ColourType::Truecolour => {
+            let key = colour_key(3);
+
+            for pixel in data.chunks_exact(bytes_per_pixel) {
+                let rgb = [sample(pixel, 0), sample(pixel, 1), sample(pixel, 2)];
+                let alpha = if key == Some(rgb) { 0 } else { 65535 };
+                push([to_u16(rgb[0]), to_u16(rgb[1]), to_u16(rgb[2]), alpha]);
+            }
+        }
+        ColourType::IndexedColour => {
+            let alphas = transparency.unwrap_or_default();
+
+            for &index in data {
+                let entry = palette
+                    .get(index as usize)
+                    .copied()
+                    .or(palette_fallback)
+                    .ok_or(PngError::PaletteIndexOutOfRange {
+                        index,
+                        palette_len: palette.len(),
+                    })?;
+                let alpha = alphas.get(index as usize).copied().unwrap_or(255);
+                push([entry[0], entry[1], entry[2], alpha].map(|value| value as u16 * 257));
+            }
+        }
+        ColourType::GreyscaleWithAlpha => {
+            for pixel in data.chunks_exact(bytes_per_pixel) {
+                let grey = to_u16(sample(pixel, 0));
+                push([grey, grey, grey, to_u16(sample(pixel, 1))]);
+            }
+        }
+        ColourType::TruecolourWithAlpha => {
+            for pixel in data.chunks_exact(bytes_per_pixel) {
+                push([
+                    to_u16(sample(pixel, 0)),
+                    to_u16(sample(pixel, 1)),
+                    to_u16(sample(pixel, 2)),
+                    to_u16(sample(pixel, 3)),
+                ]);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// How [`to_rgba8`] arranges the channels of its output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Layout {
+    /// R, G, B and A of each pixel next to each other.
+    Interleaved,
+    /// All the reds, then all the greens, blues and alphas.
+    Planar,
+}
+
+struct RgbaWriter {
+    out: Vec<u8>,
+    layout: Layout,
+    pixel_count: usize,
+    next_pixel: usize,
+}
+
+impl RgbaWriter {
+    fn new(pixel_count: usize, layout: Layout) -> Self {
+        let out = match layout {
+            Layout::Interleaved => Vec::with_capacity(pixel_count * 4),
+            Layout::Planar => vec![0; pixel_count * 4],
+        };
+
+        RgbaWriter {
+            out,
+            layout,
+            pixel_count,
+            next_pixel: 0,
+        }
+    }
+
+    fn push(&mut self, rgba: [u8; 4]) {
+        match self.layout {
+            Layout::Interleaved => self.out.extend_from_slice(&rgba),
+            Layout::Planar => {
+                for (channel, value) in rgba.into_iter().enumerate() {
+                    self.out[channel * self.pixel_count + self.next_pixel] = value;
+                }
+
+                self.next_pixel += 1;
+            }
+        }
+    }
+}
+
+/// Converts 8-bit RGBA pixels to Rec. 709 luminance, optionally followed by the unchanged alpha of each pixel.
+pub(crate) fn rgba_to_luminance(rgba: &[u8], keep_alpha: bool) -> Vec<u8> {
+    let channels = if keep_alpha { 2 } else { 1 };
+    let mut out = Vec::with_capacity(rgba.len() / 4 * channels);
+
+    for pixel in rgba.chunks_exact(4) {
+        // The weights are scaled to sum to 10000, so grey pixels keep their exact value.
+        let [r, g, b, a] = [pixel[0], pixel[1], pixel[2], pixel[3]].map(u32::from);
+        let luminance = (2126 * r + 7152 * g + 722 * b + 5000) / 10000;
+
+        out.push(luminance as u8);
+        if keep_alpha {
+            out.push(a as u8);
+        }
+    }
+
+    out
+}
+
+/// Splits rows of packed 1, 2 or 4-bit samples into a byte per sample, dropping the padding at the end of
+/// each row.
+fn unpack_samples(header: &PngHeader, data: &[u8]) -> Vec<u8> {
+    let width = header.width as usize;
+    let bits = header.bit_depth.bits() as usize;
+    let scanline_length = header.scanline_length(width);
+
+    let mut samples = Vec::with_capacity(width * header.height as usize);
+
+    if scanline_length == 0 {
+        return samples;
+    }
+
+    for row in data.chunks_exact(scanline_length) {
+        samples.extend((0..width).map(|x| packed_sample(row, x, bits)));
+    }
+
+    samples
+}
+
+/// Rescales a 16-bit sample to 8 bits, rounding to the nearest value rather than truncating.
+fn scale_16_to_8(sample: u16) -> u8 {
+    ((sample as u32 * 255 + 32767) / 65535) as u8
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::{
+        decoded_image::Pixel,
+        decoder::DecodeOptions,
+        test_util::{ihdr, parse, zlib},
+    };
+
+    #[test]
+    fn matches_image_crate() {
+        for path in ["./FL.png", "./code.png", "./PNG-Gradient.png"] {
+            let png = PngFile::from_reader(&mut File::open(path).unwrap()).unwrap();
+            let expected = image::open(path).unwrap().to_rgba8().into_raw();
+
+            assert_eq!(png.to_rgba8().unwrap(), expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn rounds_16_bit_samples() {
+        for (sample, expected) in [
+            (0, 0),
+            (128, 0),
+            (129, 1),
+            (0x7fff, 127),
+            (0x8000, 128),
+            (0xfe7d, 253),
+            (0xfe7e, 254),
+            (0xff7e, 254),
+            (0xff7f, 255),
+            (0xffff, 255),
+        ] {
+            assert_eq!(scale_16_to_8(sample), expected, "{:#x}", sample);
+        }
+    }
+
+    #[test]
+    fn replicates_bits_when_converting_to_16_bit() {
+        let grey = parse(&[
+            (b"IHDR", &ihdr(3, 1, 8, 0)),
+            (b"IDAT", &zlib(&[0, 255, 0x12, 0])),
+            (b"IEND", &[]),
+        ]);
+        assert_eq!(
+            grey.to_rgba16().unwrap(),
+            vec![65535, 65535, 65535, 65535, 0x1212, 0x1212, 0x1212, 65535, 0, 0, 0, 65535]
+        );
+
+        let two_bit = parse(&[
+            (b"IHDR", &ihdr(2, 1, 2, 0)),
+            (b"IDAT", &zlib(&[0, 0b1001_0000])),
+            (b"IEND", &[]),
+        ]);
+        assert_eq!(
+            two_bit.to_rgba16().unwrap(),
+            vec![0xaaaa, 0xaaaa, 0xaaaa, 65535, 0x5555, 0x5555, 0x5555, 65535]
+        );
+
+        let indexed = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 3)),
+            (b"PLTE", &[0x10, 0x80, 0xff]),
+            (b"tRNS", &[0x7f]),
+            (b"IDAT", &zlib(&[0, 0])),
+            (b"IEND", &[]),
+        ]);
+        assert_eq!(
+            indexed.to_rgba16().unwrap(),
+            vec![0x1010, 0x8080, 0xffff, 0x7f7f]
+        );
+    }
+
+    #[test]
+    fn keeps_16_bit_samples_at_full_precision() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 16, 6)),
+            (
+                b"IDAT",
+                &zlib(&[0, 0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0]),
+            ),
+            (b"IEND", &[]),
+        ]);
+
+        assert_eq!(
+            png.to_rgba16().unwrap(),
+            vec![0x1234, 0x5678, 0x9abc, 0xdef0]
+        );
+    }
+
+    #[test]
+    fn extracts_alpha_mask() {
+        let opaque = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 2)),
+            (b"IDAT", &zlib(&[0, 1, 2, 3])),
+            (b"IEND", &[]),
+        ]);
+        assert_eq!(opaque.alpha_mask().unwrap(), None);
+
+        let grey_alpha = parse(&[
+            (b"IHDR", &ihdr(2, 1, 16, 4)),
+            (b"IDAT", &zlib(&[0, 0, 0, 0xff, 0xff, 0, 0, 0x80, 0x00])),
+            (b"IEND", &[]),
+        ]);
+        assert_eq!(grey_alpha.alpha_mask().unwrap(), Some(vec![255, 128]));
+
+        let keyed = parse(&[
+            (b"IHDR", &ihdr(3, 1, 8, 0)),
+            (b"tRNS", &[0, 7]),
+            (b"IDAT", &zlib(&[0, 7, 8, 7])),
+            (b"IEND", &[]),
+        ]);
+        assert_eq!(keyed.alpha_mask().unwrap(), Some(vec![0, 255, 0]));
+
+        let indexed = parse(&[
+            (b"IHDR", &ihdr(3, 1, 8, 3)),
+            (b"PLTE", &[0; 6]),
+            (b"tRNS", &[0x40]),
+            (b"IDAT", &zlib(&[0, 0, 1, 0])),
+            (b"IEND", &[]),
+        ]);
+        assert_eq!(indexed.alpha_mask().unwrap(), Some(vec![0x40, 255, 0x40]));
+    }
+
+    #[test]
+    fn converts_16_bit_truecolour() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 16, 2)),
+            (b"IDAT", &zlib(&[0, 0xff, 0x7e, 0x80, 0x00, 0x00, 0x81])),
+            (b"IEND", &[]),
+        ]);
+
+        assert_eq!(png.to_rgba8().unwrap(), vec![254, 128, 1, 255]);
+    }
+
+    #[test]
+    fn compares_16_bit_colour_keys_at_full_precision() {
+        // Both pixels reduce to the same 8-bit colour, but only the first matches the key.
+        let png = parse(&[
+            (b"IHDR", &ihdr(2, 1, 16, 2)),
+            (b"tRNS", &[0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc]),
+            (
+                b"IDAT",
+                &zlib(&[
+                    0, 0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0x12, 0x35, 0x56, 0x78, 0x9a, 0xbc,
+                ]),
+            ),
+            (b"IEND", &[]),
+        ]);
+
+        assert_eq!(
+            png.to_rgba8().unwrap(),
+            vec![18, 86, 154, 0, 18, 86, 154, 255]
+        );
+
+        let mut image = png.decode_image().unwrap();
+        image.expand().unwrap();
+        assert_eq!(
+            image.get_pixel(0, 0),
+            Some(Pixel::Rgba(0x1234, 0x5678, 0x9abc, 0))
+        );
+        assert_eq!(
+            image.get_pixel(1, 0),
+            Some(Pixel::Rgba(0x1235, 0x5678, 0x9abc, 0xffff))
+        );
+
+        let grey = parse(&[
+            (b"IHDR", &ihdr(2, 1, 16, 0)),
+            (b"tRNS", &[0x80, 0x01]),
+            (b"IDAT", &zlib(&[0, 0x80, 0x01, 0x80, 0x02])),
+            (b"IEND", &[]),
+        ]);
+        assert_eq!(
+            grey.to_rgba8().unwrap(),
+            vec![128, 128, 128, 0, 128, 128, 128, 255]
+        );
+    }
+
+    #[test]
+    fn expands_sub_byte_samples() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(3, 2, 2, 0)),
+            (b"tRNS", &[0, 1]),
+            (b"IDAT", &zlib(&[0, 0b0001_1100, 0, 0b1110_0100])),
+            (b"IEND", &[]),
+        ]);
+
+        let grey = |value: u8, alpha: u8| [value, value, value, alpha];
+        let expected = [
+            grey(0, 255),
+            grey(85, 0),
+            grey(255, 255),
+            grey(255, 255),
+            grey(170, 255),
+            grey(85, 0),
+        ]
+        .concat();
+
+        assert_eq!(png.to_rgba8().unwrap(), expected);
+    }
+
+    #[test]
+    fn expands_palette_with_transparency() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(3, 1, 8, 3)),
+            (b"PLTE", &[255, 0, 0, 0, 255, 0, 0, 0, 255]),
+            (b"tRNS", &[0, 128]),
+            (b"IDAT", &zlib(&[0, 0, 1, 2])),
+            (b"IEND", &[]),
+        ]);
+
+        assert_eq!(
+            png.to_rgba8().unwrap(),
+            vec![255, 0, 0, 0, 0, 255, 0, 128, 0, 0, 255, 255]
+        );
+    }
+
+    #[test]
+    fn ignores_suggested_palette_of_truecolour_images() {
+        // The pixel values are valid indices into the PLTE, which only suggests colours for quantization here.
+        let png = parse(&[
+            (b"IHDR", &ihdr(2, 1, 8, 2)),
+            (b"PLTE", &[255, 0, 0, 0, 255, 0, 0, 0, 255]),
+            (b"IDAT", &zlib(&[0, 0, 1, 2, 2, 1, 0])),
+            (b"IEND", &[]),
+        ]);
+        let expected = vec![0, 1, 2, 255, 2, 1, 0, 255];
+
+        assert_eq!(png.to_rgba8().unwrap(), expected);
+        assert_eq!(png.to_rgba8_with_palette(&[]).unwrap(), expected);
+
+        let mut image = png.decode_image().unwrap();
+        assert_eq!(image.get_pixel(0, 0), Some(Pixel::Rgb(0, 257, 514)));
+        image.expand().unwrap();
+        assert_eq!(image.effective_colour_type(), ColourType::Truecolour);
+        assert_eq!(image.data(), [0, 1, 2, 2, 1, 0]);
+    }
+
+    #[test]
+    fn overrides_palette() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(3, 1, 8, 3)),
+            (b"PLTE", &[255, 0, 0, 0, 255, 0, 0, 0, 255]),
+            (b"tRNS", &[0, 128]),
+            (b"IDAT", &zlib(&[0, 0, 1, 2])),
+            (b"IEND", &[]),
+        ]);
+
+        // The file's tRNS alpha still applies to the replacement colours.
+        assert_eq!(
+            png.to_rgba8_with_palette(&[[1, 2, 3], [4, 5, 6], [7, 8, 9]])
+                .unwrap(),
+            vec![1, 2, 3, 0, 4, 5, 6, 128, 7, 8, 9, 255]
+        );
+
+        assert!(matches!(
+            png.to_rgba8_with_palette(&[[1, 2, 3], [4, 5, 6]]),
+            Err(PngError::PaletteIndexOutOfRange {
+                index: 2,
+                palette_len: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn converts_to_luminance() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(4, 1, 8, 3)),
+            (b"PLTE", &[255, 0, 0, 0, 255, 0, 0, 0, 255, 200, 200, 200]),
+            (b"tRNS", &[255, 128]),
+            (b"IDAT", &zlib(&[0, 0, 1, 2, 3])),
+            (b"IEND", &[]),
+        ]);
+
+        assert_eq!(png.to_greyscale8().unwrap(), vec![54, 182, 18, 200]);
+        assert_eq!(
+            png.to_greyscale_alpha8().unwrap(),
+            vec![54, 255, 182, 128, 18, 255, 200, 255]
+        );
+
+        // Greyscale images come back unchanged.
+        let grey = parse(&[
+            (b"IHDR", &ihdr(3, 1, 8, 0)),
+            (b"IDAT", &zlib(&[0, 0, 77, 255])),
+            (b"IEND", &[]),
+        ]);
+        assert_eq!(grey.to_greyscale8().unwrap(), vec![0, 77, 255]);
+    }
+
+    #[test]
+    fn converts_to_planar_layout() {
+        for path in ["./code.png", "./indexed-4bit-interlaced.png", "./FL.png"] {
+            let png = PngFile::from_reader(&mut File::open(path).unwrap()).unwrap();
+            let interleaved = png.to_rgba8().unwrap();
+            let planar = png.decode_planar().unwrap();
+            let pixel_count = interleaved.len() / 4;
+
+            assert_eq!(planar.len(), interleaved.len());
+
+            for (i, pixel) in interleaved.chunks_exact(4).enumerate() {
+                for (channel, &value) in pixel.iter().enumerate() {
+                    assert_eq!(planar[channel * pixel_count + i], value, "{}", path);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_range_palette_index() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 3)),
+            (b"PLTE", &[255, 0, 0]),
+            (b"IDAT", &zlib(&[0, 1])),
+            (b"IEND", &[]),
+        ]);
+
+        assert!(matches!(
+            png.to_rgba8(),
+            Err(PngError::PaletteIndexOutOfRange {
+                index: 1,
+                palette_len: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn substitutes_out_of_range_palette_index() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(2, 1, 8, 3)),
+            (b"PLTE", &[255, 0, 0]),
+            (b"IDAT", &zlib(&[0, 0, 7])),
+            (b"IEND", &[]),
+        ]);
+        let options = DecodeOptions {
+            palette_fallback: Some([255, 0, 255]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            png.to_rgba8_with(&options).unwrap(),
+            vec![255, 0, 0, 255, 255, 0, 255, 255]
+        );
+
+        let mut image = png.decode_image_with(&options).unwrap();
+        assert_eq!(image.get_pixel(1, 0), Some(Pixel::Rgb(0xffff, 0, 0xffff)));
+        image.expand().unwrap();
+        assert_eq!(image.data(), [255, 0, 0, 255, 0, 255]);
+
+        assert!(matches!(
+            png.decode_image().unwrap().expand(),
+            Err(PngError::PaletteIndexOutOfRange {
+                index: 7,
+                palette_len: 1
+            })
+        ));
+    }
+}
@@ -0,0 +1,111 @@
+const POLYNOMIAL: u32 = 0xedb8_8320;
+
+/// `TABLES[0]` is the usual byte-at-a-time CRC32 table. `TABLES[k]` advances a byte through `k` more zero
+/// bytes, which lets [`update_crc`] process eight bytes per step.
+static TABLES: [[u32; 256]; 8] = make_tables();
+
+const fn make_tables() -> [[u32; 256]; 8] {
+    let mut tables = [[0u32; 256]; 8];
+
+    let mut n = 0;
+    while n < 256 {
+        let mut crc = n as u32;
+
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                POLYNOMIAL ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        tables[0][n] = crc;
+        n += 1;
+    }
+
+    let mut k = 1;
+    while k < 8 {
+        let mut n = 0;
+        while n < 256 {
+            let previous = tables[k - 1][n];
+            tables[k][n] = (previous >> 8) ^ tables[0][(previous & 0xff) as usize];
+            n += 1;
+        }
+        k += 1;
+    }
+
+    tables
+}
+
+/// Feeds `bytes` into a running CRC32 register, eight bytes at a time.
+pub(crate) fn update_crc(mut crc: u32, bytes: &[u8]) -> u32 {
+    let mut chunks = bytes.chunks_exact(8);
+
+    for chunk in &mut chunks {
+        let low = crc ^ u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        let high = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+
+        crc = TABLES[7][(low & 0xff) as usize]
+            ^ TABLES[6][((low >> 8) & 0xff) as usize]
+            ^ TABLES[5][((low >> 16) & 0xff) as usize]
+            ^ TABLES[4][(low >> 24) as usize]
+            ^ TABLES[3][(high & 0xff) as usize]
+            ^ TABLES[2][((high >> 8) & 0xff) as usize]
+            ^ TABLES[1][((high >> 16) & 0xff) as usize]
+            ^ TABLES[0][(high >> 24) as usize];
+    }
+
+    for &byte in chunks.remainder() {
+        crc = TABLES[0][((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+
+    crc
+}
+
+/// Computes the CRC of a chunk, which covers the chunk type and data but not the length.
+pub(crate) fn chunk_crc(chunk_type: [u8; 4], data: &[u8]) -> u32 {
+    !update_crc(update_crc(!0, &chunk_type), data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::pattern;
+
+    fn update_crc_bitwise(mut crc: u32, bytes: &[u8]) -> u32 {
+        for &byte in bytes {
+            crc ^= byte as u32;
+
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    POLYNOMIAL ^ (crc >> 1)
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+
+        crc
+    }
+
+    #[test]
+    fn matches_reference_values() {
+        assert_eq!(!update_crc(!0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(chunk_crc(*b"IEND", &[]), 0xae42_6082);
+    }
+
+    #[test]
+    fn matches_bitwise_crc() {
+        let data = pattern(1000);
+
+        // Every length up to a few blocks, so each remainder length is covered.
+        for length in (0..40).chain([999, 1000]) {
+            for start in [0, 1, 7] {
+                let bytes = &data[start..start + length.min(1000 - start)];
+                assert_eq!(update_crc(!0, bytes), update_crc_bitwise(!0, bytes));
+            }
+        }
+    }
+}
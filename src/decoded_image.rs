@@ -0,0 +1,942 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    decoder::{decode_stream, packed_sample, set_packed_sample, DecodeOptions, DecodeWarning},
+    png_parser::{BitDepth, ChunkType, ColourType, Palette, PngError, PngFile},
+};
+
+/// Unfiltered image data, together with what is needed to interpret it.
+///
+/// The data starts out in the file's own sample format. After [`expand`](Self::expand) the layout is described
+/// by the effective colour type and bit depth instead of the ones declared in IHDR.
+#[derive(Debug, Clone)]
+pub struct DecodedImage {
+    width: u32,
+    height: u32,
+    bit_depth: BitDepth,
+    colour_type: ColourType,
+    effective_bit_depth: BitDepth,
+    effective_colour_type: ColourType,
+    data: Vec<u8>,
+    /// Only present for indexed images.
+    palette: Option<Palette>,
+    /// The tRNS chunk: per-entry alpha for indexed images, a colour key for greyscale and truecolour.
+    transparency: Option<Vec<u8>>,
+    /// From [`DecodeOptions::palette_fallback`].
+    palette_fallback: Option<[u8; 3]>,
+    warnings: Vec<DecodeWarning>,
+    compression: CompressionStats,
+}
+
+/// How well the image data compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// Total length of the IDAT chunks.
+    pub compressed_bytes: usize,
+    /// Length of the inflated stream, including the filter type byte of every scanline. For image data that
+    /// was allowed to end early, this is what it inflated to, partial last scanline included.
+    pub decompressed_bytes: usize,
+}
+
+impl CompressionStats {
+    /// Decompressed size divided by compressed size, so higher is better. `None` when there was no
+    /// compressed data, as for an image with no pixels.
+    pub fn ratio(&self) -> Option<f64> {
+        match self.compressed_bytes {
+            0 => None,
+            compressed_bytes => Some(self.decompressed_bytes as f64 / compressed_bytes as f64),
+        }
+    }
+}
+
+/// Range and average of one channel's samples, from [`DecodedImage::channel_stats`]. Samples are raw values at
+/// the effective bit depth, so an 8-bit channel ranges over 0-255 and a 16-bit one over 0-65535.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelStats {
+    pub min: u16,
+    pub max: u16,
+    pub mean: f64,
+}
+
+/// A single pixel with samples scaled to 16 bits, independent of how the image stores them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pixel {
+    Grey(u16),
+    GreyAlpha(u16, u16),
+    Rgb(u16, u16, u16),
+    Rgba(u16, u16, u16, u16),
+}
+
+impl DecodedImage {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Bit depth declared in the file.
+    pub fn bit_depth(&self) -> BitDepth {
+        self.bit_depth
+    }
+
+    /// Colour type declared in the file.
+    pub fn colour_type(&self) -> ColourType {
+        self.colour_type
+    }
+
+    /// Bit depth of the samples in [`data`](Self::data).
+    pub fn effective_bit_depth(&self) -> BitDepth {
+        self.effective_bit_depth
+    }
+
+    /// Colour type of the pixels in [`data`](Self::data).
+    pub fn effective_colour_type(&self) -> ColourType {
+        self.effective_colour_type
+    }
+
+    /// Number of samples per pixel in [`data`](Self::data).
+    pub fn channels(&self) -> usize {
+        self.effective_colour_type.channel_count()
+    }
+
+    /// Scanlines packed top to bottom. Until the image is expanded, this is what [`PngFile::decode_data`]
+    /// returns.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn compression_stats(&self) -> CompressionStats {
+        self.compression
+    }
+
+    /// Problems that were tolerated because of the [`DecodeOptions`] used.
+    pub fn warnings(&self) -> &[DecodeWarning] {
+        &self.warnings
+    }
+
+    fn scanline_length(&self) -> usize {
+        let bits_per_pixel = self.effective_bit_depth.bits() as usize * self.channels();
+        (self.width as usize * bits_per_pixel).div_ceil(8)
+    }
+
+    /// Reads sample `index` of a scanline without scaling it.
+    fn raw_sample(&self, row: &[u8], index: usize) -> u16 {
+        match self.effective_bit_depth {
+            BitDepth::B16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+            BitDepth::B8 => row[index] as u16,
+            bit_depth => packed_sample(row, index, bit_depth.bits() as usize) as u16,
+        }
+    }
+
+    /// Converts the data so that it can be read without the palette or tRNS: indexed images become RGB or
+    /// RGBA, sub-byte greyscale becomes 8-bit, and tRNS colour keys become an alpha channel. 16-bit images stay
+    /// 16-bit.
+    pub fn expand(&mut self) -> Result<(), PngError> {
+        let width = self.width as usize;
+        let scanline_length = self.scanline_length();
+        let bits = self.effective_bit_depth.bits();
+
+        let (colour_type, bit_depth) = match self.effective_colour_type {
+            ColourType::IndexedColour => {
+                let palette = self
+                    .palette
+                    .as_ref()
+                    .map_or(&[][..], |palette| &palette.entries);
+                let alphas = self.transparency.as_deref();
+                let channels = if alphas.is_some() { 4 } else { 3 };
+
+                let mut data = Vec::with_capacity(width * self.height as usize * channels);
+
+                for row in self.data.chunks_exact(scanline_length.max(1)) {
+                    for x in 0..width {
+                        let index = self.raw_sample(row, x) as u8;
+                        let entry = palette
+                            .get(index as usize)
+                            .copied()
+                            .or(self.palette_fallback)
+                            .ok_or(PngError::PaletteIndexOutOfRange {
+                                index,
+                                palette_len: palette.len(),
+                            })?;
+
+                        data.extend_from_slice(&entry);
+
+                        if let Some(alphas) = alphas {
+                            data.push(alphas.get(index as usize).copied().unwrap_or(255));
+                        }
+                    }
+                }
+
+                self.data = data;
+
+                match alphas {
+                    Some(_) => (ColourType::TruecolourWithAlpha, BitDepth::B8),
+                    None => (ColourType::Truecolour, BitDepth::B8),
+                }
+            }
+            colour_type @ (ColourType::Greyscale | ColourType::Truecolour) => {
+                let channels = colour_type.channel_count();
+                let key = self.transparency.as_deref().and_then(|key| {
+                    let key = key.get(..channels * 2)?;
+                    Some(
+                        key.chunks_exact(2)
+                            .map(|sample| u16::from_be_bytes([sample[0], sample[1]]))
+                            .collect::<Vec<_>>(),
+                    )
+                });
+
+                if key.is_none() && bits >= 8 {
+                    return Ok(());
+                }
+
+                let bit_depth = if bits == 16 {
+                    BitDepth::B16
+                } else {
+                    BitDepth::B8
+                };
+                let sample_bytes = if bits == 16 { 2 } else { 1 };
+                let sub_byte_scale = 255 / ((1u16 << bits.min(8)) - 1);
+                let out_channels = channels + key.is_some() as usize;
+
+                let mut data =
+                    Vec::with_capacity(width * self.height as usize * out_channels * sample_bytes);
+                let push = |data: &mut Vec<u8>, sample: u16| {
+                    if bits == 16 {
+                        data.extend_from_slice(&sample.to_be_bytes());
+                    } else {
+                        data.push((sample * sub_byte_scale) as u8);
+                    }
+                };
+
+                let mut samples = Vec::with_capacity(channels);
+
+                for row in self.data.chunks_exact(scanline_length.max(1)) {
+                    for x in 0..width {
+                        samples.clear();
+                        samples
+                            .extend((0..channels).map(|c| self.raw_sample(row, x * channels + c)));
+
+                        for &sample in &samples {
+                            push(&mut data, sample);
+                        }
+
+                        // Colour keys are compared against the samples before they're scaled.
+                        if let Some(key) = &key {
+                            let opaque = if bits == 16 { 0xffff } else { 255 };
+                            let alpha = if samples == *key { 0 } else { opaque };
+
+                            if bits == 16 {
+                                data.extend_from_slice(&(alpha as u16).to_be_bytes());
+                            } else {
+                                data.push(alpha as u8);
+                            }
+                        }
+                    }
+                }
+
+                self.data = data;
+
+                let colour_type = match (colour_type, key.is_some()) {
+                    (ColourType::Greyscale, true) => ColourType::GreyscaleWithAlpha,
+                    (ColourType::Truecolour, true) => ColourType::TruecolourWithAlpha,
+                    (colour_type, _) => colour_type,
+                };
+
+                (colour_type, bit_depth)
+            }
+            ColourType::GreyscaleWithAlpha | ColourType::TruecolourWithAlpha => return Ok(()),
+        };
+
+        self.effective_colour_type = colour_type;
+        self.effective_bit_depth = bit_depth;
+
+        Ok(())
+    }
+
+    /// Reads the pixel at `x`, `y`, or `None` if it's out of bounds or an index past the end of the palette.
+    ///
+    /// Indexed pixels are resolved through the palette, and become [`Pixel::Rgba`] if the file has palette
+    /// transparency. tRNS colour keys of other colour types are only applied once the image is expanded.
+    pub fn get_pixel(&self, x: u32, y: u32) -> Option<Pixel> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        let (x, y) = (x as usize, y as usize);
+        let scanline_length = self.scanline_length();
+        let row = &self.data[y * scanline_length..(y + 1) * scanline_length];
+
+        let bits = self.effective_bit_depth.bits() as usize;
+        let channels = self.channels();
+
+        let raw_sample = |index: usize| self.raw_sample(row, index);
+
+        // Replicating the bits scales every depth exactly to the full 16-bit range.
+        let scale = 0xffff / ((1u32 << bits) - 1) as u16;
+        let sample = |channel: usize| raw_sample(x * channels + channel) * scale;
+
+        let pixel = match self.effective_colour_type {
+            ColourType::Greyscale => Pixel::Grey(sample(0)),
+            ColourType::GreyscaleWithAlpha => Pixel::GreyAlpha(sample(0), sample(1)),
+            ColourType::Truecolour => Pixel::Rgb(sample(0), sample(1), sample(2)),
+            ColourType::TruecolourWithAlpha => {
+                Pixel::Rgba(sample(0), sample(1), sample(2), sample(3))
+            }
+            ColourType::IndexedColour => {
+                let index = raw_sample(x) as u8;
+                let [r, g, b] = self
+                    .palette
+                    .as_ref()?
+                    .get(index)
+                    .or(self.palette_fallback)?
+                    .map(|value| value as u16 * 257);
+
+                match &self.transparency {
+                    Some(alphas) => {
+                        let alpha = alphas.get(index as usize).copied().unwrap_or(255) as u16 * 257;
+                        Pixel::Rgba(r, g, b, alpha)
+                    }
+                    None => Pixel::Rgb(r, g, b),
+                }
+            }
+        };
+
+        Some(pixel)
+    }
+
+    /// Returns a [`Sampler`] for reading the raw bytes of pixels, or `None` for bit depths below 8, where pixels
+    /// don't fill whole bytes. [`expand`](Self::expand) the image first to sample those.
+    pub fn sampler(&self) -> Option<Sampler<'_>> {
+        if self.effective_bit_depth.bits() < 8 {
+            return None;
+        }
+
+        Some(Sampler {
+            data: &self.data,
+            stride: self.scanline_length(),
+            bytes_per_pixel: self.effective_bit_depth.bits() as usize / 8 * self.channels(),
+            width: self.width,
+            height: self.height,
+        })
+    }
+
+    /// Computes the [`ChannelStats`] of each channel of [`data`](Self::data) in one pass over the pixels. Indexed
+    /// images have a single channel of palette indices. Images without pixels have no stats.
+    pub fn channel_stats(&self) -> Vec<ChannelStats> {
+        let width = self.width as usize;
+        let channels = self.channels();
+        let scanline_length = self.scanline_length();
+        let pixel_count = width * self.height as usize;
+
+        if pixel_count == 0 {
+            return Vec::new();
+        }
+
+        let mut min = vec![u16::MAX; channels];
+        let mut max = vec![0; channels];
+        let mut sum = vec![0u64; channels];
+
+        for row in self.data.chunks_exact(scanline_length) {
+            for x in 0..width {
+                for channel in 0..channels {
+                    let sample = self.raw_sample(row, x * channels + channel);
+                    min[channel] = min[channel].min(sample);
+                    max[channel] = max[channel].max(sample);
+                    sum[channel] += sample as u64;
+                }
+            }
+        }
+
+        (0..channels)
+            .map(|channel| ChannelStats {
+                min: min[channel],
+                max: max[channel],
+                mean: sum[channel] as f64 / pixel_count as f64,
+            })
+            .collect()
+    }
+
+    /// Mirrors the image left to right in place.
+    pub fn flip_horizontal(&mut self) {
+        let width = self.width as usize;
+        let scanline_length = self.scanline_length();
+        let bits = self.effective_bit_depth.bits() as usize * self.channels();
+
+        if scanline_length == 0 {
+            return;
+        }
+
+        if bits < 8 {
+            // Packed pixels are moved one at a time. The padding at the end of each row stays at the end.
+            let mut flipped = vec![0; scanline_length];
+
+            for row in self.data.chunks_exact_mut(scanline_length) {
+                for x in 0..width {
+                    let sample = packed_sample(row, width - 1 - x, bits);
+                    set_packed_sample(&mut flipped, x, bits, sample);
+                }
+
+                row.copy_from_slice(&flipped);
+            }
+        } else {
+            let bytes_per_pixel = bits / 8;
+
+            for row in self.data.chunks_exact_mut(scanline_length) {
+                // Reversing the bytes reverses the pixel order, and reversing each pixel again restores the
+                // order of its samples.
+                row.reverse();
+                for pixel in row.chunks_exact_mut(bytes_per_pixel) {
+                    pixel.reverse();
+                }
+            }
+        }
+    }
+
+    /// Mirrors the image top to bottom in place.
+    pub fn flip_vertical(&mut self) {
+        let scanline_length = self.scanline_length();
+        let height = self.height as usize;
+
+        if scanline_length == 0 {
+            return;
+        }
+
+        for y in 0..height / 2 {
+            let (top, bottom) = self.data.split_at_mut((height - 1 - y) * scanline_length);
+            top[y * scanline_length..(y + 1) * scanline_length]
+                .swap_with_slice(&mut bottom[..scanline_length]);
+        }
+    }
+
+    /// Iterates over every pixel left to right, top to bottom, as [`get_pixel`](Self::get_pixel) reads them.
+    /// Palette indices that `get_pixel` can't resolve come out as [`PngError::PaletteIndexOutOfRange`].
+    pub fn pixels(&self) -> impl Iterator<Item = Result<Pixel, PngError>> + '_ {
+        (0..self.height).flat_map(move |y| {
+            (0..self.width).map(move |x| {
+                self.get_pixel(x, y).ok_or_else(|| {
+                    let scanline_length = self.scanline_length();
+                    let row = &self.data[y as usize * scanline_length..][..scanline_length];
+
+                    PngError::PaletteIndexOutOfRange {
+                        index: self.raw_sample(row, x as usize) as u8,
+                        palette_len: self
+                            .palette
+                            .as_ref()
+                            .map_or(0, |palette| palette.entries.len()),
+                    }
+                })
+            })
+        })
+    }
+}
+
+/// Fast repeated pixel lookups into a [`DecodedImage`], from [`DecodedImage::sampler`].
+///
+/// The offsets are computed once up front, and coordinates are only checked in debug builds. In release builds
+/// an `x` past the width reads from the next row, and a `y` past the height panics.
+#[derive(Debug, Clone, Copy)]
+pub struct Sampler<'a> {
+    data: &'a [u8],
+    stride: usize,
+    bytes_per_pixel: usize,
+    width: u32,
+    height: u32,
+}
+
+impl<'a> Sampler<'a> {
+    /// The bytes of the pixel at `x`, `y`, in the image's effective sample format.
+    #[inline]
+    pub fn get(&self, x: u32, y: u32) -> &'a [u8] {
+        debug_assert!(
+            x < self.width && y < self.height,
+            "pixel ({}, {}) is outside the {}x{} image",
+            x,
+            y,
+            self.width,
+            self.height
+        );
+
+        let start = y as usize * self.stride + x as usize * self.bytes_per_pixel;
+        &self.data[start..start + self.bytes_per_pixel]
+    }
+}
+
+impl PngFile {
+    /// Decodes the image data, keeping the palette and tRNS chunks needed to read individual pixels.
+    pub fn decode_image(&self) -> Result<DecodedImage, PngError> {
+        self.decode_image_with(&DecodeOptions::default())
+    }
+
+    pub fn decode_image_with(&self, options: &DecodeOptions) -> Result<DecodedImage, PngError> {
+        let header = self.try_parse_header()?;
+
+        let chunks = self.image_data_chunks()?;
+        let mut data = Vec::new();
+        let summary = decode_stream(
+            &header,
+            chunks.iter().map(|chunk| chunk.data.as_slice()),
+            options,
+            &mut data,
+        )?;
+
+        let palette = match header.colour_type {
+            ColourType::IndexedColour => Some(
+                self.palette()?
+                    .ok_or(PngError::MissingChunk(ChunkType::PLTE))?,
+            ),
+            _ => None,
+        };
+
+        let height = summary
+            .warnings
+            .iter()
+            .find_map(|warning| match warning {
+                DecodeWarning::TruncatedImageData { rows } => Some(*rows),
+                _ => None,
+            })
+            .unwrap_or(header.height);
+
+        Ok(DecodedImage {
+            width: header.width,
+            height,
+            bit_depth: header.bit_depth,
+            colour_type: header.colour_type,
+            effective_bit_depth: header.bit_depth,
+            effective_colour_type: header.colour_type,
+            data,
+            palette,
+            transparency: self
+                .find_chunk(ChunkType::tRNS)
+                .map(|chunk| chunk.data.clone()),
+            palette_fallback: options.palette_fallback,
+            warnings: summary.warnings,
+            compression: CompressionStats {
+                compressed_bytes: chunks.iter().map(|chunk| chunk.data.len()).sum(),
+                decompressed_bytes: summary.inflated_bytes,
+            },
+        })
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::test_util::{ihdr, parse, zlib};
+
+    #[test]
+    fn reads_pixels_matching_rgba_conversion() {
+        let png = PngFile::from_reader(&mut File::open("./code.png").unwrap()).unwrap();
+        let image = png.decode_image().unwrap();
+        let rgba = png.to_rgba8().unwrap();
+
+        for (x, y) in [(0, 0), (52, 52), (104, 0), (13, 97)] {
+            let offset = (y * 105 + x) as usize * 4;
+            let expected = Pixel::Rgba(
+                rgba[offset] as u16 * 257,
+                rgba[offset + 1] as u16 * 257,
+                rgba[offset + 2] as u16 * 257,
+                rgba[offset + 3] as u16 * 257,
+            );
+
+            assert_eq!(image.get_pixel(x, y), Some(expected), "{}, {}", x, y);
+        }
+
+        assert_eq!(image.get_pixel(105, 0), None);
+        assert_eq!(image.get_pixel(0, 105), None);
+    }
+
+    #[test]
+    fn iterates_over_pixels_in_order() {
+        let png = PngFile::from_reader(&mut File::open("./indexed-4bit-interlaced.png").unwrap())
+            .unwrap();
+        let image = png.decode_image().unwrap();
+        let rgba = png.to_rgba8().unwrap();
+
+        let pixels = image.pixels().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(pixels.len(), 13 * 11);
+
+        for (pixel, expected) in pixels.iter().zip(rgba.chunks_exact(4)) {
+            let expected = expected
+                .iter()
+                .map(|&value| value as u16 * 257)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                *pixel,
+                Pixel::Rgba(expected[0], expected[1], expected[2], expected[3])
+            );
+        }
+
+        let grey = parse(&[
+            (b"IHDR", &ihdr(2, 2, 8, 0)),
+            (b"IDAT", &zlib(&[0, 10, 20, 0, 30, 40])),
+            (b"IEND", &[]),
+        ]);
+        let sum = grey
+            .decode_image()
+            .unwrap()
+            .pixels()
+            .map(|pixel| match pixel.unwrap() {
+                Pixel::Grey(value) => value as u32 / 257,
+                _ => unreachable!(),
+            })
+            .sum::<u32>();
+        assert_eq!(sum, 100);
+
+        let past_palette = parse(&[
+            (b"IHDR", &ihdr(2, 1, 8, 3)),
+            (b"PLTE", &[255, 0, 0]),
+            (b"IDAT", &zlib(&[0, 0, 1])),
+            (b"IEND", &[]),
+        ]);
+        let image = past_palette.decode_image().unwrap();
+        let mut pixels = image.pixels();
+        assert!(matches!(pixels.next(), Some(Ok(Pixel::Rgb(0xffff, 0, 0)))));
+        assert!(matches!(
+            pixels.next(),
+            Some(Err(PngError::PaletteIndexOutOfRange {
+                index: 1,
+                palette_len: 1
+            }))
+        ));
+        assert!(pixels.next().is_none());
+    }
+
+    #[test]
+    fn computes_channel_stats() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(2, 2, 16, 4)),
+            (
+                b"IDAT",
+                &zlib(
+                    &[
+                        &[0][..],
+                        &[0x00, 0x10, 0xff, 0xff, 0x01, 0x00, 0x00, 0x00],
+                        &[0],
+                        &[0x00, 0x20, 0x80, 0x00, 0x02, 0x00, 0x00, 0x00],
+                    ]
+                    .concat(),
+                ),
+            ),
+            (b"IEND", &[]),
+        ]);
+
+        assert_eq!(
+            png.decode_image().unwrap().channel_stats(),
+            vec![
+                ChannelStats {
+                    min: 0x10,
+                    max: 0x200,
+                    mean: 204.0
+                },
+                ChannelStats {
+                    min: 0,
+                    max: 0xffff,
+                    mean: 24575.75
+                },
+            ]
+        );
+
+        let indexed = parse(&[
+            (b"IHDR", &ihdr(3, 1, 2, 3)),
+            (b"PLTE", &[0; 12]),
+            (b"IDAT", &zlib(&[0, 0b11_01_10_00])),
+            (b"IEND", &[]),
+        ]);
+        assert_eq!(
+            indexed.decode_image().unwrap().channel_stats(),
+            vec![ChannelStats {
+                min: 1,
+                max: 3,
+                mean: 2.0
+            }]
+        );
+    }
+
+    #[test]
+    fn flips_images() {
+        for path in [
+            "./code.png",
+            "./rgba16-filters.png",
+            "./indexed-4bit-interlaced.png",
+        ] {
+            let png = PngFile::from_reader(&mut File::open(path).unwrap()).unwrap();
+            let image = png.decode_image().unwrap();
+            let (width, height) = (image.width(), image.height());
+
+            let mut horizontal = image.clone();
+            horizontal.flip_horizontal();
+            let mut vertical = image.clone();
+            vertical.flip_vertical();
+
+            for (x, y) in [
+                (0, 0),
+                (width - 1, 0),
+                (3, height - 1),
+                (width / 2, height / 2),
+            ] {
+                let pixel = image.get_pixel(x, y);
+                assert_eq!(horizontal.get_pixel(width - 1 - x, y), pixel, "{}", path);
+                assert_eq!(vertical.get_pixel(x, height - 1 - y), pixel, "{}", path);
+            }
+
+            horizontal.flip_horizontal();
+            vertical.flip_vertical();
+            assert_eq!(horizontal.data(), image.data(), "{}", path);
+            assert_eq!(vertical.data(), image.data(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn flips_sub_byte_pixels() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(3, 3, 2, 0)),
+            (
+                b"IDAT",
+                &zlib(&[0, 0b00_01_10_00, 0, 0b11_00_00_00, 0, 0b01_01_11_00]),
+            ),
+            (b"IEND", &[]),
+        ]);
+        let mut image = png.decode_image().unwrap();
+
+        image.flip_horizontal();
+        assert_eq!(image.data(), [0b10_01_00_00, 0b00_00_11_00, 0b11_01_01_00]);
+
+        image.flip_vertical();
+        assert_eq!(image.data(), [0b11_01_01_00, 0b00_00_11_00, 0b10_01_00_00]);
+    }
+
+    #[test]
+    fn samples_pixel_bytes() {
+        let png = PngFile::from_reader(&mut File::open("./code.png").unwrap()).unwrap();
+        let image = png.decode_image().unwrap();
+        let sampler = image.sampler().unwrap();
+        let rgba = png.to_rgba8().unwrap();
+
+        assert_eq!(
+            image.effective_colour_type(),
+            ColourType::TruecolourWithAlpha
+        );
+
+        for (x, y) in [(0, 0), (52, 52), (104, 0), (13, 97), (104, 104)] {
+            let offset = (y * 105 + x) as usize * 4;
+            assert_eq!(sampler.get(x, y), &rgba[offset..offset + 4]);
+        }
+
+        let grey = parse(&[
+            (b"IHDR", &ihdr(3, 1, 4, 0)),
+            (b"IDAT", &zlib(&[0, 0x0f, 0x80])),
+            (b"IEND", &[]),
+        ]);
+        let mut image = grey.decode_image().unwrap();
+        assert!(image.sampler().is_none());
+
+        image.expand().unwrap();
+        assert_eq!(image.sampler().unwrap().get(1, 0), [255]);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn sampler_checks_bounds_in_debug_builds() {
+        let png = PngFile::from_reader(&mut File::open("./code.png").unwrap()).unwrap();
+        let image = png.decode_image().unwrap();
+        image.sampler().unwrap().get(105, 0);
+    }
+
+    #[test]
+    fn scales_samples_to_16_bits() {
+        let grey16 = parse(&[
+            (b"IHDR", &ihdr(2, 1, 16, 4)),
+            (
+                b"IDAT",
+                &zlib(&[0, 0x12, 0x34, 0xff, 0xff, 0x00, 0x01, 0x80, 0x00]),
+            ),
+            (b"IEND", &[]),
+        ]);
+        let image = grey16.decode_image().unwrap();
+        assert_eq!(
+            image.get_pixel(0, 0),
+            Some(Pixel::GreyAlpha(0x1234, 0xffff))
+        );
+        assert_eq!(
+            image.get_pixel(1, 0),
+            Some(Pixel::GreyAlpha(0x0001, 0x8000))
+        );
+
+        let grey2 = parse(&[
+            (b"IHDR", &ihdr(5, 1, 2, 0)),
+            (b"IDAT", &zlib(&[0, 0b0001_1011, 0b1000_0000])),
+            (b"IEND", &[]),
+        ]);
+        let image = grey2.decode_image().unwrap();
+        let samples = (0..5)
+            .map(|x| image.get_pixel(x, 0).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            samples,
+            [0, 0x5555, 0xaaaa, 0xffff, 0xaaaa].map(Pixel::Grey)
+        );
+
+        let rgb8 = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 2)),
+            (b"IDAT", &zlib(&[0, 0, 128, 255])),
+            (b"IEND", &[]),
+        ]);
+        assert_eq!(
+            rgb8.decode_image().unwrap().get_pixel(0, 0),
+            Some(Pixel::Rgb(0, 0x8080, 0xffff))
+        );
+    }
+
+    #[test]
+    fn expands_to_effective_format() {
+        let indexed = parse(&[
+            (b"IHDR", &ihdr(3, 1, 2, 3)),
+            (b"PLTE", &[255, 0, 0, 0, 255, 0, 0, 0, 255]),
+            (b"tRNS", &[0, 128]),
+            (b"IDAT", &zlib(&[0, 0b0001_1000])),
+            (b"IEND", &[]),
+        ]);
+        let mut image = indexed.decode_image().unwrap();
+        let before = (0..3).map(|x| image.get_pixel(x, 0)).collect::<Vec<_>>();
+
+        image.expand().unwrap();
+
+        assert_eq!(image.colour_type(), ColourType::IndexedColour);
+        assert_eq!(image.bit_depth(), BitDepth::B2);
+        assert_eq!(
+            image.effective_colour_type(),
+            ColourType::TruecolourWithAlpha
+        );
+        assert_eq!(image.effective_bit_depth(), BitDepth::B8);
+        assert_eq!(image.channels(), 4);
+        assert_eq!(image.data(), [255, 0, 0, 0, 0, 255, 0, 128, 0, 0, 255, 255]);
+        assert_eq!(
+            (0..3).map(|x| image.get_pixel(x, 0)).collect::<Vec<_>>(),
+            before
+        );
+
+        let keyed = parse(&[
+            (b"IHDR", &ihdr(2, 1, 16, 2)),
+            (b"tRNS", &[0x12, 0x34, 0, 0, 0xff, 0xff]),
+            (
+                b"IDAT",
+                &zlib(&[0, 0x12, 0x34, 0, 0, 0xff, 0xff, 0, 1, 0, 0, 0, 0]),
+            ),
+            (b"IEND", &[]),
+        ]);
+        let mut image = keyed.decode_image().unwrap();
+        image.expand().unwrap();
+
+        assert_eq!(
+            image.effective_colour_type(),
+            ColourType::TruecolourWithAlpha
+        );
+        assert_eq!(image.effective_bit_depth(), BitDepth::B16);
+        assert_eq!(
+            image.get_pixel(0, 0),
+            Some(Pixel::Rgba(0x1234, 0, 0xffff, 0))
+        );
+        assert_eq!(image.get_pixel(1, 0), Some(Pixel::Rgba(1, 0, 0, 0xffff)));
+
+        let grey = parse(&[
+            (b"IHDR", &ihdr(3, 1, 4, 0)),
+            (b"IDAT", &zlib(&[0, 0x0f, 0x80])),
+            (b"IEND", &[]),
+        ]);
+        let mut image = grey.decode_image().unwrap();
+        image.expand().unwrap();
+
+        assert_eq!(image.effective_colour_type(), ColourType::Greyscale);
+        assert_eq!(image.effective_bit_depth(), BitDepth::B8);
+        assert_eq!(image.data(), [0, 255, 136]);
+    }
+
+    #[test]
+    fn expands_like_rgba_conversion() {
+        let path = "./indexed-4bit-interlaced.png";
+        let png = PngFile::from_reader(&mut File::open(path).unwrap()).unwrap();
+        let mut image = png.decode_image().unwrap();
+        image.expand().unwrap();
+
+        assert_eq!(image.channels(), 4);
+        assert_eq!(image.into_data(), png.to_rgba8().unwrap());
+    }
+
+    #[test]
+    fn reports_compression_stats() {
+        let png = PngFile::from_reader(&mut File::open("./FL.png").unwrap()).unwrap();
+        let stats = png.decode_image().unwrap().compression_stats();
+
+        let idat_bytes = png
+            .chunks
+            .iter()
+            .filter(|chunk| chunk.chunk_type == ChunkType::IDAT)
+            .map(|chunk| chunk.data.len())
+            .sum::<usize>();
+
+        assert_eq!(stats.compressed_bytes, idat_bytes);
+        assert_eq!(stats.decompressed_bytes, (1318 * 4 + 1) * 1026);
+        assert!(stats.ratio().unwrap() > 1.0);
+
+        // Two and a half scanlines of a three-row image.
+        let truncated = parse(&[
+            (b"IHDR", &ihdr(2, 3, 8, 0)),
+            (b"IDAT", &zlib(&[0, 1, 2, 0, 3, 4, 0, 5])),
+            (b"IEND", &[]),
+        ]);
+        let mut options = DecodeOptions::new();
+        options.set_allow_truncated(true);
+        let image = truncated.decode_image_with(&options).unwrap();
+
+        assert_eq!(image.height(), 2);
+        assert_eq!(image.compression_stats().decompressed_bytes, 8);
+
+        let empty = CompressionStats {
+            compressed_bytes: 0,
+            decompressed_bytes: 0,
+        };
+        assert_eq!(empty.ratio(), None);
+    }
+
+    #[test]
+    fn resolves_palette_entries() {
+        let chunks: [(&[u8; 4], &[u8]); 4] = [
+            (b"IHDR", &ihdr(4, 1, 4, 3)),
+            (b"PLTE", &[255, 0, 0, 0, 0, 255]),
+            (b"IDAT", &zlib(&[0, 0x01, 0x02])),
+            (b"IEND", &[]),
+        ];
+
+        let image = parse(&chunks).decode_image().unwrap();
+        assert_eq!(image.get_pixel(0, 0), Some(Pixel::Rgb(0xffff, 0, 0)));
+        assert_eq!(image.get_pixel(1, 0), Some(Pixel::Rgb(0, 0, 0xffff)));
+        // Index 2 is past the end of the palette.
+        assert_eq!(image.get_pixel(3, 0), None);
+
+        let transparent = parse(&[
+            chunks[0],
+            chunks[1],
+            (b"tRNS", &[128]),
+            chunks[2],
+            chunks[3],
+        ]);
+        let image = transparent.decode_image().unwrap();
+        assert_eq!(
+            image.get_pixel(0, 0),
+            Some(Pixel::Rgba(0xffff, 0, 0, 0x8080))
+        );
+        assert_eq!(
+            image.get_pixel(1, 0),
+            Some(Pixel::Rgba(0, 0, 0xffff, 0xffff))
+        );
+    }
+}
@@ -1,10 +1,37 @@
-use std::io::Write;
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::fmt;
 
-use inflate::InflateWriter;
+use miniz_oxide::inflate::{
+    core::{
+        decompress,
+        inflate_flags::{
+            TINFL_FLAG_HAS_MORE_INPUT, TINFL_FLAG_PARSE_ZLIB_HEADER,
+            TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+        },
+        DecompressorOxide,
+    },
+    TINFLStatus,
+};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 
-use crate::png_parser::{ChunkType, PngChunk, PngError, PngHeader};
+use crate::{
+    adam7::PASSES,
+    png_parser::{ChunkType, InterlaceMethod, PngChunk, PngError, PngHeader},
+};
 
-fn filter_none(
+/// The zlib stream couldn't be inflated, with the status reported by miniz_oxide.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("zlib stream failed with status {0:?}")]
+pub struct InflateError(TINFLStatus);
+
+impl InflateError {
+    pub fn status(&self) -> TINFLStatus {
+        self.0
+    }
+}
+
+pub fn filter_none(
     x: u8,
     _previous: u8,
     _scanline_offset: usize,
@@ -15,7 +42,7 @@ fn filter_none(
 }
 
 #[inline]
-fn filter_sub(
+pub fn filter_sub(
     x: u8,
     previous: u8,
     _scanline_offset: usize,
@@ -26,7 +53,7 @@ fn filter_sub(
 }
 
 #[inline]
-fn filter_up(
+pub fn filter_up(
     x: u8,
     _previous: u8,
     scanline_offset: usize,
@@ -38,21 +65,24 @@ fn filter_up(
 }
 
 #[inline]
-fn filter_average(
+pub fn filter_average(
     x: u8,
     previous: u8,
     scanline_offset: usize,
     previous_scanline: &[u8],
     _pixel_size: usize,
 ) -> u8 {
-    let a = previous;
-    let b = previous_scanline[scanline_offset];
+    let a = previous as u16;
+    let b = previous_scanline[scanline_offset] as u16;
 
-    (x as i32 + ((a as i32 + b as i32) / 2)) as u8
+    // The sum of a and b needs 9 bits. Their floored mean fits back into a byte, and adding it to x is
+    // modulo 256.
+    let mean = ((a + b) / 2) as u8;
+    x.wrapping_add(mean)
 }
 
 #[inline]
-fn filter_paeth(
+pub fn filter_paeth(
     x: u8,
     previous: u8,
     scanline_offset: usize,
@@ -70,7 +100,7 @@ fn filter_paeth(
     x.wrapping_add(paeth_predictor(a, b, c))
 }
 
-fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+pub(crate) fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
     let a = a as i32;
     let b = b as i32;
     let c = c as i32;
@@ -89,67 +119,2407 @@ fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
     }
 }
 
+/// Reconstructs one byte of a filtered scanline. Arguments are the filtered byte, the reconstructed byte one
+/// pixel to the left (0 on the first pixel), the byte's offset in the scanline, the reconstructed previous
+/// scanline (all zeros on the first row) and the pixel size in bytes.
+pub type FilterFn = fn(u8, u8, usize, &[u8], usize) -> u8;
+
+/// Maps the filter type byte at the start of each scanline to the function that reverses it.
+///
+/// The default table holds the five filters of the PNG spec. Filters can be overridden or registered for
+/// other type bytes to experiment with new filters. A type byte without a filter fails decoding with
+/// [`PngError::InvalidFilterType`].
+#[derive(Clone, Copy)]
+pub struct FilterTable {
+    filters: [Option<FilterFn>; 256],
+    /// Whether type 0 is the spec's None filter, which lets scanlines be copied without running it.
+    standard_none: bool,
+}
+
+impl FilterTable {
+    /// A table with no filters registered, not even the standard ones.
+    pub fn empty() -> Self {
+        FilterTable {
+            filters: [None; 256],
+            standard_none: false,
+        }
+    }
+
+    pub fn standard() -> Self {
+        let mut table = FilterTable::empty();
+        table
+            .set(0, filter_none)
+            .set(1, filter_sub)
+            .set(2, filter_up)
+            .set(3, filter_average)
+            .set(4, filter_paeth);
+        table.standard_none = true;
+        table
+    }
+
+    pub fn set(&mut self, filter_type: u8, filter: FilterFn) -> &mut Self {
+        self.filters[filter_type as usize] = Some(filter);
+
+        if filter_type == 0 {
+            self.standard_none = false;
+        }
+
+        self
+    }
+
+    pub fn get(&self, filter_type: u8) -> Option<FilterFn> {
+        self.filters[filter_type as usize]
+    }
+}
+
+impl Default for FilterTable {
+    fn default() -> Self {
+        FilterTable::standard()
+    }
+}
+
+impl fmt::Debug for FilterTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let registered = (0..=255u8).filter(|&filter_type| self.get(filter_type).is_some());
+        f.debug_set().entries(registered).finish()
+    }
+}
+
+/// A problem that decoding tolerated because of [`DecodeOptions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeWarning {
+    /// The Adler-32 checksum at the end of the zlib stream didn't match the decompressed data.
+    Adler32Mismatch,
+    /// The image data ended early, and only the first `rows` complete scanlines were decoded.
+    TruncatedImageData { rows: u32 },
+}
+
+/// Settings for [`PngFile::decode_with`](crate::png_parser::PngFile::decode_with) and the other `_with` decoding
+/// methods. The fields can be set directly, or through the chainable setters.
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    /// Upper bound in bytes for both the inflated scanline stream and the reconstructed image. Decoding
+    /// fails with [`PngError::DecompressedTooLarge`] instead of allocating more than this.
+    pub max_decompressed_bytes: Option<usize>,
+    pub filters: FilterTable,
+    /// Accepts image data whose zlib Adler-32 checksum is wrong, which is otherwise an error. The mismatch is
+    /// reported as a [`DecodeWarning`] by
+    /// [`PngFile::decode_image_with`](crate::png_parser::PngFile::decode_image_with).
+    pub ignore_adler32: bool,
+    /// Colour for palette indices past the end of PLTE when expanding indexed images, which is otherwise an
+    /// error. Magenta (`[255, 0, 255]`) makes such pixels easy to spot.
+    pub palette_fallback: Option<[u8; 3]>,
+    /// Decodes the complete scanlines of image data that ends early, which is otherwise an error. The decoded
+    /// image is then shorter than the header says, and [`DecodeWarning::TruncatedImageData`] says how many rows
+    /// it has. Interlaced images spread every row over all seven passes, so they still have to be complete.
+    pub allow_truncated: bool,
+}
+
+impl DecodeOptions {
+    /// The defaults: no size limit, the standard filters and every check enabled.
+    pub fn new() -> Self {
+        DecodeOptions::default()
+    }
+
+    pub fn set_max_decompressed_bytes(&mut self, limit: usize) -> &mut Self {
+        self.max_decompressed_bytes = Some(limit);
+        self
+    }
+
+    pub fn set_filters(&mut self, filters: FilterTable) -> &mut Self {
+        self.filters = filters;
+        self
+    }
+
+    pub fn set_ignore_adler32(&mut self, ignore_adler32: bool) -> &mut Self {
+        self.ignore_adler32 = ignore_adler32;
+        self
+    }
+
+    pub fn set_palette_fallback(&mut self, colour: [u8; 3]) -> &mut Self {
+        self.palette_fallback = Some(colour);
+        self
+    }
+
+    pub fn set_allow_truncated(&mut self, allow_truncated: bool) -> &mut Self {
+        self.allow_truncated = allow_truncated;
+        self
+    }
+}
+
+/// Output of [`inflate_stream`].
+struct Inflated {
+    data: Vec<u8>,
+    adler32_valid: bool,
+    /// The input ran out before the end of the stream.
+    truncated: bool,
+}
+
+/// Inflates a complete zlib stream, as found in zTXt, iTXt and iCCP chunks.
+pub(crate) fn inflate_zlib(data: &[u8]) -> Result<Vec<u8>, PngError> {
+    let inflated = inflate_stream(core::iter::once(data), None, None)?;
+
+    if inflated.truncated {
+        return Err(truncated_stream());
+    }
+
+    if !inflated.adler32_valid {
+        return Err(adler32_mismatch());
+    }
+
+    Ok(inflated.data)
+}
+
+fn adler32_mismatch() -> PngError {
+    InflateError(TINFLStatus::Adler32Mismatch).into()
+}
+
+fn truncated_stream() -> PngError {
+    InflateError(TINFLStatus::FailedCannotMakeProgress).into()
+}
+
+/// Rejects zlib headers miniz_oxide can't inflate, so that the error carries the header bytes instead of a
+/// generic failure status. Preset dictionaries aren't supported, and neither are compression methods other
+/// than deflate with a window of at most 32 KiB. Smaller windows are fine, since a larger one can always
+/// hold them.
+fn check_zlib_header([cmf, flags]: [u8; 2]) -> Result<(), PngError> {
+    let is_deflate = cmf & 0x0f == 8;
+    let window_fits = cmf >> 4 <= 7;
+    let check_valid = u16::from_be_bytes([cmf, flags]) % 31 == 0;
+    let has_dictionary = flags & 0x20 != 0;
+
+    if is_deflate && window_fits && check_valid && !has_dictionary {
+        Ok(())
+    } else {
+        Err(PngError::UnsupportedZlibHeader { cmf, flags })
+    }
+}
+
+/// Collects the two zlib header bytes, which may be split across chunks, and checks them as soon as both have
+/// been seen.
+#[derive(Default)]
+struct ZlibHeader {
+    bytes: [u8; 2],
+    len: usize,
+}
+
+impl ZlibHeader {
+    fn feed(&mut self, input: &[u8]) -> Result<(), PngError> {
+        if self.len < 2 {
+            let taken = input.len().min(2 - self.len);
+            self.bytes[self.len..self.len + taken].copy_from_slice(&input[..taken]);
+            self.len += taken;
+
+            if self.len == 2 {
+                check_zlib_header(self.bytes)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Inflates a zlib stream split over several byte slices, stopping as soon as the output would exceed
+/// `limit` bytes. With `wanted`, inflating also stops once that many bytes have been produced, and the
+/// Adler-32 checksum is left unchecked.
+fn inflate_stream<'a>(
+    data: impl Iterator<Item = &'a [u8]>,
+    limit: Option<usize>,
+    wanted: Option<usize>,
+) -> Result<Inflated, PngError> {
+    let mut decompressor = Box::<DecompressorOxide>::default();
+    let mut out = Vec::new();
+    let mut out_pos = 0;
+
+    let mut data = data.peekable();
+
+    let mut header = ZlibHeader::default();
+
+    while let Some(mut input) = data.next() {
+        header.feed(input)?;
+
+        let mut flags = TINFL_FLAG_PARSE_ZLIB_HEADER | TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+
+        if data.peek().is_some() {
+            flags |= TINFL_FLAG_HAS_MORE_INPUT;
+        }
+
+        loop {
+            if out_pos == out.len() {
+                let mut new_len = (out.len() * 2).max(32 * 1024);
+
+                // One byte past the limit is enough to tell that the stream is too large.
+                if let Some(limit) = limit {
+                    new_len = new_len.min(limit.saturating_add(1));
+                }
+
+                if let Some(wanted) = wanted {
+                    new_len = new_len.min(wanted);
+                }
+
+                out.resize(new_len, 0);
+            }
+
+            let (status, consumed, produced) =
+                decompress(&mut decompressor, input, &mut out, out_pos, flags);
+
+            input = &input[consumed..];
+            out_pos += produced;
+
+            if let Some(limit) = limit {
+                if out_pos > limit {
+                    return Err(PngError::DecompressedTooLarge { limit });
+                }
+            }
+
+            if wanted == Some(out_pos) {
+                out.truncate(out_pos);
+                return Ok(Inflated {
+                    data: out,
+                    adler32_valid: true,
+                    truncated: false,
+                });
+            }
+
+            match status {
+                TINFLStatus::Done | TINFLStatus::Adler32Mismatch => {
+                    out.truncate(out_pos);
+                    return Ok(Inflated {
+                        data: out,
+                        adler32_valid: status == TINFLStatus::Done,
+                        truncated: false,
+                    });
+                }
+                TINFLStatus::NeedsMoreInput => break,
+                TINFLStatus::HasMoreOutput => continue,
+                // Only reported once the last slice has been consumed.
+                TINFLStatus::FailedCannotMakeProgress => {
+                    out.truncate(out_pos);
+                    return Ok(Inflated {
+                        data: out,
+                        adler32_valid: true,
+                        truncated: true,
+                    });
+                }
+                status => return Err(InflateError(status).into()),
+            }
+        }
+    }
+
+    out.truncate(out_pos);
+    Ok(Inflated {
+        data: out,
+        adler32_valid: true,
+        truncated: false,
+    })
+}
+
+/// Inflates a zlib stream split over several byte slices through a 32 KiB window, passing the output to
+/// `consume` as it's produced instead of collecting it.
+#[cfg(feature = "std")]
+fn inflate_streaming<'a>(
+    data: impl Iterator<Item = &'a [u8]>,
+    mut consume: impl FnMut(&[u8]) -> Result<(), PngError>,
+) -> Result<Inflated, PngError> {
+    let mut decompressor = Box::<DecompressorOxide>::default();
+    // Without TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF, miniz_oxide uses the output as the back-reference
+    // window, which has to be a power of two of at least 32 KiB.
+    let mut window = vec![0; 32 * 1024];
+    let mut out_pos = 0;
+
+    let mut data = data.peekable();
+    let mut header = ZlibHeader::default();
+
+    let inflated = |adler32_valid, truncated| Inflated {
+        data: Vec::new(),
+        adler32_valid,
+        truncated,
+    };
+
+    while let Some(mut input) = data.next() {
+        header.feed(input)?;
+
+        let mut flags = TINFL_FLAG_PARSE_ZLIB_HEADER;
+
+        if data.peek().is_some() {
+            flags |= TINFL_FLAG_HAS_MORE_INPUT;
+        }
+
+        loop {
+            let (status, consumed, produced) =
+                decompress(&mut decompressor, input, &mut window, out_pos, flags);
+
+            input = &input[consumed..];
+            consume(&window[out_pos..out_pos + produced])?;
+            out_pos = (out_pos + produced) % window.len();
+
+            match status {
+                TINFLStatus::Done | TINFLStatus::Adler32Mismatch => {
+                    return Ok(inflated(status == TINFLStatus::Done, false));
+                }
+                TINFLStatus::NeedsMoreInput => break,
+                TINFLStatus::HasMoreOutput => continue,
+                TINFLStatus::FailedCannotMakeProgress => return Ok(inflated(true, true)),
+                status => return Err(InflateError(status).into()),
+            }
+        }
+    }
+
+    Ok(inflated(true, false))
+}
+
+/// Like [`decode_data`], but writes each scanline to `out` as soon as it's reconstructed. Only the scanline
+/// being reconstructed and the one above it are kept in memory, however tall the image is.
+///
+/// Interlaced images don't produce any complete scanline until the last pass, so they are decoded in full and
+/// then written out.
+#[cfg(feature = "std")]
+pub fn decode_to_writer<'a, W: std::io::Write>(
+    header: &'a PngHeader,
+    chunks: impl Iterator<Item = &'a PngChunk>,
+    options: &DecodeOptions,
+    out: &mut W,
+) -> Result<(), PngError> {
+    if header.interlace_method != InterlaceMethod::None {
+        let mut decoded = Vec::new();
+        decode_data(header, chunks, options, &mut decoded)?;
+        out.write_all(&decoded)?;
+        return Ok(());
+    }
+
+    let data = image_data(chunks)?;
+
+    let decoded_length = header.decoded_len().ok_or(image_too_large(header))?;
+    let expected = filtered_length(header)?;
+
+    if let Some(limit) = options.max_decompressed_bytes {
+        if decoded_length > limit || expected > limit {
+            return Err(PngError::DecompressedTooLarge { limit });
+        }
+    }
+
+    let bytes_per_pixel = (header.bits_per_pixel() / 8).max(1);
+    let scanline_length = header.scanline_length(header.width as usize);
+
+    let mut filtered = Vec::with_capacity(scanline_length + 1);
+    let mut previous_scanline = vec![0; scanline_length];
+    let mut scanline = vec![0; scanline_length];
+    let mut y = 0;
+    let mut total = 0;
+
+    let inflated = inflate_streaming(data.into_iter(), |mut produced| {
+        total += produced.len();
+
+        // Anything past the last scanline is only counted, for the length check below.
+        while !produced.is_empty() && y < header.height as usize {
+            let taken = produced.len().min(scanline_length + 1 - filtered.len());
+            filtered.extend_from_slice(&produced[..taken]);
+            produced = &produced[taken..];
+
+            if filtered.len() == scanline_length + 1 {
+                unfilter_scanline(
+                    y,
+                    &filtered,
+                    &previous_scanline,
+                    &mut scanline,
+                    bytes_per_pixel,
+                    &options.filters,
+                )?;
+                out.write_all(&scanline)?;
+
+                core::mem::swap(&mut previous_scanline, &mut scanline);
+                filtered.clear();
+                y += 1;
+            }
+        }
+
+        Ok(())
+    })?;
+
+    if inflated.truncated {
+        return Err(truncated_stream());
+    }
+
+    if !inflated.adler32_valid && !options.ignore_adler32 {
+        return Err(adler32_mismatch());
+    }
+
+    if total == 0 && decoded_length > 0 {
+        return Err(PngError::MissingImageData);
+    }
+
+    if total > expected {
+        return Err(PngError::SurplusImageData { expected });
+    }
+
+    if total != expected {
+        return Err(PngError::WrongDataLength {
+            expected,
+            actual: total,
+        });
+    }
+
+    Ok(())
+}
+
+/// Inflates and unfilters the image data in `chunks` into `decoded_data_out`. Every chunk must be an IDAT
+/// chunk, or decoding fails with [`PngError::NonImageDataChunk`].
 pub fn decode_data<'a>(
     header: &'a PngHeader,
     chunks: impl Iterator<Item = &'a PngChunk>,
+    options: &DecodeOptions,
+    decoded_data_out: &mut Vec<u8>,
+) -> Result<(), PngError> {
+    let data = image_data(chunks)?;
+
+    decode_stream(header, data.into_iter(), options, decoded_data_out)?;
+    Ok(())
+}
+
+/// Like [`decode_data`], but only reconstructs the first `rows` scanlines, and stops inflating as soon as it
+/// has them. The rest of the image data is never looked at, so it isn't checked for errors either. `rows` is
+/// clamped to the image height.
+///
+/// Every Adam7 pass contributes to the first rows, so interlaced images are decoded in full and then cut short.
+pub fn decode_first_rows<'a>(
+    header: &'a PngHeader,
+    chunks: impl Iterator<Item = &'a PngChunk>,
+    options: &DecodeOptions,
+    rows: u32,
     decoded_data_out: &mut Vec<u8>,
 ) -> Result<(), PngError> {
-    let mut inflate_writer = InflateWriter::from_zlib(Vec::new());
+    let rows = rows.min(header.height) as usize;
 
-    for chunk in chunks {
-        assert_eq!(chunk.chunk_type, ChunkType::IDAT);
-        inflate_writer.write_all(&chunk.data)?;
+    if header.interlace_method != InterlaceMethod::None {
+        decode_data(header, chunks, options, decoded_data_out)?;
+        let scanline_length = header.scanline_length(header.width as usize);
+        decoded_data_out.truncate(rows * scanline_length);
+        return Ok(());
     }
 
-    let decompressed = inflate_writer.finish()?;
+    let data = image_data(chunks)?;
+    decoded_data_out.clear();
 
-    // TODO: handle 1-4 bit depth
-    let bytes_per_channel = header.bit_depth.to_bytes();
-    let number_of_channels = header.colour_type.channel_count();
-    let bytes_per_pixel = number_of_channels * bytes_per_channel;
+    // The header alone decides these sizes, so they're checked before anything is allocated.
+    let scanline_length = (header.width as usize)
+        .checked_mul(header.bits_per_pixel())
+        .ok_or(image_too_large(header))?
+        .div_ceil(8);
+    let wanted = (scanline_length + 1)
+        .checked_mul(rows)
+        .ok_or(image_too_large(header))?;
 
-    let scanline_length = header.width as usize * bytes_per_pixel;
-    let scanline_length_with_filter = scanline_length + 1;
+    if let Some(limit) = options.max_decompressed_bytes {
+        if wanted > limit {
+            return Err(PngError::DecompressedTooLarge { limit });
+        }
+    }
 
-    decoded_data_out.resize(scanline_length * header.height as usize, 0);
+    if rows == 0 || scanline_length == 0 {
+        return Ok(());
+    }
 
-    let mut previous_scanline = vec![0u8; scanline_length];
+    if data.is_empty() {
+        return Err(PngError::MissingImageData);
+    }
+
+    let decompressed = inflate_stream(
+        data.into_iter(),
+        options.max_decompressed_bytes,
+        Some(wanted),
+    )?
+    .data;
 
-    let input_chunks = decompressed.chunks_exact(scanline_length_with_filter);
-    let output_chunks = decoded_data_out.chunks_exact_mut(scanline_length);
+    if decompressed.len() != wanted {
+        return Err(PngError::WrongDataLength {
+            expected: wanted,
+            actual: decompressed.len(),
+        });
+    }
 
-    for (scanline_in, scanline_out) in input_chunks.zip(output_chunks) {
-        let (filter_type, scanline_in) = scanline_in.split_first().unwrap();
+    // Only sized now that the stream has shown it really holds this many rows.
+    decoded_data_out.resize(rows * scanline_length, 0);
 
-        let filter = match filter_type {
-            0 => filter_none,
-            1 => filter_sub,
-            2 => filter_up,
-            3 => filter_average,
-            4 => filter_paeth,
-            _ => panic!("Invalid filter type"),
-        };
+    unfilter_scanlines(
+        &decompressed,
+        decoded_data_out,
+        scanline_length,
+        (header.bits_per_pixel() / 8).max(1),
+        &options.filters,
+    )
+}
 
-        for (scanline_offset, byte) in scanline_in.iter().copied().enumerate() {
-            let previous = if scanline_offset >= bytes_per_pixel {
-                scanline_out[scanline_offset - bytes_per_pixel]
-            } else {
-                0
-            };
+/// Inflates the image data without reversing the filters: every scanline with its filter type byte, across all
+/// passes for interlaced images. The stream is still checked to have the right length for `header`.
+pub fn filtered_stream<'a>(
+    header: &'a PngHeader,
+    chunks: impl Iterator<Item = &'a PngChunk>,
+    options: &DecodeOptions,
+) -> Result<Vec<u8>, PngError> {
+    let data = image_data(chunks)?;
+    let (decompressed, _) = inflate_image_data(header, data.into_iter(), options, false)?;
+    Ok(decompressed)
+}
 
-            let decoded = filter(
-                byte,
-                previous,
-                scanline_offset,
-                &previous_scanline,
+/// Like [`decode_data`], but stores the image as `tile_width` by `tile_height` tiles instead of scanlines.
+///
+/// Tiles are laid out left to right, top to bottom, and each tile's rows are stored one after another. Tiles on
+/// the right and bottom edges only contain the pixels inside the image, so they can be smaller. Every tile row
+/// starts on a byte boundary, like a scanline does.
+pub fn decode_tiled<'a>(
+    header: &'a PngHeader,
+    chunks: impl Iterator<Item = &'a PngChunk>,
+    options: &DecodeOptions,
+    tile_width: u32,
+    tile_height: u32,
+    tiled_data_out: &mut Vec<u8>,
+) -> Result<(), PngError> {
+    if tile_width == 0 || tile_height == 0 {
+        return Err(PngError::InvalidTileSize {
+            width: tile_width,
+            height: tile_height,
+        });
+    }
+
+    let data = image_data(chunks)?;
+    let (decompressed, _) = inflate_image_data(header, data.into_iter(), options, false)?;
+
+    // Tiles bigger than the image hold the whole image, so clamping doesn't change the layout.
+    let layout = TileLayout {
+        width: header.width as usize,
+        tile_width: tile_width.min(header.width.max(1)) as usize,
+        tile_height: tile_height.min(header.height.max(1)) as usize,
+        height: header.height as usize,
+        bits_per_pixel: header.bits_per_pixel(),
+    };
+    let bytes_per_pixel = (header.bits_per_pixel() / 8).max(1);
+    let scanline_length = header.scanline_length(header.width as usize);
+
+    tiled_data_out.clear();
+    tiled_data_out.resize(layout.len(), 0);
+
+    if scanline_length == 0 {
+        return Ok(());
+    }
+
+    match header.interlace_method {
+        // Scanlines are scattered into place as soon as they're reconstructed, so the linear image is never
+        // stored.
+        InterlaceMethod::None => {
+            let mut previous_scanline = vec![0u8; scanline_length];
+            let mut scanline = vec![0u8; scanline_length];
+
+            for (y, scanline_in) in decompressed.chunks_exact(scanline_length + 1).enumerate() {
+                unfilter_scanline(
+                    y,
+                    scanline_in,
+                    &previous_scanline,
+                    &mut scanline,
+                    bytes_per_pixel,
+                    &options.filters,
+                )?;
+                layout.scatter_row(y, &scanline, tiled_data_out);
+                core::mem::swap(&mut previous_scanline, &mut scanline);
+            }
+        }
+        // Every pass contributes to every tile, so the full image has to be assembled first.
+        InterlaceMethod::Adam7 => {
+            let mut linear = vec![0u8; scanline_length * header.height as usize];
+            decode_adam7(
+                header,
+                &decompressed,
+                &mut linear,
                 bytes_per_pixel,
-            );
+                &options.filters,
+            )?;
+
+            for (y, scanline) in linear.chunks_exact(scanline_length).enumerate() {
+                layout.scatter_row(y, scanline, tiled_data_out);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn image_data<'a>(chunks: impl Iterator<Item = &'a PngChunk>) -> Result<Vec<&'a [u8]>, PngError> {
+    chunks
+        .map(|chunk| match chunk.chunk_type {
+            ChunkType::IDAT => Ok(chunk.data.as_slice()),
+            was => Err(PngError::NonImageDataChunk(was)),
+        })
+        .collect()
+}
+
+struct TileLayout {
+    width: usize,
+    height: usize,
+    tile_width: usize,
+    tile_height: usize,
+    bits_per_pixel: usize,
+}
+
+impl TileLayout {
+    fn row_length(&self, width: usize) -> usize {
+        (width * self.bits_per_pixel).div_ceil(8)
+    }
+
+    /// Bytes taken up by one pixel row of every tile in a row of tiles.
+    fn tile_row_length(&self) -> usize {
+        self.width / self.tile_width * self.row_length(self.tile_width)
+            + self.row_length(self.width % self.tile_width)
+    }
+
+    fn len(&self) -> usize {
+        self.tile_row_length() * self.height
+    }
+
+    fn scatter_row(&self, y: usize, scanline: &[u8], out: &mut [u8]) {
+        let tile_y = y / self.tile_height;
+        let row_in_tile = y % self.tile_height;
+        let tile_height = self
+            .tile_height
+            .min(self.height - tile_y * self.tile_height);
+
+        let tile_row_start = tile_y * self.tile_height * self.tile_row_length();
+        let full_tile_length = tile_height * self.row_length(self.tile_width);
 
-            scanline_out[scanline_offset] = decoded;
+        for (tile_x, x_start) in (0..self.width).step_by(self.tile_width).enumerate() {
+            let width = self.tile_width.min(self.width - x_start);
+            let start =
+                tile_row_start + tile_x * full_tile_length + row_in_tile * self.row_length(width);
+            let row = &mut out[start..start + self.row_length(width)];
+
+            copy_pixels(scanline, x_start, self.bits_per_pixel, row, width);
+        }
+    }
+}
+
+/// Copies `width` pixels starting at `x_start` in `scanline` to the start of `row`.
+fn copy_pixels(
+    scanline: &[u8],
+    x_start: usize,
+    bits_per_pixel: usize,
+    row: &mut [u8],
+    width: usize,
+) {
+    if bits_per_pixel < 8 {
+        for x in 0..width {
+            let sample = packed_sample(scanline, x_start + x, bits_per_pixel);
+            set_packed_sample(row, x, bits_per_pixel, sample);
+        }
+    } else {
+        let bytes_per_pixel = bits_per_pixel / 8;
+        row[..width * bytes_per_pixel].copy_from_slice(
+            &scanline[x_start * bytes_per_pixel..(x_start + width) * bytes_per_pixel],
+        );
+    }
+}
+
+/// Like [`decode_data`], but only outputs the `width` by `height` region with its top left corner at `x`, `y`.
+///
+/// Scanlines below the region are not reconstructed, and the ones above it are only reconstructed because the
+/// filters depend on them. Each row of the output starts on a byte boundary, like a scanline does. Interlaced
+/// images are not supported.
+#[allow(clippy::too_many_arguments)]
+pub fn decode_region<'a>(
+    header: &'a PngHeader,
+    chunks: impl Iterator<Item = &'a PngChunk>,
+    options: &DecodeOptions,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    region_out: &mut Vec<u8>,
+) -> Result<(), PngError> {
+    if header.interlace_method != InterlaceMethod::None {
+        return Err(PngError::UnsupportedInterlaceMethod(
+            header.interlace_method,
+        ));
+    }
+
+    let in_bounds = |start: u32, length: u32, limit: u32| {
+        start.checked_add(length).is_some_and(|end| end <= limit)
+    };
+
+    if !in_bounds(x, width, header.width) || !in_bounds(y, height, header.height) {
+        return Err(PngError::RegionOutOfBounds {
+            x,
+            y,
+            width,
+            height,
+        });
+    }
+
+    let data = image_data(chunks)?;
+    let (decompressed, _) = inflate_image_data(header, data.into_iter(), options, false)?;
+
+    let bits_per_pixel = header.bits_per_pixel();
+    let bytes_per_pixel = (bits_per_pixel / 8).max(1);
+    let scanline_length = header.scanline_length(header.width as usize);
+    let row_length = header.scanline_length(width as usize);
+
+    region_out.clear();
+    region_out.resize(row_length * height as usize, 0);
+
+    if row_length == 0 {
+        return Ok(());
+    }
+
+    let mut previous_scanline = vec![0u8; scanline_length];
+    let mut scanline = vec![0u8; scanline_length];
+
+    let rows = decompressed
+        .chunks_exact(scanline_length + 1)
+        .take((y + height) as usize);
+
+    for (scanline_y, scanline_in) in rows.enumerate() {
+        unfilter_scanline(
+            scanline_y,
+            scanline_in,
+            &previous_scanline,
+            &mut scanline,
+            bytes_per_pixel,
+            &options.filters,
+        )?;
+
+        if let Some(row) = scanline_y.checked_sub(y as usize) {
+            let row_out = &mut region_out[row * row_length..(row + 1) * row_length];
+            copy_pixels(
+                &scanline,
+                x as usize,
+                bits_per_pixel,
+                row_out,
+                width as usize,
+            );
         }
 
-        previous_scanline.copy_from_slice(scanline_out);
+        core::mem::swap(&mut previous_scanline, &mut scanline);
     }
 
     Ok(())
 }
+
+/// What [`decode_stream`] found out about the image data, besides the decoded pixels.
+pub(crate) struct StreamSummary {
+    /// Length of the inflated stream, before a truncated one is cut down to its complete scanlines.
+    pub inflated_bytes: usize,
+    /// The problems that the [`DecodeOptions`] allowed decoding to continue past.
+    pub warnings: Vec<DecodeWarning>,
+}
+
+/// Decodes a zlib stream split over several byte slices, such as the payloads of IDAT or fdAT chunks.
+pub(crate) fn decode_stream<'a>(
+    header: &PngHeader,
+    data: impl Iterator<Item = &'a [u8]>,
+    options: &DecodeOptions,
+    decoded_data_out: &mut Vec<u8>,
+) -> Result<StreamSummary, PngError> {
+    let allow_truncated = options.allow_truncated && !header.is_interlaced();
+    let (decompressed, summary) = inflate_image_data(header, data, options, allow_truncated)?;
+
+    // Filters operate on whole bytes, so sub-byte pixels use the preceding byte.
+    let bytes_per_pixel = (header.bits_per_pixel() / 8).max(1);
+    let scanline_length = header.scanline_length(header.width as usize);
+    // Truncated image data has fewer rows than the header says.
+    let rows = match header.interlace_method {
+        InterlaceMethod::None => decompressed.len() / (scanline_length + 1),
+        InterlaceMethod::Adam7 => header.height as usize,
+    };
+    // Checked against overflow while inflating.
+    let decoded_length = scanline_length * rows;
+
+    decoded_data_out.resize(decoded_length, 0);
+
+    match header.interlace_method {
+        InterlaceMethod::None => unfilter_scanlines(
+            &decompressed,
+            decoded_data_out,
+            scanline_length,
+            bytes_per_pixel,
+            &options.filters,
+        )?,
+        InterlaceMethod::Adam7 => decode_adam7(
+            header,
+            &decompressed,
+            decoded_data_out,
+            bytes_per_pixel,
+            &options.filters,
+        )?,
+    }
+
+    Ok(summary)
+}
+
+/// Inflates the image data and checks that it has the right length for `header`. With `allow_truncated`, a
+/// stream that ends early is cut down to its complete scanlines instead, which only makes sense for
+/// non-interlaced images.
+fn inflate_image_data<'a>(
+    header: &PngHeader,
+    data: impl Iterator<Item = &'a [u8]>,
+    options: &DecodeOptions,
+    allow_truncated: bool,
+) -> Result<(Vec<u8>, StreamSummary), PngError> {
+    let decoded_length = header.decoded_len().ok_or(image_too_large(header))?;
+
+    if let Some(limit) = options.max_decompressed_bytes {
+        if decoded_length > limit {
+            return Err(PngError::DecompressedTooLarge { limit });
+        }
+    }
+
+    let expected = filtered_length(header)?;
+    // Without a limit, one byte of surplus is still enough to reject the stream, so a small file can't
+    // inflate to any size it likes.
+    let wanted = match options.max_decompressed_bytes {
+        Some(_) => None,
+        None => Some(expected.saturating_add(1)),
+    };
+    let inflated = inflate_stream(data, options.max_decompressed_bytes, wanted)?;
+    let mut decompressed = inflated.data;
+    let mut warnings = Vec::new();
+
+    if inflated.truncated && !allow_truncated {
+        return Err(truncated_stream());
+    }
+
+    if !inflated.adler32_valid {
+        if !options.ignore_adler32 {
+            return Err(adler32_mismatch());
+        }
+
+        warnings.push(DecodeWarning::Adler32Mismatch);
+    }
+
+    if decompressed.is_empty() && decoded_length > 0 {
+        return Err(PngError::MissingImageData);
+    }
+
+    let inflated_bytes = decompressed.len();
+
+    if allow_truncated && decompressed.len() < expected {
+        let scanline_length = header.scanline_length(header.width as usize) + 1;
+        let rows = decompressed.len() / scanline_length;
+
+        decompressed.truncate(rows * scanline_length);
+        warnings.push(DecodeWarning::TruncatedImageData { rows: rows as u32 });
+    } else if decompressed.len() > expected {
+        return Err(PngError::SurplusImageData { expected });
+    } else if decompressed.len() != expected {
+        // A short stream would otherwise leave the last scanlines zeroed.
+        return Err(PngError::WrongDataLength {
+            expected,
+            actual: decompressed.len(),
+        });
+    }
+
+    Ok((
+        decompressed,
+        StreamSummary {
+            inflated_bytes,
+            warnings,
+        },
+    ))
+}
+
+pub(crate) fn image_too_large(header: &PngHeader) -> PngError {
+    PngError::ImageTooLarge {
+        width: header.width,
+        height: header.height,
+    }
+}
+
+/// Length of the decompressed stream for `header`: every scanline with its filter type byte, across all
+/// passes for interlaced images.
+pub(crate) fn filtered_length(header: &PngHeader) -> Result<usize, PngError> {
+    let length = |width: usize, height: usize| {
+        if width == 0 {
+            Some(0)
+        } else {
+            width
+                .checked_mul(header.bits_per_pixel())?
+                .div_ceil(8)
+                .checked_add(1)?
+                .checked_mul(height)
+        }
+    };
+
+    match header.interlace_method {
+        InterlaceMethod::None => length(header.width as usize, header.height as usize),
+        InterlaceMethod::Adam7 => PASSES.iter().try_fold(0usize, |total, pass| {
+            total.checked_add(length(
+                pass.width(header.width),
+                pass.height(header.height),
+            )?)
+        }),
+    }
+    .ok_or(image_too_large(header))
+}
+
+/// Reverses the per-scanline filters of `input` (scanlines prefixed with their filter type) into `output`.
+fn unfilter_scanlines(
+    input: &[u8],
+    output: &mut [u8],
+    scanline_length: usize,
+    bytes_per_pixel: usize,
+    filters: &FilterTable,
+) -> Result<(), PngError> {
+    if scanline_length == 0 {
+        return Ok(());
+    }
+
+    let scanline_length_with_filter = scanline_length + 1;
+
+    let input_chunks = input.chunks_exact(scanline_length_with_filter);
+    let rows = output.len() / scanline_length;
+
+    // Images written without filtering are common enough that skipping the per-byte loop is worth a pass over
+    // the filter type bytes.
+    if filters.standard_none
+        && input_chunks
+            .clone()
+            .take(rows)
+            .all(|scanline| scanline[0] == 0)
+    {
+        for (scanline_in, scanline_out) in
+            input_chunks.zip(output.chunks_exact_mut(scanline_length))
+        {
+            scanline_out.copy_from_slice(&scanline_in[1..]);
+        }
+
+        return Ok(());
+    }
+
+    // The spec defines the row above the first scanline as all zeros. Later rows read the previous row
+    // straight from `output`, so the first row needs this separate buffer rather than a stale or missing one.
+    let first_previous_scanline = vec![0u8; scanline_length];
+
+    for (y, scanline_in) in input_chunks.take(rows).enumerate() {
+        let (done, rest) = output.split_at_mut(y * scanline_length);
+        let scanline_out = &mut rest[..scanline_length];
+
+        let previous_scanline = if y == 0 {
+            first_previous_scanline.as_slice()
+        } else {
+            &done[(y - 1) * scanline_length..]
+        };
+
+        unfilter_scanline(
+            y,
+            scanline_in,
+            previous_scanline,
+            scanline_out,
+            bytes_per_pixel,
+            filters,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Reverses the filter of scanline number `y`, given with its filter type byte.
+fn unfilter_scanline(
+    y: usize,
+    scanline_in: &[u8],
+    previous_scanline: &[u8],
+    scanline_out: &mut [u8],
+    bytes_per_pixel: usize,
+    filters: &FilterTable,
+) -> Result<(), PngError> {
+    let (&filter_type, scanline_in) = scanline_in.split_first().unwrap();
+
+    let filter = filters
+        .get(filter_type)
+        .ok_or(PngError::InvalidFilterType {
+            pass: None,
+            scanline: y,
+            value: filter_type,
+        })?;
+
+    for (scanline_offset, byte) in scanline_in.iter().copied().enumerate() {
+        let previous = if scanline_offset >= bytes_per_pixel {
+            scanline_out[scanline_offset - bytes_per_pixel]
+        } else {
+            0
+        };
+
+        let decoded = filter(
+            byte,
+            previous,
+            scanline_offset,
+            previous_scanline,
+            bytes_per_pixel,
+        );
+
+        scanline_out[scanline_offset] = decoded;
+    }
+
+    Ok(())
+}
+
+/// Reads the `index`th sample of `bits` bits from a row of packed samples, most significant bits first.
+pub(crate) fn packed_sample(row: &[u8], index: usize, bits: usize) -> u8 {
+    let bit_offset = index * bits;
+    let shift = 8 - bits - bit_offset % 8;
+    let mask = (1u8 << bits) - 1;
+
+    (row[bit_offset / 8] >> shift) & mask
+}
+
+pub(crate) fn set_packed_sample(row: &mut [u8], index: usize, bits: usize, sample: u8) {
+    let bit_offset = index * bits;
+    let shift = 8 - bits - bit_offset % 8;
+    let mask = ((1u8 << bits) - 1) << shift;
+
+    let byte = &mut row[bit_offset / 8];
+    *byte = (*byte & !mask) | ((sample << shift) & mask);
+}
+
+/// Reconstructs the seven passes independently, then deinterlaces them into the full image. With the
+/// `rayon` feature both steps run in parallel.
+fn decode_adam7(
+    header: &PngHeader,
+    decompressed: &[u8],
+    decoded_data_out: &mut [u8],
+    bytes_per_pixel: usize,
+    filters: &FilterTable,
+) -> Result<(), PngError> {
+    let mut pass_inputs = Vec::with_capacity(PASSES.len());
+    let mut offset = 0;
+
+    for pass in PASSES {
+        let width = pass.width(header.width);
+        let height = pass.height(header.height);
+
+        let length = if width == 0 {
+            0
+        } else {
+            (header.scanline_length(width) + 1) * height
+        };
+
+        let start = offset.min(decompressed.len());
+        let end = (offset + length).min(decompressed.len());
+        offset += length;
+
+        pass_inputs.push((width, height, &decompressed[start..end]));
+    }
+
+    let reconstruct = |(i, &(width, height, input)): (usize, &(usize, usize, &[u8]))| {
+        let scanline_length = header.scanline_length(width);
+        let mut data = vec![0u8; scanline_length * height];
+
+        unfilter_scanlines(input, &mut data, scanline_length, bytes_per_pixel, filters).map_err(
+            |err| match err {
+                PngError::InvalidFilterType {
+                    scanline, value, ..
+                } => PngError::InvalidFilterType {
+                    pass: Some(i as u8 + 1),
+                    scanline,
+                    value,
+                },
+                err => err,
+            },
+        )?;
+
+        Ok(data)
+    };
+
+    #[cfg(feature = "rayon")]
+    let passes = pass_inputs
+        .par_iter()
+        .enumerate()
+        .map(reconstruct)
+        .collect::<Result<Vec<_>, PngError>>()?;
+    #[cfg(not(feature = "rayon"))]
+    let passes = pass_inputs
+        .iter()
+        .enumerate()
+        .map(reconstruct)
+        .collect::<Result<Vec<_>, PngError>>()?;
+
+    let passes: [&[u8]; 7] = core::array::from_fn(|i| passes[i].as_slice());
+    deinterlace(header, &passes, decoded_data_out)
+        .expect("pass and output buffers are sized from the header");
+
+    Ok(())
+}
+
+/// Scatters the pixels of the seven reduced Adam7 images into the full image.
+///
+/// Each buffer in `passes` holds the reconstructed scanlines of one pass, without their filter type bytes, and
+/// is empty if the pass is. `out` receives the scanlines of the full image, as returned by
+/// [`PngFile::decode_data`](crate::png_parser::PngFile::decode_data).
+pub fn deinterlace(
+    header: &PngHeader,
+    passes: &[&[u8]; 7],
+    out: &mut [u8],
+) -> Result<(), PngError> {
+    let pass_scanline_lengths: [usize; 7] =
+        core::array::from_fn(|i| header.scanline_length(PASSES[i].width(header.width)));
+
+    for ((pass, data), &scanline_length) in PASSES.iter().zip(passes).zip(&pass_scanline_lengths) {
+        let expected = scanline_length * pass.height(header.height);
+
+        if data.len() != expected {
+            return Err(PngError::WrongDataLength {
+                expected,
+                actual: data.len(),
+            });
+        }
+    }
+
+    let bits_per_pixel = header.bits_per_pixel();
+    let bytes_per_pixel = bits_per_pixel / 8;
+    let scanline_length = header.scanline_length(header.width as usize);
+    let expected = scanline_length * header.height as usize;
+
+    if out.len() != expected {
+        return Err(PngError::WrongDataLength {
+            expected,
+            actual: out.len(),
+        });
+    }
+
+    if scanline_length == 0 {
+        return Ok(());
+    }
+
+    let scatter_row = |(y, row): (usize, &mut [u8])| {
+        for ((pass, data), &pass_scanline_length) in
+            PASSES.iter().zip(passes).zip(&pass_scanline_lengths)
+        {
+            let pass_row = match pass.row_for(y) {
+                Some(pass_row) => pass_row,
+                None => continue,
+            };
+
+            let pass_width = pass.width(header.width);
+            let pass_scanline =
+                &data[pass_row * pass_scanline_length..(pass_row + 1) * pass_scanline_length];
+
+            if bits_per_pixel < 8 {
+                for column in 0..pass_width {
+                    let x = pass.x_start + column * pass.x_step;
+                    let sample = packed_sample(pass_scanline, column, bits_per_pixel);
+                    set_packed_sample(row, x, bits_per_pixel, sample);
+                }
+            } else {
+                for (column, pixel) in pass_scanline.chunks_exact(bytes_per_pixel).enumerate() {
+                    let x = pass.x_start + column * pass.x_step;
+                    row[x * bytes_per_pixel..(x + 1) * bytes_per_pixel].copy_from_slice(pixel);
+                }
+            }
+        }
+    };
+
+    #[cfg(feature = "rayon")]
+    out.par_chunks_exact_mut(scanline_length)
+        .enumerate()
+        .for_each(scatter_row);
+    #[cfg(not(feature = "rayon"))]
+    out.chunks_exact_mut(scanline_length)
+        .enumerate()
+        .for_each(scatter_row);
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{
+        encoder::PngEncoder,
+        png_parser::{BitDepth, ColourType, PngFile},
+        test_util::{ihdr, parse, pattern, zlib},
+    };
+
+    #[test]
+    fn rejects_non_image_data_chunks() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"IDAT", &zlib(&[0, 0])),
+            (b"IEND", &[]),
+        ]);
+        let header = png.try_parse_header().unwrap();
+
+        assert!(matches!(
+            decode_data(
+                &header,
+                png.chunks.iter(),
+                &DecodeOptions::default(),
+                &mut Vec::new()
+            ),
+            Err(PngError::NonImageDataChunk(ChunkType::IHDR))
+        ));
+    }
+
+    #[test]
+    fn decodes_adam7() {
+        let formats = [
+            (ColourType::Truecolour, BitDepth::B8, 3),
+            (ColourType::GreyscaleWithAlpha, BitDepth::B16, 4),
+        ];
+
+        for (colour_type, bit_depth, bytes_per_pixel) in formats {
+            for (width, height) in [(1, 1), (3, 3), (8, 8), (37, 23)] {
+                let data = pattern(width as usize * height as usize * bytes_per_pixel);
+
+                let mut encoder = PngEncoder::new(width, height, colour_type, bit_depth).unwrap();
+                encoder.set_interlace_method(InterlaceMethod::Adam7);
+                let encoded = encoder.encode(&data).unwrap();
+
+                // Cross-check the encoder against an independent decoder.
+                let reference = image::load_from_memory(&encoded).unwrap();
+                if bit_depth == BitDepth::B8 {
+                    assert_eq!(reference.to_rgb8().into_raw(), data);
+                }
+
+                let png = PngFile::from_reader(&mut encoded.as_slice()).unwrap();
+                assert_eq!(
+                    png.decode_data().unwrap(),
+                    data,
+                    "{:?} {}x{}",
+                    colour_type,
+                    width,
+                    height
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_to_writer() {
+        for path in ["./FL.png", "./code.png", "./indexed-4bit-interlaced.png"] {
+            let png = PngFile::from_bytes(&std::fs::read(path).unwrap()).unwrap();
+            let mut out = Vec::new();
+            png.decode_to_writer(&mut out).unwrap();
+
+            assert_eq!(out, png.decode_data().unwrap(), "{}", path);
+        }
+
+        // Rows are written as they're reconstructed, so a short stream fails only after the complete ones.
+        let png = parse(&[
+            (b"IHDR", &ihdr(2, 3, 8, 0)),
+            (b"IDAT", &zlib(&[0, 1, 2, 2, 1, 1, 0])),
+            (b"IEND", &[]),
+        ]);
+        let mut out = Vec::new();
+        assert!(matches!(
+            png.decode_to_writer(&mut out),
+            Err(PngError::WrongDataLength {
+                expected: 9,
+                actual: 7
+            })
+        ));
+        assert_eq!(out, [1, 2, 2, 3]);
+
+        let surplus = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"IDAT", &zlib(&[0, 1, 0, 2])),
+            (b"IEND", &[]),
+        ]);
+        assert!(matches!(
+            surplus.decode_to_writer(&mut Vec::new()),
+            Err(PngError::SurplusImageData { expected: 2 })
+        ));
+
+        let mut full = [0u8; 3];
+        assert!(matches!(
+            png.decode_to_writer(&mut full.as_mut_slice()),
+            Err(PngError::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn decodes_into_tiles() {
+        let cases = [
+            (ColourType::Truecolour, BitDepth::B8, InterlaceMethod::None),
+            (
+                ColourType::GreyscaleWithAlpha,
+                BitDepth::B16,
+                InterlaceMethod::Adam7,
+            ),
+            (ColourType::Greyscale, BitDepth::B2, InterlaceMethod::None),
+            (ColourType::Greyscale, BitDepth::B1, InterlaceMethod::Adam7),
+        ];
+
+        for (colour_type, bit_depth, interlace_method) in cases {
+            let (width, height) = (13, 7);
+            let bits = bit_depth.bits() as usize * colour_type.channel_count();
+            let scanline_length = (width * bits).div_ceil(8);
+            let data = pattern(scanline_length * height);
+
+            let mut encoder =
+                PngEncoder::new(width as u32, height as u32, colour_type, bit_depth).unwrap();
+            encoder.set_interlace_method(interlace_method);
+            let png = PngFile::from_bytes(&encoder.encode(&data).unwrap()).unwrap();
+            let linear = png.decode_data().unwrap();
+
+            for (tile_width, tile_height) in
+                [(4, 3), (1, 1), (13, 7), (64, 64), (u32::MAX as usize, 5)]
+            {
+                let mut expected = Vec::new();
+
+                for tile_y in (0..height).step_by(tile_height) {
+                    for tile_x in (0..width).step_by(tile_width) {
+                        let tile_width = tile_width.min(width - tile_x);
+
+                        for y in tile_y..(tile_y + tile_height).min(height) {
+                            let scanline = &linear[y * scanline_length..(y + 1) * scanline_length];
+                            let mut row = vec![0u8; (tile_width * bits).div_ceil(8)];
+
+                            for x in 0..tile_width * bits {
+                                let bit = packed_sample(scanline, tile_x * bits + x, 1);
+                                set_packed_sample(&mut row, x, 1, bit);
+                            }
+
+                            expected.extend_from_slice(&row);
+                        }
+                    }
+                }
+
+                let mut tiled = Vec::new();
+                png.decode_tiled(tile_width as u32, tile_height as u32, &mut tiled)
+                    .unwrap();
+                assert_eq!(
+                    tiled, expected,
+                    "{:?} {:?} {:?} {}x{}",
+                    colour_type, bit_depth, interlace_method, tile_width, tile_height
+                );
+            }
+        }
+
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"IDAT", &zlib(&[0, 0])),
+            (b"IEND", &[]),
+        ]);
+        assert!(matches!(
+            png.decode_tiled(0, 8, &mut Vec::new()),
+            Err(PngError::InvalidTileSize {
+                width: 0,
+                height: 8
+            })
+        ));
+    }
+
+    #[test]
+    fn decodes_first_rows() {
+        for path in ["./code.png", "./indexed-4bit-interlaced.png"] {
+            let png = PngFile::from_bytes(&std::fs::read(path).unwrap()).unwrap();
+            let header = png.try_parse_header().unwrap();
+            let scanline_length = header.scanline_length(header.width as usize);
+            let full = png.decode_data().unwrap();
+
+            for rows in [0, 1, 7, header.height, header.height + 1] {
+                let mut out = vec![1; 3];
+                png.decode_first_rows(rows, &mut out).unwrap();
+
+                let expected = rows.min(header.height) as usize * scanline_length;
+                assert_eq!(out, full[..expected], "{}, {} rows", path, rows);
+            }
+        }
+
+        // Whatever comes after the requested rows is never inflated, even a corrupt or missing stream end.
+        let stream = zlib(&[0, 1, 2, 2, 1, 1, 0, 5, 6]);
+        let png = parse(&[
+            (b"IHDR", &ihdr(2, 4, 8, 0)),
+            (b"IDAT", &stream[..stream.len() - 4]),
+            (b"IEND", &[]),
+        ]);
+        let mut out = Vec::new();
+        png.decode_first_rows(2, &mut out).unwrap();
+        assert_eq!(out, [1, 2, 2, 3]);
+
+        let short = parse(&[
+            (b"IHDR", &ihdr(2, 4, 8, 0)),
+            (b"IDAT", &stream),
+            (b"IEND", &[]),
+        ]);
+        assert!(matches!(
+            short.decode_first_rows(4, &mut out),
+            Err(PngError::WrongDataLength {
+                expected: 12,
+                actual: 9
+            })
+        ));
+    }
+
+    #[test]
+    fn decodes_first_rows_without_trusting_the_header() {
+        // A 2^28 pixel wide RGBA16 row would take 2 GiB, but the stream behind it only holds a few bytes.
+        let png = parse(&[
+            (b"IHDR", &ihdr(1 << 28, 1, 16, 6)),
+            (b"IDAT", &zlib(&[0; 10])),
+            (b"IEND", &[]),
+        ]);
+        let header = png.try_parse_header().unwrap();
+        let chunks = png.image_data_chunks().unwrap();
+        let mut out = Vec::new();
+
+        let mut options = DecodeOptions::new();
+        options.set_max_decompressed_bytes(1024);
+        assert!(matches!(
+            decode_first_rows(&header, chunks.iter(), &options, 1, &mut out),
+            Err(PngError::DecompressedTooLarge { limit: 1024 })
+        ));
+
+        assert!(matches!(
+            png.decode_first_rows(1, &mut out),
+            Err(PngError::WrongDataLength { actual: 10, .. })
+        ));
+        assert!(out.capacity() < 1024 * 1024);
+
+        let missing = parse(&[(b"IHDR", &ihdr(2, 2, 8, 0)), (b"IEND", &[])]);
+        assert!(matches!(
+            missing.decode_first_rows(1, &mut out),
+            Err(PngError::MissingImageData)
+        ));
+    }
+
+    #[test]
+    fn decodes_region() {
+        for (colour_type, bit_depth) in [
+            (ColourType::TruecolourWithAlpha, BitDepth::B16),
+            (ColourType::Truecolour, BitDepth::B8),
+            (ColourType::IndexedColour, BitDepth::B4),
+            (ColourType::Greyscale, BitDepth::B1),
+        ] {
+            let (width, height) = (11, 9);
+            let bits = bit_depth.bits() as usize * colour_type.channel_count();
+            let scanline_length = (width * bits).div_ceil(8);
+            let data = pattern(scanline_length * height);
+
+            let mut encoder =
+                PngEncoder::new(width as u32, height as u32, colour_type, bit_depth).unwrap();
+            if colour_type == ColourType::IndexedColour {
+                encoder.set_palette(&[[0; 3]; 16]).unwrap();
+            }
+            let png = PngFile::from_bytes(&encoder.encode(&data).unwrap()).unwrap();
+
+            for (x, y, region_width, region_height) in [
+                (0, 0, 11, 9),
+                (3, 2, 5, 4),
+                (10, 8, 1, 1),
+                (4, 4, 0, 3),
+                (1, 7, 3, 0),
+            ] {
+                let row_length = (region_width * bits).div_ceil(8);
+                let mut expected = vec![0u8; row_length * region_height];
+
+                for (row, y) in (y..y + region_height).enumerate() {
+                    let scanline = &data[y * scanline_length..];
+
+                    for bit in 0..region_width * bits {
+                        let sample = packed_sample(scanline, x * bits + bit, 1);
+                        set_packed_sample(&mut expected[row * row_length..], bit, 1, sample);
+                    }
+                }
+
+                let mut region = Vec::new();
+                png.decode_region(
+                    x as u32,
+                    y as u32,
+                    region_width as u32,
+                    region_height as u32,
+                    &mut region,
+                )
+                .unwrap();
+                assert_eq!(region, expected, "{:?} {:?}", colour_type, bit_depth);
+            }
+
+            assert!(matches!(
+                png.decode_region(8, 0, 4, 1, &mut Vec::new()),
+                Err(PngError::RegionOutOfBounds { .. })
+            ));
+            assert!(matches!(
+                png.decode_region(0, 1, 1, u32::MAX, &mut Vec::new()),
+                Err(PngError::RegionOutOfBounds { .. })
+            ));
+        }
+
+        let mut encoder = PngEncoder::new(4, 4, ColourType::Greyscale, BitDepth::B8).unwrap();
+        encoder.set_interlace_method(InterlaceMethod::Adam7);
+        let png = PngFile::from_bytes(&encoder.encode(&[0; 16]).unwrap()).unwrap();
+        assert!(matches!(
+            png.decode_region(0, 0, 2, 2, &mut Vec::new()),
+            Err(PngError::UnsupportedInterlaceMethod(InterlaceMethod::Adam7))
+        ));
+    }
+
+    #[test]
+    fn decodes_sub_byte_adam7() {
+        for bit_depth in [BitDepth::B1, BitDepth::B2, BitDepth::B4] {
+            let bits = bit_depth.bits() as usize;
+
+            for (width, height) in [(1, 1), (3, 3), (13, 7), (37, 23)] {
+                let scanline_length = (width as usize * bits).div_ceil(8);
+                let samples = pattern(width as usize * height as usize);
+
+                let mut data = vec![0u8; scanline_length * height as usize];
+                for (i, sample) in samples.iter().enumerate() {
+                    let (y, x) = (i / width as usize, i % width as usize);
+                    let row = &mut data[y * scanline_length..(y + 1) * scanline_length];
+                    set_packed_sample(row, x, bits, sample >> (8 - bits));
+                }
+
+                let mut encoder =
+                    PngEncoder::new(width, height, ColourType::Greyscale, bit_depth).unwrap();
+                encoder.set_interlace_method(InterlaceMethod::Adam7);
+                let encoded = encoder.encode(&data).unwrap();
+
+                let png = PngFile::from_bytes(&encoded).unwrap();
+                assert_eq!(
+                    png.decode_data().unwrap(),
+                    data,
+                    "{:?} {}x{}",
+                    bit_depth,
+                    width,
+                    height
+                );
+
+                let reference = image::load_from_memory(&encoded).unwrap();
+                assert_eq!(png.to_rgba8().unwrap(), reference.to_rgba8().into_raw());
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_missing_image_data() {
+        let no_idat = parse(&[(b"IHDR", &ihdr(2, 2, 8, 0)), (b"IEND", &[])]);
+        assert!(matches!(
+            no_idat.decode_data(),
+            Err(PngError::MissingImageData)
+        ));
+
+        let empty_stream = parse(&[
+            (b"IHDR", &ihdr(2, 2, 8, 0)),
+            (b"IDAT", &zlib(&[])),
+            (b"IEND", &[]),
+        ]);
+        assert!(matches!(
+            empty_stream.decode_data(),
+            Err(PngError::MissingImageData)
+        ));
+    }
+
+    #[test]
+    fn rejects_surplus_image_data() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(2, 1, 8, 0)),
+            (b"IDAT", &zlib(&[0, 1, 2, 0, 3, 4])),
+            (b"IEND", &[]),
+        ]);
+
+        assert!(matches!(
+            png.decode_data(),
+            Err(PngError::SurplusImageData { expected: 3 })
+        ));
+
+        // The same error whether or not inflating was limited.
+        let mut options = DecodeOptions::new();
+        options.max_decompressed_bytes = Some(1024);
+        assert!(matches!(
+            png.decode_with(&options),
+            Err(PngError::SurplusImageData { expected: 3 })
+        ));
+    }
+
+    #[test]
+    fn rejects_images_too_large_for_memory() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(0x7fff_ffff, 0x7fff_ffff, 16, 6)),
+            (b"IDAT", &zlib(&[0, 0])),
+            (b"IEND", &[]),
+        ]);
+
+        assert!(matches!(
+            png.decode_data(),
+            Err(PngError::ImageTooLarge {
+                width: 0x7fff_ffff,
+                height: 0x7fff_ffff
+            })
+        ));
+        assert!(matches!(
+            png.decode_to_writer(&mut Vec::new()),
+            Err(PngError::ImageTooLarge { .. })
+        ));
+
+        for interlace_method in [InterlaceMethod::None, InterlaceMethod::Adam7] {
+            let header = PngHeader::new(
+                0x7fff_ffff,
+                0x7fff_ffff,
+                BitDepth::B16,
+                ColourType::TruecolourWithAlpha,
+                interlace_method,
+            )
+            .unwrap();
+            assert!(matches!(
+                filtered_length(&header),
+                Err(PngError::ImageTooLarge { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn optionally_decodes_truncated_image_data() {
+        let (width, height) = (16, 32);
+        let image = pattern(width * height);
+        let stream = zlib(
+            &image
+                .chunks_exact(width)
+                .flat_map(|row| [&[2][..], row].concat())
+                .collect::<Vec<_>>(),
+        );
+
+        let mut options = DecodeOptions::new();
+        options.set_allow_truncated(true);
+
+        // Cutting the zlib stream short, and a complete stream with fewer rows than the header.
+        let short_stream = zlib(&[0, 1, 2, 3, 4][..]);
+        for (image_height, idat) in [
+            (height, &stream[..stream.len() / 2]),
+            (3, short_stream.as_slice()),
+        ] {
+            let png = parse(&[
+                (b"IHDR", &ihdr(width as u32, image_height as u32, 8, 0)),
+                (b"IDAT", idat),
+                (b"IEND", &[]),
+            ]);
+            assert!(png.decode_data().is_err());
+
+            let decoded = png.decode_image_with(&options).unwrap();
+            let rows = decoded.height();
+            assert!(rows < image_height as u32);
+            assert_eq!(
+                decoded.warnings(),
+                [DecodeWarning::TruncatedImageData { rows }]
+            );
+            assert_eq!(png.decode_with(&options).unwrap(), decoded.data());
+            assert_eq!(
+                png.to_rgba8_with(&options).unwrap().len(),
+                width * rows as usize * 4
+            );
+        }
+
+        let png = parse(&[
+            (b"IHDR", &ihdr(width as u32, height as u32, 8, 0)),
+            (b"IDAT", &stream[..stream.len() / 2]),
+            (b"IEND", &[]),
+        ]);
+        let decoded = png.decode_image_with(&options).unwrap();
+        assert!(decoded.height() > 0);
+        let mut full = Vec::new();
+        decode_stream(
+            &png.try_parse_header().unwrap(),
+            core::iter::once(stream.as_slice()),
+            &DecodeOptions::default(),
+            &mut full,
+        )
+        .unwrap();
+        assert_eq!(decoded.data(), &full[..decoded.data().len()]);
+
+        // Surplus data and interlaced images are still errors.
+        let surplus = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"IDAT", &zlib(&[0, 1, 0, 2])),
+            (b"IEND", &[]),
+        ]);
+        assert!(surplus.decode_with(&options).is_err());
+
+        let interlaced = parse(&[
+            (
+                b"IHDR",
+                &[ihdr(3, 3, 8, 0)[..12].to_vec(), vec![1]].concat(),
+            ),
+            (b"IDAT", &zlib(&[0, 1, 0, 2])),
+            (b"IEND", &[]),
+        ]);
+        assert!(interlaced.decode_with(&options).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_image_data() {
+        let partial_scanline = parse(&[
+            (b"IHDR", &ihdr(2, 2, 8, 0)),
+            (b"IDAT", &zlib(&[0, 1, 2, 0, 3])),
+            (b"IEND", &[]),
+        ]);
+        assert!(matches!(
+            partial_scanline.decode_data(),
+            Err(PngError::WrongDataLength {
+                expected: 6,
+                actual: 5
+            })
+        ));
+
+        let interlaced = parse(&[
+            (
+                b"IHDR",
+                &[ihdr(3, 3, 8, 0)[..12].to_vec(), vec![1]].concat(),
+            ),
+            (b"IDAT", &zlib(&[0, 1, 0, 2])),
+            (b"IEND", &[]),
+        ]);
+        assert!(matches!(
+            interlaced.decode_data(),
+            Err(PngError::WrongDataLength {
+                expected: 15,
+                actual: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn reconstructs_single_pixel_wide_images_with_every_filter() {
+        // A single pixel has no left neighbour, so Sub, Average and Paeth only ever see a = c = 0.
+        fn filter_row(
+            filter_type: u8,
+            row: &[u8],
+            previous: &[u8],
+            bytes_per_pixel: usize,
+        ) -> Vec<u8> {
+            let mut filtered = vec![filter_type];
+
+            for (i, &x) in row.iter().enumerate() {
+                let a = if i >= bytes_per_pixel {
+                    row[i - bytes_per_pixel]
+                } else {
+                    0
+                };
+                let b = previous[i];
+                let c = if i >= bytes_per_pixel {
+                    previous[i - bytes_per_pixel]
+                } else {
+                    0
+                };
+
+                let predictor = match filter_type {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    _ => paeth_predictor(a, b, c),
+                };
+                filtered.push(x.wrapping_sub(predictor));
+            }
+
+            filtered
+        }
+
+        let formats = [
+            (0, [1, 2, 4, 8, 16].as_slice()),
+            (2, &[8, 16]),
+            (3, &[1, 2, 4, 8]),
+            (4, &[8, 16]),
+            (6, &[8, 16]),
+        ];
+
+        for (colour_type, bit_depths) in formats {
+            for &bit_depth in bit_depths {
+                let ihdr = ihdr(1, 4, bit_depth, colour_type);
+                let header = parse(&[(b"IHDR", &ihdr), (b"IEND", &[])])
+                    .try_parse_header()
+                    .unwrap();
+                let scanline_length = header.scanline_length(1);
+                let bytes_per_pixel = (header.bits_per_pixel() / 8).max(1);
+
+                // Sub-byte pixels only use the most significant bits of their byte.
+                let mask = match bit_depth {
+                    1 | 2 | 4 => !(0xff >> bit_depth),
+                    _ => 0xff,
+                };
+                let raw = pattern(scanline_length * 4)
+                    .into_iter()
+                    .map(|byte| byte & mask)
+                    .collect::<Vec<_>>();
+
+                for filter_type in 0..5 {
+                    let mut previous = vec![0; scanline_length];
+                    let mut stream = Vec::new();
+                    for row in raw.chunks_exact(scanline_length) {
+                        stream.extend(filter_row(filter_type, row, &previous, bytes_per_pixel));
+                        previous = row.to_vec();
+                    }
+
+                    let png = parse(&[(b"IHDR", &ihdr), (b"IDAT", &zlib(&stream)), (b"IEND", &[])]);
+                    assert_eq!(
+                        png.decode_data().unwrap(),
+                        raw,
+                        "colour type {}, bit depth {}, filter type {}",
+                        colour_type,
+                        bit_depth,
+                        filter_type
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn first_row_filters_see_a_zero_previous_row() {
+        // Up is the identity on the first row, Average halves the left neighbour only, and Paeth degrades to
+        // Sub.
+        for (filter_type, row, expected) in [
+            (2, [10, 20, 30], [10, 20, 30]),
+            (3, [10, 20, 30], [10, 25, 42]),
+            (4, [10, 20, 30], [10, 30, 60]),
+        ] {
+            let png = parse(&[
+                (b"IHDR", &ihdr(3, 2, 8, 0)),
+                (
+                    b"IDAT",
+                    &zlib(&[&[filter_type][..], &row, &[2, 1, 1, 1]].concat()),
+                ),
+                (b"IEND", &[]),
+            ]);
+
+            let decoded = png.decode_data().unwrap();
+            assert_eq!(decoded[..3], expected, "filter {}", filter_type);
+            // The second row reads the first one back through Up.
+            assert_eq!(
+                decoded[3..],
+                expected.map(|value| value + 1),
+                "filter {}",
+                filter_type
+            );
+        }
+    }
+
+    #[test]
+    fn average_filter_matches_reference_at_image_edges() {
+        // Average-filters `raw` straight from the spec's definition, where bytes left of the row and the row
+        // above the image are zero.
+        fn average_filter(raw: &[u8], stride: usize, bytes_per_pixel: usize) -> Vec<u8> {
+            let mut filtered = Vec::new();
+
+            for (y, row) in raw.chunks_exact(stride).enumerate() {
+                filtered.push(3);
+
+                for (x, &byte) in row.iter().enumerate() {
+                    let left = if x >= bytes_per_pixel {
+                        row[x - bytes_per_pixel]
+                    } else {
+                        0
+                    };
+                    let above = if y > 0 { raw[(y - 1) * stride + x] } else { 0 };
+                    let mean = ((left as u16 + above as u16) / 2) as u8;
+                    filtered.push(byte.wrapping_sub(mean));
+                }
+            }
+
+            filtered
+        }
+
+        // 8-bit RGB and 16-bit greyscale with alpha, so the first pixel spans several bytes.
+        for (colour_type, bit_depth, bytes_per_pixel) in [(2, 8, 3), (4, 16, 4)] {
+            let (width, height) = (4, 3);
+            let stride = width * bytes_per_pixel;
+            let raw = pattern(stride * height);
+            let filtered = average_filter(&raw, stride, bytes_per_pixel);
+
+            // The top-left pixel has neither neighbour, so it's stored unchanged.
+            assert_eq!(filtered[1..1 + bytes_per_pixel], raw[..bytes_per_pixel]);
+
+            let png = parse(&[
+                (
+                    b"IHDR",
+                    &ihdr(width as u32, height as u32, bit_depth, colour_type),
+                ),
+                (b"IDAT", &zlib(&filtered)),
+                (b"IEND", &[]),
+            ]);
+
+            assert_eq!(
+                png.decode_data().unwrap(),
+                raw,
+                "colour type {}",
+                colour_type
+            );
+        }
+    }
+
+    #[test]
+    fn paeth_predictor_breaks_ties_in_spec_order() {
+        for ((a, b, c), expected) in [
+            // p = 10 is equally far from all three, so a wins.
+            ((10, 10, 10), 10),
+            // p = 20: pa = 10, pb = 10, pc = 0, so c is nearest.
+            ((10, 30, 20), 20),
+            // p = 15: pa = 5, pb = 5, pc = 0.
+            ((20, 10, 15), 15),
+            // p = 2: pa = 2, pb = 4, pc = 2. a ties with c and wins.
+            ((0, 6, 4), 0),
+            // p = 2: pa = 4, pb = 2, pc = 2. b ties with c and wins.
+            ((6, 0, 4), 0),
+            // p = -100: pa = 100, pb = 100, pc = 200. a ties with b and wins.
+            ((0, 0, 100), 0),
+            // p = 200: pa = 100, pb = 100, pc = 200.
+            ((100, 100, 0), 100),
+            // p = 255 + 255 - 0 overflows a byte.
+            ((255, 255, 0), 255),
+            ((0, 0, 255), 0),
+        ] {
+            assert_eq!(
+                paeth_predictor(a, b, c),
+                expected,
+                "a = {}, b = {}, c = {}",
+                a,
+                b,
+                c
+            );
+        }
+    }
+
+    #[test]
+    fn paeth_predictor_matches_spec_pseudocode() {
+        // Transcribed from the PNG specification, section 9.4.
+        fn reference(a: u8, b: u8, c: u8) -> u8 {
+            let p = a as i16 + b as i16 - c as i16;
+            let pa = (p - a as i16).abs();
+            let pb = (p - b as i16).abs();
+            let pc = (p - c as i16).abs();
+
+            if pa <= pb && pa <= pc {
+                a
+            } else if pb <= pc {
+                b
+            } else {
+                c
+            }
+        }
+
+        // Every third value keeps this fast in debug builds while still hitting plenty of ties.
+        for a in (0..=255).step_by(3) {
+            for b in (0..=255).step_by(3) {
+                for c in (0..=255).step_by(3) {
+                    assert_eq!(paeth_predictor(a, b, c), reference(a, b, c));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn average_filter_wraps_and_rounds_down() {
+        // (255 + 255) / 2 = 255 would overflow an 8-bit sum; 200 + 255 wraps to 199.
+        assert_eq!(filter_average(200, 255, 0, &[255], 1), 199);
+        // (1 + 2) / 2 rounds down to 1.
+        assert_eq!(filter_average(10, 1, 0, &[2], 1), 11);
+        // (255 + 0) / 2 = 127, and 129 + 127 wraps to 0.
+        assert_eq!(filter_average(129, 255, 0, &[0], 1), 0);
+        assert_eq!(filter_average(0, 0, 0, &[0], 1), 0);
+    }
+
+    #[test]
+    fn uses_custom_filters() {
+        fn filter_xor(x: u8, previous: u8, _: usize, _: &[u8], _: usize) -> u8 {
+            x ^ previous
+        }
+
+        let png = parse(&[
+            (b"IHDR", &ihdr(3, 1, 8, 0)),
+            (b"IDAT", &zlib(&[42, 1, 3, 5])),
+            (b"IEND", &[]),
+        ]);
+
+        let mut options = DecodeOptions::default();
+        options.filters.set(42, filter_xor);
+
+        assert_eq!(png.decode_with(&options).unwrap(), vec![1, 2, 7]);
+
+        // Overriding a standard filter type replaces it.
+        let png = parse(&[
+            (b"IHDR", &ihdr(3, 1, 8, 0)),
+            (b"IDAT", &zlib(&[1, 1, 3, 5])),
+            (b"IEND", &[]),
+        ]);
+        options.filters.set(1, filter_xor);
+
+        assert_eq!(png.decode_with(&options).unwrap(), vec![1, 2, 7]);
+        assert_eq!(png.decode_data().unwrap(), vec![1, 4, 9]);
+    }
+
+    #[test]
+    fn reports_invalid_filter_type_position() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(2, 3, 8, 0)),
+            (b"IDAT", &zlib(&[0, 1, 2, 1, 3, 4, 9, 5, 6])),
+            (b"IEND", &[]),
+        ]);
+
+        let err = png.decode_data().unwrap_err();
+        assert!(matches!(
+            err,
+            PngError::InvalidFilterType {
+                pass: None,
+                scanline: 2,
+                value: 9
+            }
+        ));
+        assert_eq!(err.to_string(), "invalid filter type 9 in scanline 2");
+
+        for decoded in [
+            png.decode_tiled(1, 1, &mut Vec::new()),
+            png.decode_region(0, 2, 1, 1, &mut Vec::new()),
+        ] {
+            assert!(matches!(
+                decoded,
+                Err(PngError::InvalidFilterType { scanline: 2, .. })
+            ));
+        }
+
+        // Registering a filter for the type byte makes it valid.
+        let mut options = DecodeOptions::default();
+        options.filters.set(9, filter_none);
+        assert!(png.decode_with(&options).is_ok());
+
+        // A 2x2 interlaced image has passes 1, 6 and 7, with one scanline each.
+        let mut interlaced = ihdr(2, 2, 8, 0);
+        interlaced[12] = 1;
+        let png = parse(&[
+            (b"IHDR", &interlaced),
+            (b"IDAT", &zlib(&[0, 1, 0, 2, 5, 3, 4])),
+            (b"IEND", &[]),
+        ]);
+
+        let err = png.decode_data().unwrap_err();
+        assert!(matches!(
+            err,
+            PngError::InvalidFilterType {
+                pass: Some(7),
+                scanline: 0,
+                value: 5
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "invalid filter type 5 in scanline 0 of pass 7"
+        );
+    }
+
+    #[test]
+    fn builds_options() {
+        let mut filters = FilterTable::standard();
+        filters.set(42, filter_none);
+
+        let mut options = DecodeOptions::new();
+        options
+            .set_max_decompressed_bytes(1024)
+            .set_filters(filters)
+            .set_ignore_adler32(true)
+            .set_palette_fallback([255, 0, 255]);
+
+        assert_eq!(options.max_decompressed_bytes, Some(1024));
+        assert!(options.filters.get(42).is_some());
+        assert!(options.ignore_adler32);
+        assert_eq!(options.palette_fallback, Some([255, 0, 255]));
+
+        let png = parse(&[
+            (b"IHDR", &ihdr(2, 1, 8, 0)),
+            (b"IDAT", &zlib(&[42, 1, 2])),
+            (b"IEND", &[]),
+        ]);
+        assert_eq!(png.decode_with(&options).unwrap(), vec![1, 2]);
+        assert!(matches!(
+            png.decode_with(DecodeOptions::new().set_max_decompressed_bytes(1)),
+            Err(PngError::DecompressedTooLarge { limit: 1 })
+        ));
+    }
+
+    #[test]
+    fn extracts_filtered_stream() {
+        let stream = [1, 10, 5, 0, 20, 30];
+        let png = parse(&[
+            (b"IHDR", &ihdr(2, 2, 8, 0)),
+            (b"IDAT", &zlib(&stream)),
+            (b"IEND", &[]),
+        ]);
+
+        assert_eq!(png.filtered_stream().unwrap(), stream);
+        assert_eq!(png.decode_data().unwrap(), [10, 15, 20, 30]);
+
+        let short = parse(&[
+            (b"IHDR", &ihdr(2, 2, 8, 0)),
+            (b"IDAT", &zlib(&stream[..4])),
+            (b"IEND", &[]),
+        ]);
+        assert!(matches!(
+            short.filtered_stream(),
+            Err(PngError::WrongDataLength {
+                expected: 6,
+                actual: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn copies_unfiltered_scanlines() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(3, 2, 8, 0)),
+            (b"IDAT", &zlib(&[0, 1, 2, 3, 0, 4, 5, 6])),
+            (b"IEND", &[]),
+        ]);
+        assert_eq!(png.decode_data().unwrap(), vec![1, 2, 3, 4, 5, 6]);
+
+        // A filtered scanline anywhere falls back to reconstructing every scanline.
+        let mixed = parse(&[
+            (b"IHDR", &ihdr(3, 2, 8, 0)),
+            (b"IDAT", &zlib(&[0, 1, 2, 3, 2, 4, 5, 6])),
+            (b"IEND", &[]),
+        ]);
+        assert_eq!(mixed.decode_data().unwrap(), vec![1, 2, 3, 5, 7, 9]);
+
+        // So does a replaced None filter.
+        fn filter_invert(x: u8, _: u8, _: usize, _: &[u8], _: usize) -> u8 {
+            !x
+        }
+
+        let mut options = DecodeOptions::default();
+        options.filters.set(0, filter_invert);
+        assert_eq!(
+            png.decode_with(&options).unwrap(),
+            vec![254, 253, 252, 251, 250, 249]
+        );
+    }
+
+    #[test]
+    fn decodes_zlib_stream_split_across_tiny_idat_chunks() {
+        let png = PngFile::from_bytes(&std::fs::read("./code.png").unwrap()).unwrap();
+        let expected = png.decode_data().unwrap();
+
+        let stream = png
+            .chunks
+            .iter()
+            .filter(|chunk| chunk.chunk_type == ChunkType::IDAT)
+            .flat_map(|chunk| chunk.data.iter().copied())
+            .collect::<Vec<_>>();
+
+        // Odd sizes split the stream inside the zlib header, deflate blocks and the Adler-32 trailer.
+        for chunk_size in [1, 2, 7, 61, 1000] {
+            let ihdr = png.header_chunk().unwrap().data.as_slice();
+            let mut chunks: Vec<(&[u8; 4], &[u8])> = vec![(b"IHDR", ihdr)];
+            for data in stream.chunks(chunk_size) {
+                // Empty IDAT chunks are legal and carry no part of the stream.
+                chunks.extend([(b"IDAT", data), (b"IDAT", &[][..])]);
+            }
+            chunks.push((b"IEND", &[]));
+
+            let split = parse(&chunks);
+            assert_eq!(
+                split.decode_data().unwrap(),
+                expected,
+                "IDAT chunks of {} bytes",
+                chunk_size
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_zlib_headers() {
+        let stream = zlib(&[0, 0]);
+
+        // A preset dictionary, a 64 KiB window, a non-deflate method and a bad check value.
+        for header in [[0x78, 0xbb], [0x88, 0x1c], [0x79, 0x18], [0x78, 0x9d]] {
+            // Splitting the header between chunks shouldn't hide it.
+            let png = parse(&[
+                (b"IHDR", &ihdr(1, 1, 8, 0)),
+                (b"IDAT", &header[..1]),
+                (b"IDAT", &[&header[1..], &stream[2..]].concat()),
+                (b"IEND", &[]),
+            ]);
+
+            assert!(
+                matches!(
+                    png.decode_data(),
+                    Err(PngError::UnsupportedZlibHeader { cmf, flags }) if [cmf, flags] == header
+                ),
+                "header {:02x?}",
+                header
+            );
+        }
+
+        // Windows smaller than 32 KiB are fine.
+        let mut small_window = stream.clone();
+        small_window[..2].copy_from_slice(&[0x18, 0x95]);
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"IDAT", &small_window),
+            (b"IEND", &[]),
+        ]);
+        assert_eq!(png.decode_data().unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn deinterlaces_passes() {
+        let png = parse(&[(b"IHDR", &ihdr(8, 8, 8, 0)), (b"IEND", &[])]);
+        let header = png.try_parse_header().unwrap();
+
+        // Fill every pass with its own number to recover the Adam7 pattern.
+        let passes = PASSES
+            .iter()
+            .enumerate()
+            .map(|(i, pass)| vec![i as u8 + 1; pass.width(8) * pass.height(8)])
+            .collect::<Vec<_>>();
+        let passes: [&[u8]; 7] = core::array::from_fn(|i| passes[i].as_slice());
+
+        let mut out = vec![0; 64];
+        deinterlace(&header, &passes, &mut out).unwrap();
+
+        #[rustfmt::skip]
+        let expected = [
+            1, 6, 4, 6, 2, 6, 4, 6,
+            7, 7, 7, 7, 7, 7, 7, 7,
+            5, 6, 5, 6, 5, 6, 5, 6,
+            7, 7, 7, 7, 7, 7, 7, 7,
+            3, 6, 4, 6, 3, 6, 4, 6,
+            7, 7, 7, 7, 7, 7, 7, 7,
+            5, 6, 5, 6, 5, 6, 5, 6,
+            7, 7, 7, 7, 7, 7, 7, 7,
+        ];
+        assert_eq!(out, expected);
+
+        let mut too_small = vec![0; 63];
+        assert!(matches!(
+            deinterlace(&header, &passes, &mut too_small),
+            Err(PngError::WrongDataLength {
+                expected: 64,
+                actual: 63
+            })
+        ));
+    }
+
+    #[test]
+    fn optionally_ignores_adler32_mismatches() {
+        let mut data = zlib(&[0, 1, 2, 0, 3, 4]);
+        *data.last_mut().unwrap() ^= 0xff;
+
+        let png = parse(&[
+            (b"IHDR", &ihdr(2, 2, 8, 0)),
+            (b"IDAT", &data),
+            (b"IEND", &[]),
+        ]);
+
+        let error = png.decode_data().unwrap_err();
+        assert!(matches!(
+            error,
+            PngError::InflateError(e) if e.status() == TINFLStatus::Adler32Mismatch
+        ));
+        #[cfg(feature = "std")]
+        {
+            use std::error::Error;
+            let source = error.source().unwrap().downcast_ref::<InflateError>();
+            assert_eq!(source, Some(&InflateError(TINFLStatus::Adler32Mismatch)));
+        }
+
+        let options = DecodeOptions {
+            ignore_adler32: true,
+            ..Default::default()
+        };
+        let image = png.decode_image_with(&options).unwrap();
+
+        assert_eq!(image.data(), [1, 2, 3, 4]);
+        assert_eq!(image.warnings(), [DecodeWarning::Adler32Mismatch]);
+        assert!(png.decode_image().is_err());
+        assert!(parse(&[
+            (b"IHDR", &ihdr(2, 2, 8, 0)),
+            (b"IDAT", &zlib(&[0, 1, 2, 0, 3, 4])),
+            (b"IEND", &[]),
+        ])
+        .decode_image_with(&options)
+        .unwrap()
+        .warnings()
+        .is_empty());
+    }
+
+    #[test]
+    fn decodes_interlaced_4_bit_indexed_fixture() {
+        // 13x11, so passes 4, 5 and 7 are 3, 7 and 13 pixels wide and end half way through a byte.
+        let path = "./indexed-4bit-interlaced.png";
+        let png = PngFile::from_bytes(&std::fs::read(path).unwrap()).unwrap();
+
+        let mut expected = vec![0u8; 7 * 11];
+        for y in 0..11 {
+            for x in 0..13 {
+                let index = ((x * 3 + y * 5 + x * y) % 16) as u8;
+                set_packed_sample(&mut expected[y * 7..(y + 1) * 7], x, 4, index);
+            }
+        }
+
+        assert_eq!(png.decode_data().unwrap(), expected);
+        assert_eq!(
+            png.to_rgba8().unwrap(),
+            image::open(path).unwrap().to_rgba8().into_raw()
+        );
+    }
+
+    #[test]
+    fn reconstructs_16_bit_rgba_with_every_filter() {
+        // 9x10 RGBA at 16 bits, so filters look back 8 bytes. Row y uses filter type y % 5, and every sample
+        // has a distinct high and low byte, which a filter reading the wrong neighbour would mix up.
+        let path = "./rgba16-filters.png";
+        let png = PngFile::from_bytes(&std::fs::read(path).unwrap()).unwrap();
+
+        let inflated = inflate_zlib(&png.image_data_chunks().unwrap()[0].data).unwrap();
+        let filter_types = inflated
+            .chunks_exact(9 * 8 + 1)
+            .map(|scanline| scanline[0])
+            .collect::<Vec<_>>();
+        assert_eq!(filter_types, [0, 1, 2, 3, 4, 0, 1, 2, 3, 4]);
+
+        let mut expected = Vec::new();
+        for y in 0..10u32 {
+            for x in 0..9u32 {
+                for channel in 0..4 {
+                    let sample = (x * 7919 + y * 104729 + channel * 31337 + x * y * 613) as u16;
+                    expected.extend_from_slice(&sample.to_be_bytes());
+                }
+            }
+        }
+
+        assert_eq!(png.decode_data().unwrap(), expected);
+
+        let reference = image::open(path).unwrap().to_rgba16().into_raw();
+        let reference = reference
+            .iter()
+            .flat_map(|sample| sample.to_be_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(expected, reference);
+    }
+
+    #[test]
+    fn limits_decompressed_size() {
+        let options = DecodeOptions {
+            max_decompressed_bytes: Some(1024),
+            ..Default::default()
+        };
+
+        // A tiny image whose IDAT inflates far beyond what the header needs.
+        let bomb = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"IDAT", &zlib(&[0; 1 << 20])),
+            (b"IEND", &[]),
+        ]);
+        assert!(matches!(
+            bomb.decode_with(&options),
+            Err(PngError::DecompressedTooLarge { limit: 1024 })
+        ));
+
+        // A header demanding a huge reconstruction buffer is rejected before inflating anything.
+        let huge = parse(&[
+            (b"IHDR", &ihdr(100_000, 100_000, 8, 6)),
+            (b"IDAT", &zlib(&[0])),
+            (b"IEND", &[]),
+        ]);
+        assert!(matches!(
+            huge.decode_with(&options),
+            Err(PngError::DecompressedTooLarge { limit: 1024 })
+        ));
+
+        let small = parse(&[
+            (b"IHDR", &ihdr(16, 16, 8, 0)),
+            (b"IDAT", &zlib(&[0; 16 * 17])),
+            (b"IEND", &[]),
+        ]);
+        assert_eq!(small.decode_with(&options).unwrap(), vec![0; 256]);
+    }
+}
@@ -0,0 +1,78 @@
+use sha2::{Digest, Sha256};
+
+use crate::png_parser::{PngError, PngFile};
+
+impl PngFile {
+    /// SHA-256 of the image's pixels, independent of how they were encoded.
+    ///
+    /// The pixels are first converted to 8-bit RGBA like [`to_rgba8`](Self::to_rgba8), which means palettes and
+    /// transparency are expanded and interlacing is undone. The hash covers the width and height as big-endian
+    /// `u32`s followed by the converted pixels, so files that decode to the same RGBA image have the same
+    /// digest.
+    pub fn pixel_digest(&self) -> Result<[u8; 32], PngError> {
+        let header = self.try_parse_header()?;
+        let rgba = self.to_rgba8()?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(header.width.to_be_bytes());
+        hasher.update(header.height.to_be_bytes());
+        hasher.update(&rgba);
+
+        Ok(hasher.finalize().into())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::{
+        encoder::PngEncoder,
+        png_parser::{BitDepth, ColourType, InterlaceMethod, PngFile},
+        test_util::{ihdr, parse, zlib},
+    };
+
+    #[test]
+    fn digest_ignores_encoding() {
+        for path in ["./code.png", "./indexed-4bit-interlaced.png"] {
+            let png = PngFile::from_bytes(&std::fs::read(path).unwrap()).unwrap();
+            let header = png.try_parse_header().unwrap();
+
+            let mut encoder = PngEncoder::new(
+                header.width,
+                header.height,
+                ColourType::TruecolourWithAlpha,
+                BitDepth::B8,
+            )
+            .unwrap();
+            encoder.set_interlace_method(InterlaceMethod::Adam7);
+            let reencoded = encoder.encode(&png.to_rgba8().unwrap()).unwrap();
+
+            assert_eq!(
+                PngFile::from_bytes(&reencoded)
+                    .unwrap()
+                    .pixel_digest()
+                    .unwrap(),
+                png.pixel_digest().unwrap(),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn digest_covers_dimensions_and_pixels() {
+        let digest = |width, height, data: &[u8]| {
+            parse(&[
+                (b"IHDR", &ihdr(width, height, 8, 0)),
+                (b"IDAT", &zlib(data)),
+                (b"IEND", &[]),
+            ])
+            .pixel_digest()
+            .unwrap()
+        };
+
+        let wide = digest(2, 1, &[0, 1, 2]);
+        assert_ne!(wide, digest(1, 2, &[0, 1, 0, 2]));
+        assert_ne!(wide, digest(2, 1, &[0, 1, 3]));
+        assert_eq!(wide, digest(2, 1, &[1, 1, 1]));
+    }
+}
@@ -0,0 +1,955 @@
+use std::io::{self, Write};
+
+use deflate::{write::ZlibEncoder, Compression};
+
+use crate::{
+    adam7::PASSES,
+    crc::chunk_crc,
+    decoder::{packed_sample, paeth_predictor, set_packed_sample},
+    png_parser::{
+        check_bit_depth, check_dimensions, BitDepth, ChunkType, ColourType, InterlaceMethod,
+        PngError, PngFile, PngHeader, MAGIC, MAX_CHUNK_LENGTH,
+    },
+};
+
+/// How [`PngEncoder`] picks the filter of each scanline.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FilterStrategy {
+    /// Filter type 0 on every scanline. Usually the best choice for indexed and sub-byte images.
+    #[default]
+    None,
+    /// Tries all five filters on each scanline and keeps the one with the minimum sum of absolute
+    /// differences, reading the filtered bytes as signed.
+    Adaptive,
+}
+
+/// How hard [`PngEncoder`] compresses the filtered image data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompressionLevel {
+    Fast,
+    #[default]
+    Default,
+    Best,
+}
+
+impl From<CompressionLevel> for Compression {
+    fn from(level: CompressionLevel) -> Self {
+        match level {
+            CompressionLevel::Fast => Compression::Fast,
+            CompressionLevel::Default => Compression::Default,
+            CompressionLevel::Best => Compression::Best,
+        }
+    }
+}
+
+/// Encodes raw, unfiltered image data into a PNG file.
+///
+/// The data layout matches what the decoder produces: scanlines packed top to bottom, 16-bit samples in
+/// big-endian order.
+#[derive(Debug, Clone)]
+pub struct PngEncoder {
+    width: u32,
+    height: u32,
+    colour_type: ColourType,
+    bit_depth: BitDepth,
+    interlace_method: InterlaceMethod,
+    filter_strategy: FilterStrategy,
+    compression: CompressionLevel,
+    palette: Option<Vec<[u8; 3]>>,
+    transparency: Option<Vec<u8>>,
+    text: Vec<(String, String)>,
+}
+
+impl PngEncoder {
+    pub fn new(
+        width: u32,
+        height: u32,
+        colour_type: ColourType,
+        bit_depth: BitDepth,
+    ) -> Result<Self, PngError> {
+        check_dimensions(width, height)?;
+        check_bit_depth(colour_type, bit_depth)?;
+
+        Ok(PngEncoder {
+            width,
+            height,
+            colour_type,
+            bit_depth,
+            interlace_method: InterlaceMethod::None,
+            filter_strategy: FilterStrategy::None,
+            compression: CompressionLevel::Default,
+            palette: None,
+            transparency: None,
+            text: Vec::new(),
+        })
+    }
+
+    /// Sets the palette, written as a PLTE chunk. Indexed images need one, and truecolour images may carry one
+    /// as a suggestion for viewers that can't show every colour. Greyscale images can't have a palette.
+    pub fn set_palette(&mut self, palette: &[[u8; 3]]) -> Result<&mut Self, PngError> {
+        if matches!(
+            self.colour_type,
+            ColourType::Greyscale | ColourType::GreyscaleWithAlpha
+        ) {
+            return Err(PngError::MisplacedChunk(ChunkType::PLTE));
+        }
+
+        if palette.is_empty() || palette.len() > 256 {
+            return Err(PngError::MalformedChunk(ChunkType::PLTE));
+        }
+
+        let max = 1 << self.bit_depth.bits();
+
+        if self.colour_type == ColourType::IndexedColour && palette.len() > max {
+            return Err(PngError::PaletteTooLargeForDepth {
+                entries: palette.len(),
+                max,
+            });
+        }
+
+        self.palette = Some(palette.to_vec());
+        Ok(self)
+    }
+
+    /// Sets the data of the tRNS chunk: the alpha of each palette entry for indexed images, or the colour key
+    /// of greyscale (2 bytes) and truecolour (6 bytes) images. Images with an alpha channel can't have one.
+    pub fn set_transparency(&mut self, transparency: &[u8]) -> Result<&mut Self, PngError> {
+        let valid = match self.colour_type {
+            ColourType::Greyscale => transparency.len() == 2,
+            ColourType::Truecolour => transparency.len() == 6,
+            // Checked against the palette once the image is encoded, since it may be set later.
+            ColourType::IndexedColour => transparency.len() <= 256,
+            ColourType::GreyscaleWithAlpha | ColourType::TruecolourWithAlpha => {
+                return Err(PngError::MisplacedChunk(ChunkType::tRNS));
+            }
+        };
+
+        if !valid {
+            return Err(PngError::MalformedChunk(ChunkType::tRNS));
+        }
+
+        self.transparency = Some(transparency.to_vec());
+        Ok(self)
+    }
+
+    /// Adds a tEXt entry. The keyword must be 1-79 printable Latin-1 characters and the value must be Latin-1.
+    pub fn add_text(&mut self, keyword: &str, value: &str) -> Result<&mut Self, PngError> {
+        let valid_keyword = (1..=79).contains(&keyword.chars().count())
+            && keyword
+                .chars()
+                .all(|c| matches!(c as u32, 32..=126 | 161..=255));
+
+        if !valid_keyword {
+            return Err(PngError::InvalidTextKeyword(keyword.to_owned()));
+        }
+
+        if value.chars().any(|c| c == '\0' || c as u32 > 255) {
+            return Err(PngError::NonLatin1Text);
+        }
+
+        self.text.push((keyword.to_owned(), value.to_owned()));
+        Ok(self)
+    }
+
+    pub fn set_interlace_method(&mut self, interlace_method: InterlaceMethod) -> &mut Self {
+        self.interlace_method = interlace_method;
+        self
+    }
+
+    pub fn set_filter_strategy(&mut self, filter_strategy: FilterStrategy) -> &mut Self {
+        self.filter_strategy = filter_strategy;
+        self
+    }
+
+    pub fn set_compression(&mut self, compression: CompressionLevel) -> &mut Self {
+        self.compression = compression;
+        self
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.bit_depth.bits() as usize * self.colour_type.channel_count()
+    }
+
+    fn scanline_length(&self) -> usize {
+        (self.width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    pub fn encode(&self, data: &[u8]) -> Result<Vec<u8>, PngError> {
+        let mut out = Vec::new();
+        self.encode_to(&mut out, data)?;
+        Ok(out)
+    }
+
+    pub fn encode_to<W: Write>(&self, writer: &mut W, data: &[u8]) -> Result<(), PngError> {
+        let image_data = self.compress_image_data(data)?;
+
+        self.write_header(writer)?;
+        write_image_data(writer, &image_data)?;
+        write_chunk(writer, ChunkType::IEND, &[])?;
+
+        Ok(())
+    }
+
+    /// Filters and compresses `data` into the zlib stream that goes into the IDAT chunks.
+    pub(crate) fn compress_image_data(&self, data: &[u8]) -> Result<Vec<u8>, PngError> {
+        let expected = self.scanline_length() * self.height as usize;
+
+        if data.len() != expected {
+            return Err(PngError::WrongDataLength {
+                expected,
+                actual: data.len(),
+            });
+        }
+
+        let filtered = self.filter_scanlines(data);
+        Ok(deflate::deflate_bytes_zlib_conf(
+            &filtered,
+            Compression::from(self.compression),
+        ))
+    }
+
+    /// Writes the signature, IHDR and every chunk that goes before the image data. Fails before writing
+    /// anything if the chunks wouldn't make a valid file.
+    fn write_header<W: Write>(&self, writer: &mut W) -> Result<(), PngError> {
+        if self.colour_type == ColourType::IndexedColour {
+            let palette_len = self
+                .palette
+                .as_ref()
+                .ok_or(PngError::MissingChunk(ChunkType::PLTE))?
+                .len();
+
+            if self
+                .transparency
+                .as_ref()
+                .is_some_and(|transparency| transparency.len() > palette_len)
+            {
+                return Err(PngError::MalformedChunk(ChunkType::tRNS));
+            }
+        }
+
+        writer.write_all(&MAGIC)?;
+
+        let header = PngHeader {
+            width: self.width,
+            height: self.height,
+            bit_depth: self.bit_depth,
+            colour_type: self.colour_type,
+            interlace_method: self.interlace_method,
+            compression_method: 0,
+            filter_method: 0,
+        };
+        write_chunk(writer, ChunkType::IHDR, &header.to_chunk().data)?;
+
+        if let Some(palette) = &self.palette {
+            write_chunk(writer, ChunkType::PLTE, palette.as_flattened())?;
+        }
+
+        if let Some(transparency) = &self.transparency {
+            write_chunk(writer, ChunkType::tRNS, transparency)?;
+        }
+
+        for (keyword, value) in &self.text {
+            let text_data = keyword
+                .chars()
+                .chain(std::iter::once('\0'))
+                .chain(value.chars())
+                .map(|c| c as u8)
+                .collect::<Vec<_>>();
+
+            write_chunk(writer, ChunkType::tEXt, &text_data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Splits `data` into the scanlines to be compressed, and filters each one as the strategy says.
+    fn filter_scanlines(&self, data: &[u8]) -> Vec<u8> {
+        let scanline_length = self.scanline_length();
+        let bits_per_pixel = self.bits_per_pixel();
+        let bytes_per_pixel = (bits_per_pixel / 8).max(1);
+        let mut filtered = Vec::with_capacity(data.len() + self.height as usize);
+
+        match self.interlace_method {
+            InterlaceMethod::None => {
+                let mut previous = vec![0; scanline_length];
+
+                for scanline in data.chunks_exact(scanline_length.max(1)) {
+                    filter_row(
+                        self.filter_strategy,
+                        scanline,
+                        &previous,
+                        bytes_per_pixel,
+                        &mut filtered,
+                    );
+                    previous.copy_from_slice(scanline);
+                }
+            }
+            InterlaceMethod::Adam7 => {
+                let mut pass_scanline = Vec::new();
+                let mut previous = Vec::new();
+
+                for pass in PASSES {
+                    let width = pass.width(self.width);
+
+                    if width == 0 {
+                        continue;
+                    }
+
+                    let pass_scanline_length = (width * bits_per_pixel).div_ceil(8);
+
+                    // Each pass is filtered as an image of its own, starting from a zero row.
+                    previous.clear();
+                    previous.resize(pass_scanline_length, 0);
+
+                    for pass_row in 0..pass.height(self.height) {
+                        let y = pass.y_start + pass_row * pass.y_step;
+                        let scanline = &data[y * scanline_length..(y + 1) * scanline_length];
+
+                        pass_scanline.clear();
+
+                        if bits_per_pixel < 8 {
+                            pass_scanline.resize(pass_scanline_length, 0);
+
+                            for column in 0..width {
+                                let x = pass.x_start + column * pass.x_step;
+                                let sample = packed_sample(scanline, x, bits_per_pixel);
+                                set_packed_sample(
+                                    &mut pass_scanline,
+                                    column,
+                                    bits_per_pixel,
+                                    sample,
+                                );
+                            }
+                        } else {
+                            for column in 0..width {
+                                let x = pass.x_start + column * pass.x_step;
+                                pass_scanline.extend_from_slice(
+                                    &scanline[x * bytes_per_pixel..(x + 1) * bytes_per_pixel],
+                                );
+                            }
+                        }
+
+                        filter_row(
+                            self.filter_strategy,
+                            &pass_scanline,
+                            &previous,
+                            bytes_per_pixel,
+                            &mut filtered,
+                        );
+                        std::mem::swap(&mut previous, &mut pass_scanline);
+                    }
+                }
+            }
+        }
+
+        filtered
+    }
+}
+
+/// Appends the filter type byte chosen by `strategy` and `row` filtered with it. `previous` is the unfiltered
+/// row above, all zeros for the first row.
+fn filter_row(
+    strategy: FilterStrategy,
+    row: &[u8],
+    previous: &[u8],
+    bytes_per_pixel: usize,
+    out: &mut Vec<u8>,
+) {
+    let filter_type = match strategy {
+        FilterStrategy::None => 0,
+        FilterStrategy::Adaptive => (0..5)
+            .min_by_key(|&filter_type| {
+                (0..row.len())
+                    .map(|i| {
+                        let byte = filter_byte(filter_type, row, previous, i, bytes_per_pixel);
+                        (byte as i8).unsigned_abs() as u64
+                    })
+                    .sum::<u64>()
+            })
+            .unwrap(),
+    };
+
+    out.push(filter_type);
+    out.extend((0..row.len()).map(|i| filter_byte(filter_type, row, previous, i, bytes_per_pixel)));
+}
+
+/// Applies filter `filter_type` to byte `i` of `row`. The inverse of the decoder's filter functions.
+fn filter_byte(
+    filter_type: u8,
+    row: &[u8],
+    previous: &[u8],
+    i: usize,
+    bytes_per_pixel: usize,
+) -> u8 {
+    let a = if i >= bytes_per_pixel {
+        row[i - bytes_per_pixel]
+    } else {
+        0
+    };
+    let b = previous[i];
+    let c = if i >= bytes_per_pixel {
+        previous[i - bytes_per_pixel]
+    } else {
+        0
+    };
+
+    let predictor = match filter_type {
+        0 => 0,
+        1 => a,
+        2 => b,
+        3 => ((a as u16 + b as u16) / 2) as u8,
+        _ => paeth_predictor(a, b, c),
+    };
+
+    row[i].wrapping_sub(predictor)
+}
+
+/// Size of the IDAT chunks written by [`PngEncoder`] and [`PngWriter`].
+const IDAT_CHUNK_SIZE: usize = 32 * 1024;
+
+/// Writes a PNG one scanline at a time, so the whole image never has to be in memory.
+///
+/// Rows are compressed as they arrive and written out in IDAT chunks of up to 32 KiB. Only non-interlaced
+/// images can be streamed, since Adam7 needs every row before the first pass is complete.
+pub struct PngWriter<W: Write> {
+    encoder: ZlibEncoder<IdatWriter<W>>,
+    scanline_length: usize,
+    height: u32,
+    rows_written: u32,
+    filter_strategy: FilterStrategy,
+    bytes_per_pixel: usize,
+    previous: Vec<u8>,
+    filtered: Vec<u8>,
+}
+
+impl<W: Write> PngWriter<W> {
+    /// Writes the signature and the chunks before the image data described by `config`.
+    pub fn new(mut writer: W, config: &PngEncoder) -> Result<Self, PngError> {
+        if config.interlace_method != InterlaceMethod::None {
+            return Err(PngError::UnsupportedInterlaceMethod(
+                config.interlace_method,
+            ));
+        }
+
+        config.write_header(&mut writer)?;
+
+        let idat_writer = IdatWriter {
+            writer,
+            buffer: Vec::with_capacity(IDAT_CHUNK_SIZE),
+        };
+
+        Ok(PngWriter {
+            encoder: ZlibEncoder::new(idat_writer, Compression::from(config.compression)),
+            scanline_length: config.scanline_length(),
+            height: config.height,
+            rows_written: 0,
+            filter_strategy: config.filter_strategy,
+            bytes_per_pixel: (config.bits_per_pixel() / 8).max(1),
+            previous: vec![0; config.scanline_length()],
+            filtered: Vec::with_capacity(config.scanline_length() + 1),
+        })
+    }
+
+    /// Writes the next unfiltered scanline, laid out like a row of [`PngEncoder::encode`]'s input.
+    pub fn write_row(&mut self, row: &[u8]) -> Result<(), PngError> {
+        if row.len() != self.scanline_length {
+            return Err(PngError::WrongDataLength {
+                expected: self.scanline_length,
+                actual: row.len(),
+            });
+        }
+
+        if self.rows_written == self.height {
+            return Err(self.wrong_row_count(self.rows_written as usize + 1));
+        }
+
+        self.filtered.clear();
+        filter_row(
+            self.filter_strategy,
+            row,
+            &self.previous,
+            self.bytes_per_pixel,
+            &mut self.filtered,
+        );
+        self.encoder.write_all(&self.filtered)?;
+        self.previous.copy_from_slice(row);
+        self.rows_written += 1;
+
+        Ok(())
+    }
+
+    /// Writes the remaining image data and IEND, and returns the underlying writer. Fails if fewer rows than
+    /// the image height were written.
+    pub fn finish(self) -> Result<W, PngError> {
+        if self.rows_written != self.height {
+            return Err(self.wrong_row_count(self.rows_written as usize));
+        }
+
+        let mut idat_writer = self.encoder.finish()?;
+        idat_writer.write_chunk()?;
+
+        let mut writer = idat_writer.writer;
+        write_chunk(&mut writer, ChunkType::IEND, &[])?;
+
+        Ok(writer)
+    }
+
+    fn wrong_row_count(&self, rows: usize) -> PngError {
+        PngError::WrongDataLength {
+            expected: self.scanline_length * self.height as usize,
+            actual: self.scanline_length * rows,
+        }
+    }
+}
+
+/// Collects compressed output and writes it to `writer` as IDAT chunks.
+struct IdatWriter<W> {
+    writer: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> IdatWriter<W> {
+    fn write_chunk(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            write_chunk(&mut self.writer, ChunkType::IDAT, &self.buffer).map_err(
+                |err| match err {
+                    PngError::IoError(err) => err,
+                    err => io::Error::other(err),
+                },
+            )?;
+            self.buffer.clear();
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for IdatWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = buf.len().min(IDAT_CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..length]);
+
+        if self.buffer.len() == IDAT_CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writes a single chunk with its length prefix and a freshly computed CRC.
+pub(crate) fn write_chunk<W: Write>(
+    writer: &mut W,
+    chunk_type: ChunkType,
+    data: &[u8],
+) -> Result<(), PngError> {
+    if data.len() > MAX_CHUNK_LENGTH as usize {
+        return Err(PngError::ChunkTooLarge {
+            chunk_type,
+            length: data.len(),
+        });
+    }
+
+    let chunk_type: [u8; 4] = chunk_type.into();
+
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(&chunk_type)?;
+    writer.write_all(data)?;
+    writer.write_all(&chunk_crc(chunk_type, data).to_be_bytes())?;
+
+    Ok(())
+}
+
+/// Writes a zlib stream as IDAT chunks of up to [`IDAT_CHUNK_SIZE`] bytes.
+pub(crate) fn write_image_data<W: Write>(writer: &mut W, data: &[u8]) -> Result<(), PngError> {
+    for chunk in data.chunks(IDAT_CHUNK_SIZE) {
+        write_chunk(writer, ChunkType::IDAT, chunk)?;
+    }
+
+    Ok(())
+}
+
+impl PngFile {
+    /// Serializes the file, recomputing every chunk's length and CRC but leaving the chunk data untouched.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), PngError> {
+        writer.write_all(&MAGIC)?;
+
+        for chunk in &self.chunks {
+            write_chunk(writer, chunk.chunk_type, &chunk.data)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn repairs_corrupted_crcs() {
+        let original = fs::read("./code.png").unwrap();
+
+        let mut corrupted = original.clone();
+        // Last byte of the IHDR CRC.
+        corrupted[8 + 8 + 13 + 3] ^= 0xff;
+
+        let png = PngFile::from_reader(&mut corrupted.as_slice()).unwrap();
+        assert!(!png.chunks[0].is_crc_valid());
+
+        let mut repaired = Vec::new();
+        png.write_to(&mut repaired).unwrap();
+
+        assert_eq!(repaired, original);
+    }
+
+    #[test]
+    fn encodes_text_metadata() {
+        let data = [255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255];
+
+        let mut encoder = PngEncoder::new(2, 2, ColourType::Truecolour, BitDepth::B8).unwrap();
+        encoder
+            .add_text("Software", "poeng")
+            .unwrap()
+            .add_text("Comment", "Gr\u{fc}\u{df}e")
+            .unwrap();
+
+        let encoded = encoder.encode(&data).unwrap();
+        let png = PngFile::from_reader(&mut encoded.as_slice()).unwrap();
+
+        assert!(png.chunks.iter().all(|chunk| chunk.is_crc_valid()));
+        assert_eq!(png.chunks[1].chunk_type, ChunkType::tEXt);
+        assert_eq!(png.decode_data().unwrap(), data);
+
+        let text = png.text_metadata().unwrap();
+        assert_eq!(text.len(), 2);
+        assert_eq!(text[0].keyword, "Software");
+        assert_eq!(text[0].text, "poeng");
+        assert_eq!(text[1].keyword, "Comment");
+        assert_eq!(text[1].text, "Gr\u{fc}\u{df}e");
+    }
+
+    #[test]
+    fn rejects_invalid_keywords() {
+        let mut encoder = PngEncoder::new(1, 1, ColourType::Greyscale, BitDepth::B8).unwrap();
+
+        for keyword in ["", "Null\0Byte", &"k".repeat(80), "\u{3c0}"] {
+            assert!(matches!(
+                encoder.add_text(keyword, "value"),
+                Err(PngError::InvalidTextKeyword(_))
+            ));
+        }
+
+        assert!(matches!(
+            encoder.add_text("Title", "\u{3c0}"),
+            Err(PngError::NonLatin1Text)
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_dimensions() {
+        for (width, height) in [(0, 5), (5, 0), (0x8000_0000, 1), (1, u32::MAX)] {
+            assert!(matches!(
+                PngEncoder::new(width, height, ColourType::Greyscale, BitDepth::B8),
+                Err(PngError::InvalidDimensions { .. })
+            ));
+        }
+
+        assert!(PngEncoder::new(0x7fff_ffff, 1, ColourType::Greyscale, BitDepth::B1).is_ok());
+    }
+
+    #[test]
+    fn writes_palette_and_transparency() {
+        let mut encoder = PngEncoder::new(3, 1, ColourType::IndexedColour, BitDepth::B2).unwrap();
+        let data = [0b00_01_10_00];
+
+        assert!(matches!(
+            encoder.encode(&data),
+            Err(PngError::MissingChunk(ChunkType::PLTE))
+        ));
+        assert!(matches!(
+            PngWriter::new(Vec::new(), &encoder),
+            Err(PngError::MissingChunk(ChunkType::PLTE))
+        ));
+        assert!(matches!(
+            encoder.set_palette(&[[0; 3]; 5]),
+            Err(PngError::PaletteTooLargeForDepth { entries: 5, max: 4 })
+        ));
+
+        encoder
+            .set_palette(&[[255, 0, 0], [0, 255, 0], [0, 0, 255]])
+            .unwrap()
+            .set_transparency(&[255, 128])
+            .unwrap();
+        let encoded = encoder.encode(&data).unwrap();
+
+        assert!(crate::validate::validate_stream(&mut encoded.as_slice()).is_empty());
+        assert_eq!(
+            PngFile::from_bytes(&encoded).unwrap().to_rgba8().unwrap(),
+            [255, 0, 0, 255, 0, 255, 0, 128, 0, 0, 255, 255]
+        );
+
+        encoder.set_transparency(&[0; 4]).unwrap();
+        assert!(matches!(
+            encoder.encode(&data),
+            Err(PngError::MalformedChunk(ChunkType::tRNS))
+        ));
+
+        let mut grey = PngEncoder::new(1, 1, ColourType::Greyscale, BitDepth::B8).unwrap();
+        assert!(matches!(
+            grey.set_palette(&[[0; 3]]),
+            Err(PngError::MisplacedChunk(ChunkType::PLTE))
+        ));
+    }
+
+    #[test]
+    fn filters_adaptively() {
+        for path in [
+            "./PNG-Gradient.png",
+            "./code.png",
+            "./indexed-4bit-interlaced.png",
+            "./rgba16-filters.png",
+        ] {
+            let bytes = fs::read(path).unwrap();
+            let png = PngFile::from_bytes(&bytes).unwrap();
+            let header = png.try_parse_header().unwrap();
+            let data = png.decode_data().unwrap();
+            let rgba = png.to_rgba8().unwrap();
+
+            let mut encoder = PngEncoder::new(
+                header.width,
+                header.height,
+                header.colour_type,
+                header.bit_depth,
+            )
+            .unwrap();
+
+            if let Some(palette) = png.palette().unwrap() {
+                encoder.set_palette(&palette.entries).unwrap();
+            }
+
+            if let Some(transparency) = png.find_chunk(ChunkType::tRNS) {
+                encoder.set_transparency(&transparency.data).unwrap();
+            }
+
+            for interlace_method in [InterlaceMethod::None, InterlaceMethod::Adam7] {
+                encoder.set_interlace_method(interlace_method);
+
+                for compression in [
+                    CompressionLevel::Fast,
+                    CompressionLevel::Default,
+                    CompressionLevel::Best,
+                ] {
+                    let encoded = encoder
+                        .set_filter_strategy(FilterStrategy::Adaptive)
+                        .set_compression(compression)
+                        .encode(&data)
+                        .unwrap();
+                    let decoded = PngFile::from_bytes(&encoded).unwrap();
+                    let context = format!("{} {:?} {:?}", path, interlace_method, compression);
+
+                    let errors = crate::validate::validate_stream(&mut encoded.as_slice());
+                    assert!(errors.is_empty(), "{}: {:?}", context, errors);
+                    assert_eq!(decoded.decode_data().unwrap(), data, "{}", context);
+                    assert_eq!(decoded.to_rgba8().unwrap(), rgba, "{}", context);
+                }
+            }
+        }
+
+        // A gradient is where filtering pays off.
+        let png = PngFile::from_bytes(&fs::read("./PNG-Gradient.png").unwrap()).unwrap();
+        let header = png.try_parse_header().unwrap();
+        let data = png.decode_data().unwrap();
+        let mut encoder = PngEncoder::new(
+            header.width,
+            header.height,
+            header.colour_type,
+            header.bit_depth,
+        )
+        .unwrap();
+
+        let unfiltered = encoder.encode(&data).unwrap();
+        let filtered = encoder
+            .set_filter_strategy(FilterStrategy::Adaptive)
+            .encode(&data)
+            .unwrap();
+        assert!(filtered.len() < unfiltered.len());
+    }
+
+    #[test]
+    fn streams_adaptively_filtered_rows() {
+        let png = PngFile::from_bytes(&fs::read("./PNG-Gradient.png").unwrap()).unwrap();
+        let header = png.try_parse_header().unwrap();
+        let data = png.decode_data().unwrap();
+
+        let mut encoder = PngEncoder::new(
+            header.width,
+            header.height,
+            header.colour_type,
+            header.bit_depth,
+        )
+        .unwrap();
+        encoder.set_filter_strategy(FilterStrategy::Adaptive);
+
+        let mut writer = PngWriter::new(Vec::new(), &encoder).unwrap();
+        for row in data.chunks_exact(data.len() / header.height as usize) {
+            writer.write_row(row).unwrap();
+        }
+        let streamed = PngFile::from_bytes(&writer.finish().unwrap()).unwrap();
+
+        assert_eq!(streamed.decode_data().unwrap(), data);
+        assert_eq!(
+            streamed.filtered_stream().unwrap(),
+            PngFile::from_bytes(&encoder.encode(&data).unwrap())
+                .unwrap()
+                .filtered_stream()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn streams_rows_into_idat_chunks() {
+        let (width, height) = (300, 200);
+
+        // Noise, so that the compressed stream needs several chunks.
+        let mut state = 0x2545_f491u32;
+        let data = (0..width * height * 3)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+
+        let mut encoder = PngEncoder::new(
+            width as u32,
+            height as u32,
+            ColourType::Truecolour,
+            BitDepth::B8,
+        )
+        .unwrap();
+        encoder.add_text("Software", "poeng").unwrap();
+
+        let mut writer = PngWriter::new(Vec::new(), &encoder).unwrap();
+        for row in data.chunks_exact(width * 3) {
+            writer.write_row(row).unwrap();
+        }
+        let encoded = writer.finish().unwrap();
+
+        let png = PngFile::from_bytes(&encoded).unwrap();
+        let idat_chunks = png
+            .chunks
+            .iter()
+            .filter(|chunk| chunk.chunk_type == ChunkType::IDAT)
+            .collect::<Vec<_>>();
+
+        assert!(idat_chunks.len() > 1);
+        assert!(idat_chunks
+            .iter()
+            .all(|chunk| chunk.data.len() <= IDAT_CHUNK_SIZE));
+        assert_eq!(png.text_metadata().unwrap()[0].text, "poeng");
+        assert_eq!(png.decode_data().unwrap(), data);
+        assert_eq!(
+            image::load_from_memory(&encoded)
+                .unwrap()
+                .to_rgb8()
+                .into_raw(),
+            data
+        );
+    }
+
+    #[test]
+    fn splits_image_data_into_idat_chunks() {
+        let (width, height) = (300, 200);
+
+        // Noise, so that the compressed stream needs several chunks.
+        let mut state = 0x9e37_79b9u32;
+        let data = (0..width * height * 3)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+
+        let encoder = PngEncoder::new(
+            width as u32,
+            height as u32,
+            ColourType::Truecolour,
+            BitDepth::B8,
+        )
+        .unwrap();
+        let png = PngFile::from_bytes(&encoder.encode(&data).unwrap()).unwrap();
+        let idat_lengths = png
+            .chunks
+            .iter()
+            .filter(|chunk| chunk.chunk_type == ChunkType::IDAT)
+            .map(|chunk| chunk.data.len())
+            .collect::<Vec<_>>();
+
+        assert!(idat_lengths.len() > 1);
+        assert!(idat_lengths.iter().all(|&length| length <= IDAT_CHUNK_SIZE));
+        assert_eq!(png.decode_data().unwrap(), data);
+    }
+
+    #[test]
+    fn streaming_requires_every_row() {
+        let encoder = PngEncoder::new(2, 2, ColourType::Greyscale, BitDepth::B8).unwrap();
+
+        let mut writer = PngWriter::new(Vec::new(), &encoder).unwrap();
+        assert!(matches!(
+            writer.write_row(&[0; 3]),
+            Err(PngError::WrongDataLength {
+                expected: 2,
+                actual: 3
+            })
+        ));
+        writer.write_row(&[1, 2]).unwrap();
+        assert!(matches!(
+            writer.finish(),
+            Err(PngError::WrongDataLength {
+                expected: 4,
+                actual: 2
+            })
+        ));
+
+        let mut writer = PngWriter::new(Vec::new(), &encoder).unwrap();
+        writer.write_row(&[1, 2]).unwrap();
+        writer.write_row(&[3, 4]).unwrap();
+        assert!(matches!(
+            writer.write_row(&[5, 6]),
+            Err(PngError::WrongDataLength {
+                expected: 4,
+                actual: 6
+            })
+        ));
+
+        let mut interlaced = encoder.clone();
+        interlaced.set_interlace_method(InterlaceMethod::Adam7);
+        assert!(matches!(
+            PngWriter::new(Vec::new(), &interlaced),
+            Err(PngError::UnsupportedInterlaceMethod(InterlaceMethod::Adam7))
+        ));
+    }
+
+    #[test]
+    fn rejects_wrong_data_length() {
+        let encoder = PngEncoder::new(2, 2, ColourType::Greyscale, BitDepth::B8).unwrap();
+
+        assert!(matches!(
+            encoder.encode(&[0; 3]),
+            Err(PngError::WrongDataLength {
+                expected: 4,
+                actual: 3
+            })
+        ));
+    }
+}
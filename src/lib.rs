@@ -1,5 +1,30 @@
-mod decoder;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+mod adam7;
+pub mod apng;
+pub mod borrowed;
+mod convert;
+mod crc;
+pub mod decoded_image;
+pub mod decoder;
+#[cfg(feature = "digest")]
+mod digest;
+#[cfg(feature = "std")]
+pub mod encoder;
+pub mod metadata;
+#[cfg(feature = "std")]
+pub mod optimize;
 pub mod png_parser;
+mod reader;
+pub mod sink;
+pub mod support;
+#[cfg(feature = "std")]
+pub mod validate;
+
+#[cfg(test)]
+mod test_util;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,761 @@
+use alloc::{string::String, vec::Vec};
+
+use crate::{
+    decoder::inflate_zlib,
+    png_parser::{ChunkType, PngChunk, PngError, PngFile},
+    reader::SliceReader,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuggestedPalette {
+    pub name: String,
+    pub sample_depth: u8,
+    /// Red, green, blue, alpha and frequency of each entry.
+    pub entries: Vec<(u16, u16, u16, u16, u16)>,
+}
+
+impl<'a> TryFrom<&'a PngChunk> for SuggestedPalette {
+    type Error = PngError;
+
+    fn try_from(value: &'a PngChunk) -> Result<Self, Self::Error> {
+        if value.chunk_type != ChunkType::sPLT {
+            return Err(PngError::UnexpectedChunkType {
+                expected: ChunkType::sPLT,
+                was: value.chunk_type,
+            });
+        }
+
+        let (name, rest) =
+            split_latin1_keyword(&value.data).ok_or(PngError::MalformedChunk(ChunkType::sPLT))?;
+        let (&sample_depth, entry_data) = rest
+            .split_first()
+            .ok_or(PngError::MalformedChunk(ChunkType::sPLT))?;
+
+        let entry_size = match sample_depth {
+            8 => 6,
+            16 => 10,
+            unknown => return Err(PngError::UnknownSampleDepth(unknown)),
+        };
+
+        if entry_data.len() % entry_size != 0 {
+            return Err(PngError::MalformedChunk(ChunkType::sPLT));
+        }
+
+        let mut reader = SliceReader::new(entry_data);
+        let mut entries = Vec::with_capacity(entry_data.len() / entry_size);
+
+        for _ in 0..entry_data.len() / entry_size {
+            let (red, green, blue, alpha) = if sample_depth == 8 {
+                (
+                    reader.read_u8()? as u16,
+                    reader.read_u8()? as u16,
+                    reader.read_u8()? as u16,
+                    reader.read_u8()? as u16,
+                )
+            } else {
+                (
+                    reader.read_u16()?,
+                    reader.read_u16()?,
+                    reader.read_u16()?,
+                    reader.read_u16()?,
+                )
+            };
+
+            let frequency = reader.read_u16()?;
+            entries.push((red, green, blue, alpha, frequency));
+        }
+
+        Ok(SuggestedPalette {
+            name,
+            sample_depth,
+            entries,
+        })
+    }
+}
+
+/// Image gamma from a gAMA chunk, times 100000.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gamma(pub u32);
+
+impl Gamma {
+    /// The gamma implied by an sRGB chunk.
+    pub const SRGB: Gamma = Gamma(45455);
+
+    /// Whether this is close enough to [`Gamma::SRGB`] to be written by an sRGB-aware encoder. Some write
+    /// 45454, for example.
+    pub fn matches_srgb(self) -> bool {
+        self.0.abs_diff(Gamma::SRGB.0) <= 100
+    }
+}
+
+impl<'a> TryFrom<&'a PngChunk> for Gamma {
+    type Error = PngError;
+
+    fn try_from(value: &'a PngChunk) -> Result<Self, Self::Error> {
+        if value.chunk_type != ChunkType::gAMA {
+            return Err(PngError::UnexpectedChunkType {
+                expected: ChunkType::gAMA,
+                was: value.chunk_type,
+            });
+        }
+
+        if value.data.len() != 4 {
+            return Err(PngError::MalformedChunk(ChunkType::gAMA));
+        }
+
+        Ok(Gamma(SliceReader::new(&value.data).read_u32()?))
+    }
+}
+
+/// CIE 1931 x and y coordinates of the white point and primaries from a cHRM chunk, times 100000.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chromaticities {
+    pub white: (u32, u32),
+    pub red: (u32, u32),
+    pub green: (u32, u32),
+    pub blue: (u32, u32),
+}
+
+impl Chromaticities {
+    /// The chromaticities implied by an sRGB chunk.
+    pub const SRGB: Chromaticities = Chromaticities {
+        white: (31270, 32900),
+        red: (64000, 33000),
+        green: (30000, 60000),
+        blue: (15000, 6000),
+    };
+
+    /// Whether every coordinate is within 0.01 of [`Chromaticities::SRGB`].
+    pub fn matches_srgb(&self) -> bool {
+        let close = |(x, y): (u32, u32), (srgb_x, srgb_y): (u32, u32)| {
+            x.abs_diff(srgb_x) <= 1000 && y.abs_diff(srgb_y) <= 1000
+        };
+        let srgb = Chromaticities::SRGB;
+
+        close(self.white, srgb.white)
+            && close(self.red, srgb.red)
+            && close(self.green, srgb.green)
+            && close(self.blue, srgb.blue)
+    }
+}
+
+impl<'a> TryFrom<&'a PngChunk> for Chromaticities {
+    type Error = PngError;
+
+    fn try_from(value: &'a PngChunk) -> Result<Self, Self::Error> {
+        if value.chunk_type != ChunkType::cHRM {
+            return Err(PngError::UnexpectedChunkType {
+                expected: ChunkType::cHRM,
+                was: value.chunk_type,
+            });
+        }
+
+        if value.data.len() != 32 {
+            return Err(PngError::MalformedChunk(ChunkType::cHRM));
+        }
+
+        let mut reader = SliceReader::new(&value.data);
+        let mut point =
+            || -> Result<(u32, u32), PngError> { Ok((reader.read_u32()?, reader.read_u32()?)) };
+
+        Ok(Chromaticities {
+            white: point()?,
+            red: point()?,
+            green: point()?,
+            blue: point()?,
+        })
+    }
+}
+
+/// Rendering intent from an sRGB chunk, which also marks the image as being in the sRGB colour space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderingIntent {
+    Perceptual,
+    RelativeColorimetric,
+    Saturation,
+    AbsoluteColorimetric,
+}
+
+impl<'a> TryFrom<&'a PngChunk> for RenderingIntent {
+    type Error = PngError;
+
+    fn try_from(value: &'a PngChunk) -> Result<Self, Self::Error> {
+        if value.chunk_type != ChunkType::sRGB {
+            return Err(PngError::UnexpectedChunkType {
+                expected: ChunkType::sRGB,
+                was: value.chunk_type,
+            });
+        }
+
+        match value.data.as_slice() {
+            [0] => Ok(RenderingIntent::Perceptual),
+            [1] => Ok(RenderingIntent::RelativeColorimetric),
+            [2] => Ok(RenderingIntent::Saturation),
+            [3] => Ok(RenderingIntent::AbsoluteColorimetric),
+            _ => Err(PngError::MalformedChunk(ChunkType::sRGB)),
+        }
+    }
+}
+
+/// Unit of [`PhysicalDimensions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhysicalUnit {
+    /// Only the aspect ratio is known.
+    Unknown,
+    Metre,
+}
+
+/// Pixels per unit along each axis, from a pHYs chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhysicalDimensions {
+    pub pixels_per_unit_x: u32,
+    pub pixels_per_unit_y: u32,
+    pub unit: PhysicalUnit,
+}
+
+impl<'a> TryFrom<&'a PngChunk> for PhysicalDimensions {
+    type Error = PngError;
+
+    fn try_from(value: &'a PngChunk) -> Result<Self, Self::Error> {
+        if value.chunk_type != ChunkType::pHYs {
+            return Err(PngError::UnexpectedChunkType {
+                expected: ChunkType::pHYs,
+                was: value.chunk_type,
+            });
+        }
+
+        if value.data.len() != 9 {
+            return Err(PngError::MalformedChunk(ChunkType::pHYs));
+        }
+
+        let mut reader = SliceReader::new(&value.data);
+        let pixels_per_unit_x = reader.read_u32()?;
+        let pixels_per_unit_y = reader.read_u32()?;
+
+        let unit = match reader.read_u8()? {
+            0 => PhysicalUnit::Unknown,
+            1 => PhysicalUnit::Metre,
+            _ => return Err(PngError::MalformedChunk(ChunkType::pHYs)),
+        };
+
+        Ok(PhysicalDimensions {
+            pixels_per_unit_x,
+            pixels_per_unit_y,
+            unit,
+        })
+    }
+}
+
+/// Which ancillary chunks a file has, from [`PngFile::metadata_summary`].
+///
+/// The values of chunks that fail to parse are left as `None`, and their types are listed in `malformed`
+/// instead.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetadataSummary {
+    pub gamma: Option<Gamma>,
+    pub chromaticities: Option<Chromaticities>,
+    pub rendering_intent: Option<RenderingIntent>,
+    pub physical_dimensions: Option<PhysicalDimensions>,
+    /// Number of tEXt, zTXt and iTXt chunks.
+    pub text_entries: usize,
+    /// Number of sPLT chunks.
+    pub suggested_palettes: usize,
+    pub has_icc_profile: bool,
+    pub has_exif: bool,
+    pub has_transparency: bool,
+    pub has_background: bool,
+    pub has_significant_bits: bool,
+    pub has_histogram: bool,
+    pub has_modification_time: bool,
+    pub malformed: Vec<ChunkType>,
+}
+
+/// An embedded ICC profile from an iCCP chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IccProfile {
+    pub name: String,
+    /// The decompressed profile.
+    pub profile: Vec<u8>,
+}
+
+impl<'a> TryFrom<&'a PngChunk> for IccProfile {
+    type Error = PngError;
+
+    fn try_from(value: &'a PngChunk) -> Result<Self, Self::Error> {
+        if value.chunk_type != ChunkType::iCCP {
+            return Err(PngError::UnexpectedChunkType {
+                expected: ChunkType::iCCP,
+                was: value.chunk_type,
+            });
+        }
+
+        let (name, rest) =
+            split_latin1_keyword(&value.data).ok_or(PngError::MalformedChunk(ChunkType::iCCP))?;
+        let (&compression_method, compressed) = rest
+            .split_first()
+            .ok_or(PngError::MalformedChunk(ChunkType::iCCP))?;
+
+        // Checked before inflating, since another method's data would only produce a misleading inflate error.
+        if compression_method != 0 {
+            return Err(PngError::UnknownCompressionMethod(compression_method));
+        }
+
+        Ok(IccProfile {
+            name,
+            profile: inflate_zlib(compressed)?,
+        })
+    }
+}
+
+/// A keyword and text pair from a tEXt, zTXt or iTXt chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEntry {
+    pub keyword: String,
+    pub text: String,
+}
+
+impl<'a> TryFrom<&'a PngChunk> for TextEntry {
+    type Error = PngError;
+
+    fn try_from(value: &'a PngChunk) -> Result<Self, Self::Error> {
+        let chunk_type = value.chunk_type;
+        let malformed = || PngError::MalformedChunk(chunk_type);
+
+        let (keyword, rest) = split_latin1_keyword(&value.data).ok_or_else(malformed)?;
+
+        let text = match chunk_type {
+            ChunkType::tEXt => latin1(rest),
+            ChunkType::zTXt => {
+                let (&compression_method, compressed) = rest.split_first().ok_or_else(malformed)?;
+
+                if compression_method != 0 {
+                    return Err(PngError::UnknownCompressionMethod(compression_method));
+                }
+
+                latin1(&inflate_zlib(compressed)?)
+            }
+            ChunkType::iTXt => {
+                let (flags, rest) = rest.split_at_checked(2).ok_or_else(malformed)?;
+                let (compressed, compression_method) = (flags[0] != 0, flags[1]);
+
+                // The language tag and translated keyword are skipped.
+                let mut fields = rest.splitn(3, |&b| b == 0);
+                let text = fields.nth(2).ok_or_else(malformed)?;
+
+                let text = if compressed {
+                    if compression_method != 0 {
+                        return Err(PngError::UnknownCompressionMethod(compression_method));
+                    }
+
+                    inflate_zlib(text)?
+                } else {
+                    text.to_vec()
+                };
+
+                String::from_utf8(text).map_err(|_| malformed())?
+            }
+            was => {
+                return Err(PngError::UnexpectedChunkType {
+                    expected: ChunkType::tEXt,
+                    was,
+                })
+            }
+        };
+
+        Ok(TextEntry { keyword, text })
+    }
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+/// Splits a null-terminated Latin-1 keyword (1-79 bytes) off the front of a chunk payload.
+fn split_latin1_keyword(data: &[u8]) -> Option<(String, &[u8])> {
+    let terminator = data.iter().position(|&b| b == 0)?;
+
+    if !(1..=79).contains(&terminator) {
+        return None;
+    }
+
+    Some((latin1(&data[..terminator]), &data[terminator + 1..]))
+}
+
+impl PngFile {
+    pub fn suggested_palettes(&self) -> Result<Vec<SuggestedPalette>, PngError> {
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.chunk_type == ChunkType::sPLT)
+            .map(SuggestedPalette::try_from)
+            .collect()
+    }
+
+    pub fn gamma(&self) -> Result<Option<Gamma>, PngError> {
+        self.find_chunk(ChunkType::gAMA)
+            .map(Gamma::try_from)
+            .transpose()
+    }
+
+    pub fn chromaticities(&self) -> Result<Option<Chromaticities>, PngError> {
+        self.find_chunk(ChunkType::cHRM)
+            .map(Chromaticities::try_from)
+            .transpose()
+    }
+
+    /// The sRGB rendering intent, or `None` if the file doesn't declare itself as sRGB.
+    pub fn rendering_intent(&self) -> Result<Option<RenderingIntent>, PngError> {
+        self.find_chunk(ChunkType::sRGB)
+            .map(RenderingIntent::try_from)
+            .transpose()
+    }
+
+    /// Parsing doesn't inflate the profile, the first call to this does, and later calls return the same
+    /// profile. A profile that fails to parse isn't cached, so each call reports the error again.
+    pub fn icc_profile(&self) -> Result<Option<&IccProfile>, PngError> {
+        let chunk = match self.find_chunk(ChunkType::iCCP) {
+            Some(chunk) => chunk,
+            None => return Ok(None),
+        };
+
+        if let Some(profile) = chunk.icc_profile.get() {
+            return Ok(Some(profile));
+        }
+
+        let profile = IccProfile::try_from(chunk)?;
+        Ok(Some(chunk.icc_profile.get_or_init(|| profile)))
+    }
+
+    pub fn physical_dimensions(&self) -> Result<Option<PhysicalDimensions>, PngError> {
+        self.find_chunk(ChunkType::pHYs)
+            .map(PhysicalDimensions::try_from)
+            .transpose()
+    }
+
+    /// Collects the ancillary chunks of the file in a single pass. Compressed chunks aren't inflated, so only
+    /// the presence of ICC profiles is reported and text is only counted.
+    pub fn metadata_summary(&self) -> MetadataSummary {
+        let mut summary = MetadataSummary::default();
+
+        fn parse<T: for<'a> TryFrom<&'a PngChunk>>(
+            chunk: &PngChunk,
+            value: &mut Option<T>,
+            malformed: &mut Vec<ChunkType>,
+        ) {
+            // Like the accessors, only the first of several chunks counts.
+            if value.is_none() {
+                match T::try_from(chunk) {
+                    Ok(parsed) => *value = Some(parsed),
+                    Err(_) => malformed.push(chunk.chunk_type),
+                }
+            }
+        }
+
+        for chunk in &self.chunks {
+            let malformed = &mut summary.malformed;
+
+            match chunk.chunk_type {
+                ChunkType::gAMA => parse(chunk, &mut summary.gamma, malformed),
+                ChunkType::cHRM => parse(chunk, &mut summary.chromaticities, malformed),
+                ChunkType::sRGB => parse(chunk, &mut summary.rendering_intent, malformed),
+                ChunkType::pHYs => parse(chunk, &mut summary.physical_dimensions, malformed),
+                ChunkType::tEXt | ChunkType::zTXt | ChunkType::iTXt => summary.text_entries += 1,
+                ChunkType::sPLT => summary.suggested_palettes += 1,
+                ChunkType::iCCP => summary.has_icc_profile = true,
+                ChunkType::eXIf => summary.has_exif = true,
+                ChunkType::tRNS => summary.has_transparency = true,
+                ChunkType::bKGD => summary.has_background = true,
+                ChunkType::sBIT => summary.has_significant_bits = true,
+                ChunkType::hIST => summary.has_histogram = true,
+                ChunkType::tIME => summary.has_modification_time = true,
+                ChunkType::IHDR
+                | ChunkType::PLTE
+                | ChunkType::IDAT
+                | ChunkType::IEND
+                | ChunkType::Unknown(_) => {}
+            }
+        }
+
+        summary
+    }
+
+    /// Returns the entries of every tEXt, zTXt and iTXt chunk, in file order.
+    pub fn text_metadata(&self) -> Result<Vec<TextEntry>, PngError> {
+        self.chunks
+            .iter()
+            .filter(|chunk| {
+                matches!(
+                    chunk.chunk_type,
+                    ChunkType::tEXt | ChunkType::zTXt | ChunkType::iTXt
+                )
+            })
+            .map(TextEntry::try_from)
+            .collect()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::test_util::{ihdr, parse, zlib};
+
+    /// A profile header with no tags: the smallest well-formed ICC profile.
+    fn minimal_icc_profile() -> Vec<u8> {
+        let mut profile = vec![0u8; 132];
+        profile[..4].copy_from_slice(&132u32.to_be_bytes());
+        // Version 4.3, display device class, RGB data, XYZ connection space.
+        profile[8..12].copy_from_slice(&[4, 0x30, 0, 0]);
+        profile[12..16].copy_from_slice(b"mntr");
+        profile[16..20].copy_from_slice(b"RGB ");
+        profile[20..24].copy_from_slice(b"XYZ ");
+        profile[36..40].copy_from_slice(b"acsp");
+        profile
+    }
+
+    #[test]
+    fn parses_icc_profile() {
+        let profile = minimal_icc_profile();
+        let mut data = b"Display P3\0\0".to_vec();
+        data.extend_from_slice(&zlib(&profile));
+
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 2)),
+            (b"iCCP", &data),
+            (b"IEND", &[]),
+        ]);
+
+        assert_eq!(
+            png.icc_profile().unwrap(),
+            Some(&IccProfile {
+                name: "Display P3".into(),
+                profile,
+            })
+        );
+        // Inflated only once, by the first call.
+        assert!(core::ptr::eq(
+            png.icc_profile().unwrap().unwrap(),
+            png.icc_profile().unwrap().unwrap()
+        ));
+
+        let no_profile = parse(&[(b"IHDR", &ihdr(1, 1, 8, 2)), (b"IEND", &[])]);
+        assert_eq!(no_profile.icc_profile().unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_unknown_icc_compression_method() {
+        let mut data = b"profile\0\x01".to_vec();
+        data.extend_from_slice(&zlib(&minimal_icc_profile()));
+
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 2)),
+            (b"iCCP", &data),
+            (b"IEND", &[]),
+        ]);
+
+        assert!(matches!(
+            png.icc_profile(),
+            Err(PngError::UnknownCompressionMethod(1))
+        ));
+    }
+
+    #[test]
+    fn summarises_metadata() {
+        let mut physical_dimensions = Vec::new();
+        physical_dimensions.extend_from_slice(&2835u32.to_be_bytes());
+        physical_dimensions.extend_from_slice(&2835u32.to_be_bytes());
+        physical_dimensions.push(1);
+
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 2)),
+            (b"gAMA", &45455u32.to_be_bytes()),
+            (b"sRGB", &[9]),
+            (b"pHYs", &physical_dimensions),
+            (b"tEXt", b"Title\0one"),
+            (b"zTXt", b"Comment\0\0not inflated"),
+            (b"eXIf", b"MM\0*"),
+            (b"IDAT", &zlib(&[0, 0, 0, 0])),
+            (b"tIME", &[7, 234, 10, 14, 12, 0, 0]),
+            (b"IEND", &[]),
+        ]);
+
+        assert_eq!(
+            png.metadata_summary(),
+            MetadataSummary {
+                gamma: Some(Gamma::SRGB),
+                physical_dimensions: Some(PhysicalDimensions {
+                    pixels_per_unit_x: 2835,
+                    pixels_per_unit_y: 2835,
+                    unit: PhysicalUnit::Metre,
+                }),
+                text_entries: 2,
+                has_exif: true,
+                has_modification_time: true,
+                malformed: vec![ChunkType::sRGB],
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            png.physical_dimensions().unwrap(),
+            png.metadata_summary().physical_dimensions
+        );
+    }
+
+    #[test]
+    fn parses_colour_chunks() {
+        let mut chromaticities = Vec::new();
+        for value in [31270u32, 32900, 64000, 33000, 30000, 60000, 15000, 6000] {
+            chromaticities.extend_from_slice(&value.to_be_bytes());
+        }
+
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 2)),
+            (b"sRGB", &[1]),
+            (b"gAMA", &45455u32.to_be_bytes()),
+            (b"cHRM", &chromaticities),
+            (b"IEND", &[]),
+        ]);
+
+        assert_eq!(
+            png.rendering_intent().unwrap(),
+            Some(RenderingIntent::RelativeColorimetric)
+        );
+        assert_eq!(png.gamma().unwrap(), Some(Gamma::SRGB));
+        assert_eq!(png.chromaticities().unwrap(), Some(Chromaticities::SRGB));
+
+        let malformed = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 2)),
+            (b"sRGB", &[4]),
+            (b"gAMA", &[0, 1]),
+            (b"IEND", &[]),
+        ]);
+
+        assert!(matches!(
+            malformed.rendering_intent(),
+            Err(PngError::MalformedChunk(ChunkType::sRGB))
+        ));
+        assert!(matches!(
+            malformed.gamma(),
+            Err(PngError::MalformedChunk(ChunkType::gAMA))
+        ));
+        assert_eq!(malformed.chromaticities().unwrap(), None);
+    }
+
+    #[test]
+    fn parses_8_and_16_bit_suggested_palettes() {
+        let mut eight_bit = b"web\0\x08".to_vec();
+        eight_bit.extend_from_slice(&[255, 0, 0, 255, 0, 10]);
+        eight_bit.extend_from_slice(&[0, 0, 255, 128, 0, 5]);
+
+        let mut sixteen_bit = b"precise\0\x10".to_vec();
+        sixteen_bit.extend_from_slice(&[0xff, 0xff, 0x80, 0x00, 0, 1, 0xff, 0xff, 0, 42]);
+
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 2)),
+            (b"sPLT", &eight_bit),
+            (b"sPLT", &sixteen_bit),
+            (b"IEND", &[]),
+        ]);
+
+        let palettes = png.suggested_palettes().unwrap();
+
+        assert_eq!(
+            palettes,
+            vec![
+                SuggestedPalette {
+                    name: String::from("web"),
+                    sample_depth: 8,
+                    entries: vec![(255, 0, 0, 255, 10), (0, 0, 255, 128, 5)],
+                },
+                SuggestedPalette {
+                    name: String::from("precise"),
+                    sample_depth: 16,
+                    entries: vec![(0xffff, 0x8000, 1, 0xffff, 42)],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_entries() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 2)),
+            (b"sPLT", b"web\0\x08\x01\x02\x03"),
+            (b"IEND", &[]),
+        ]);
+
+        assert!(matches!(
+            png.suggested_palettes(),
+            Err(PngError::MalformedChunk(ChunkType::sPLT))
+        ));
+    }
+
+    #[test]
+    fn parses_compressed_and_international_text() {
+        let mut compressed = b"Comment\0\0".to_vec();
+        compressed.extend_from_slice(&zlib(b"squeezed"));
+
+        let mut international = b"Title\0\x01\0fi\0Otsikko\0".to_vec();
+        international.extend_from_slice(&zlib("P\u{e4}\u{e4}kuva".as_bytes()));
+
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"zTXt", &compressed),
+            (b"iTXt", &international),
+            (b"iTXt", b"Author\0\0\0\0\0Paavo"),
+            (b"IEND", &[]),
+        ]);
+
+        let text = png.text_metadata().unwrap();
+
+        assert_eq!(
+            text,
+            vec![
+                TextEntry {
+                    keyword: String::from("Comment"),
+                    text: String::from("squeezed"),
+                },
+                TextEntry {
+                    keyword: String::from("Title"),
+                    text: String::from("P\u{e4}\u{e4}kuva"),
+                },
+                TextEntry {
+                    keyword: String::from("Author"),
+                    text: String::from("Paavo"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_metadata_after_image_data() {
+        // The spec lets text and tIME follow IDAT, which is where encoders that only know them at the end put
+        // them.
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"tEXt", b"Title\0Before"),
+            (b"IDAT", &zlib(&[0, 0])),
+            (b"tEXt", b"Comment\0After"),
+            (b"tIME", &[0x07, 0xea, 10, 14, 12, 0, 0]),
+            (b"IEND", &[]),
+        ]);
+
+        assert!(png.validate().is_ok());
+        assert_eq!(png.decode_data().unwrap(), vec![0]);
+        assert_eq!(
+            png.text_metadata().unwrap(),
+            vec![
+                TextEntry {
+                    keyword: String::from("Title"),
+                    text: String::from("Before"),
+                },
+                TextEntry {
+                    keyword: String::from("Comment"),
+                    text: String::from("After"),
+                },
+            ]
+        );
+
+        let summary = png.metadata_summary();
+        assert_eq!(summary.text_entries, 2);
+        assert!(summary.has_modification_time);
+    }
+}
@@ -0,0 +1,183 @@
+//! Shrinking existing PNG files without changing their pixels.
+
+use crate::{
+    apng::ACTL,
+    encoder::{write_chunk, write_image_data, CompressionLevel, FilterStrategy, PngEncoder},
+    png_parser::{affects_appearance, ChunkType, PngError, PngFile, MAGIC},
+};
+
+/// How much effort [`optimize`] spends.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OptLevel {
+    /// Adaptive filtering at the default compression level.
+    Fast,
+    /// Both filter strategies at the best compression level, keeping the smaller result.
+    #[default]
+    Best,
+}
+
+/// Re-encodes `input` with better filtering and compression, and drops the ancillary chunks that don't affect
+/// how the image looks. Returns whichever of the optimized file and `input` is smaller.
+///
+/// Interlacing and the same chunks as [`PngFile::strip_ancillary`] are kept: the critical chunks, tRNS and the
+/// colour space chunks. Animated PNGs are returned unchanged, since only their default image would survive.
+pub fn optimize(input: &[u8], level: OptLevel) -> Result<Vec<u8>, PngError> {
+    let mut png = PngFile::from_bytes(input)?;
+    png.validate()?;
+
+    if png.find_chunk(ACTL).is_some() {
+        return Ok(input.to_vec());
+    }
+
+    let header = png.try_parse_header()?;
+    let data = png.decode_data()?;
+
+    let mut encoder = PngEncoder::new(
+        header.width,
+        header.height,
+        header.colour_type,
+        header.bit_depth,
+    )?;
+    encoder.set_interlace_method(header.interlace_method);
+
+    let (strategies, compression): (&[FilterStrategy], _) = match level {
+        OptLevel::Fast => (&[FilterStrategy::Adaptive], CompressionLevel::Default),
+        OptLevel::Best => (
+            &[FilterStrategy::None, FilterStrategy::Adaptive],
+            CompressionLevel::Best,
+        ),
+    };
+    encoder.set_compression(compression);
+
+    let mut image_data: Option<Vec<u8>> = None;
+    for &strategy in strategies {
+        let candidate = encoder
+            .set_filter_strategy(strategy)
+            .compress_image_data(&data)?;
+
+        if image_data
+            .as_ref()
+            .is_none_or(|best| candidate.len() < best.len())
+        {
+            image_data = Some(candidate);
+        }
+    }
+    let image_data = image_data.unwrap();
+
+    png.retain_chunks(affects_appearance);
+
+    let mut out = MAGIC.to_vec();
+    let mut wrote_image_data = false;
+
+    for chunk in &png.chunks {
+        match chunk.chunk_type {
+            // The recompressed stream replaces all of the IDAT chunks, where the first one was.
+            ChunkType::IDAT if !wrote_image_data => {
+                write_image_data(&mut out, &image_data)?;
+                wrote_image_data = true;
+            }
+            ChunkType::IDAT => {}
+            _ => write_chunk(&mut out, chunk.chunk_type, &chunk.data)?,
+        }
+    }
+
+    Ok(if out.len() < input.len() {
+        out
+    } else {
+        input.to_vec()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test_util::{ihdr, png_bytes, zlib};
+
+    #[test]
+    fn shrinks_files_without_changing_pixels() {
+        for path in [
+            "./code.png",
+            "./PNG-Gradient.png",
+            "./indexed-4bit-interlaced.png",
+            "./rgba16-filters.png",
+        ] {
+            let input = fs::read(path).unwrap();
+            let original = PngFile::from_bytes(&input).unwrap();
+
+            for level in [OptLevel::Fast, OptLevel::Best] {
+                let optimized = optimize(&input, level).unwrap();
+                assert!(optimized.len() <= input.len(), "{} at {:?}", path, level);
+
+                let png = PngFile::from_bytes_strict(&optimized).unwrap();
+                png.validate().unwrap();
+                assert_eq!(
+                    png.try_parse_header().unwrap(),
+                    original.try_parse_header().unwrap()
+                );
+                assert_eq!(png.decode_data().unwrap(), original.decode_data().unwrap());
+                assert_eq!(png.to_rgba8().unwrap(), original.to_rgba8().unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn strips_metadata_and_keeps_appearance_chunks() {
+        // Every row is the same ramp, stored unfiltered and split over several IDAT chunks.
+        let (width, height) = (64, 64);
+        let data = (0..height)
+            .flat_map(|_| (0..width).map(|x| (x * 4) as u8))
+            .collect::<Vec<_>>();
+        let stream = zlib(
+            &data
+                .chunks_exact(width)
+                .flat_map(|row| [&[0][..], row].concat())
+                .collect::<Vec<_>>(),
+        );
+
+        let input = png_bytes(&[
+            (b"IHDR", &ihdr(width as u32, height as u32, 8, 0)),
+            (b"gAMA", &45455u32.to_be_bytes()),
+            (b"sRGB", &[0]),
+            (b"tEXt", b"Comment\0dropped by the optimizer"),
+            (b"tRNS", &[0, 0]),
+            (b"IDAT", &stream[..10]),
+            (b"IDAT", &[]),
+            (b"IDAT", &stream[10..]),
+            (b"IEND", &[]),
+        ]);
+
+        let optimized = PngFile::from_bytes(&optimize(&input, OptLevel::Best).unwrap()).unwrap();
+        let chunk_types = optimized
+            .chunks
+            .iter()
+            .map(|chunk| chunk.chunk_type)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            chunk_types,
+            [
+                ChunkType::IHDR,
+                ChunkType::gAMA,
+                ChunkType::sRGB,
+                ChunkType::tRNS,
+                ChunkType::IDAT,
+                ChunkType::IEND
+            ]
+        );
+        assert_eq!(optimized.decode_data().unwrap(), data);
+    }
+
+    #[test]
+    fn returns_animations_unchanged() {
+        let input = png_bytes(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"acTL", &[0, 0, 0, 1, 0, 0, 0, 0]),
+            (b"tEXt", b"Comment\0kept"),
+            (b"IDAT", &zlib(&[0, 0])),
+            (b"IEND", &[]),
+        ]);
+
+        assert_eq!(optimize(&input, OptLevel::Best).unwrap(), input);
+    }
+}
@@ -1,16 +1,33 @@
-use std::io::{Cursor, Read};
+use alloc::{format, string::String, vec::Vec};
+use core::fmt;
 
-use byteorder::{BigEndian, ReadBytesExt};
 use thiserror::Error;
 
-use crate::decoder::decode_data;
+use crate::{
+    borrowed::ChunkRef,
+    convert::{alpha_mask, rgba_to_luminance, to_rgba16, to_rgba8, Layout},
+    crc::chunk_crc,
+    decoder::{
+        decode_data, decode_first_rows, decode_region, decode_tiled, filtered_stream,
+        DecodeOptions, InflateError,
+    },
+    metadata::IccProfile,
+    reader::{ChunkSource, SliceReader},
+};
+
+/// Holds values computed from a chunk on first use. Shared files have to stay `Sync` where that's possible.
+#[cfg(feature = "std")]
+pub(crate) type OnceCell<T> = std::sync::OnceLock<T>;
+#[cfg(not(feature = "std"))]
+pub(crate) type OnceCell<T> = core::cell::OnceCell<T>;
 
-const MAGIC: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+pub(crate) const MAGIC: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
 #[derive(Error, Debug)]
 pub enum PngError {
-    #[error("invalid png header magic")]
-    InvalidMagic,
+    /// Holds the first eight bytes of the input, which usually tell what kind of file it actually is.
+    #[error("not a PNG (starts with {})", hex_bytes(.0))]
+    InvalidMagic([u8; 8]),
     #[error("expected chunk type {expected:?}, was {was:?}")]
     UnexpectedChunkType { expected: ChunkType, was: ChunkType },
     #[error("invalid bit depth {0}")]
@@ -22,27 +39,254 @@ pub enum PngError {
         bit_depth: BitDepth,
         colour_type: ColourType,
     },
+    /// Width and height must both be between 1 and 2^31 - 1.
+    #[error("invalid image size {width}x{height}")]
+    InvalidDimensions { width: u32, height: u32 },
     #[error("invalid compression method {0}")]
     UnknownCompressionMethod(u8),
     #[error("invalid filter method {0}")]
     UnknownFilterMethod(u8),
     #[error("invalid interlace method {0}")]
     UnknownInterlaceMethod(u8),
-    #[error("inflate error: {0}")]
-    InflateError(String),
+    #[error("malformed {0:?} chunk")]
+    MalformedChunk(ChunkType),
+    #[error("invalid sample depth {0}")]
+    UnknownSampleDepth(u8),
+    #[error("missing {0:?} chunk")]
+    MissingChunk(ChunkType),
+    #[error("unsupported interlace method {0:?}")]
+    UnsupportedInterlaceMethod(InterlaceMethod),
+    #[error("palette index {index} out of range for palette of {palette_len} entries")]
+    PaletteIndexOutOfRange { index: u8, palette_len: usize },
+    #[error("palette of {entries} entries is larger than the {max} the bit depth can index")]
+    PaletteTooLargeForDepth { entries: usize, max: usize },
+    /// Reported for image data that inflates to less than the header needs. Surplus data is reported as
+    /// [`SurplusImageData`](Self::SurplusImageData) instead.
+    #[error("expected {expected} bytes of image data, got {actual}")]
+    WrongDataLength { expected: usize, actual: usize },
+    /// The image data inflates to more than the `expected` bytes the header needs. Inflating stops soon after,
+    /// so how much more isn't known.
+    #[error("image data is longer than the expected {expected} bytes")]
+    SurplusImageData { expected: usize },
+    #[error("invalid text keyword {0:?}")]
+    InvalidTextKeyword(String),
+    #[error("text is not representable in Latin-1")]
+    NonLatin1Text,
+    #[error("{0:?} chunk passed as image data")]
+    NonImageDataChunk(ChunkType),
+    #[error("IDAT chunks are not contiguous")]
+    NonContiguousIdat,
+    #[error("decompressed image data exceeds the limit of {limit} bytes")]
+    DecompressedTooLarge { limit: usize },
+    /// The decoded image wouldn't fit in memory on this platform, whatever the limits.
+    #[error("image of {width}x{height} pixels is too large to decode")]
+    ImageTooLarge { width: u32, height: u32 },
+    #[error("unsupported zlib header {cmf:#04x} {flags:#04x}")]
+    UnsupportedZlibHeader { cmf: u8, flags: u8 },
+    #[error("inflate error")]
+    InflateError(#[from] InflateError),
+    #[error("{0:?} chunk is not allowed here")]
+    MisplacedChunk(ChunkType),
+    #[error("{0:?} chunk may only appear once")]
+    DuplicateChunk(ChunkType),
+    #[error("unknown critical chunk {:?}", String::from_utf8_lossy(.0))]
+    UnknownCriticalChunk([u8; 4]),
+    #[error("no image data")]
+    MissingImageData,
+    #[error(
+        "CRC mismatch in {chunk_type:?} chunk: stored {stored:#010x}, computed {computed:#010x}"
+    )]
+    CrcMismatch {
+        chunk_type: ChunkType,
+        stored: u32,
+        computed: u32,
+    },
+    /// The input ended in the middle of a PNG, whether it came from a slice or a reader.
+    #[error("unexpected end of data")]
+    UnexpectedEof,
+    /// `scanline` counts from 0 within the Adam7 pass `pass` (1 to 7) of interlaced images.
+    #[error(
+        "invalid filter type {value} in scanline {scanline}{}",
+        .pass.map(|pass| format!(" of pass {}", pass)).unwrap_or_default()
+    )]
+    InvalidFilterType {
+        pass: Option<u8>,
+        scanline: usize,
+        value: u8,
+    },
+    #[error("{0:?} chunk contradicts the sRGB chunk")]
+    ColourChunkConflict(ChunkType),
+    #[error(
+        "{chunk_type:?} chunk declares {expected} bytes of data, but the input ended after {got}"
+    )]
+    TruncatedChunk {
+        chunk_type: ChunkType,
+        expected: u32,
+        got: u32,
+    },
+    #[error("IEND chunk declares {length} bytes of data, but must be empty")]
+    MalformedIend { length: u32 },
+    #[error(
+        "{chunk_type:?} chunk declares {length} bytes of data, more than the 2^31 - 1 allowed"
+    )]
+    InvalidChunkLength { chunk_type: ChunkType, length: u32 },
+    #[error("{chunk_type:?} chunk has {length} bytes of data, more than a chunk can hold")]
+    ChunkTooLarge {
+        chunk_type: ChunkType,
+        length: usize,
+    },
+    #[error("more than {limit} chunks")]
+    TooManyChunks { limit: usize },
+    #[error("data after the IEND chunk")]
+    TrailingData,
+    #[error("invalid tile size {width}x{height}")]
+    InvalidTileSize { width: u32, height: u32 },
+    #[error("region of {width}x{height} at ({x}, {y}) is outside the image")]
+    RegionOutOfBounds {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    #[cfg(feature = "std")]
     #[error("io error")]
     IoError(#[from] std::io::Error),
 }
 
+/// Formats `bytes` as space-separated hex, for error messages.
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Lets `?` propagate decoding errors from functions returning [`std::io::Result`]. IO errors are unwrapped,
+/// running out of input becomes [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof), and everything else is
+/// [`InvalidData`](std::io::ErrorKind::InvalidData) with the `PngError` as its source.
+#[cfg(feature = "std")]
+impl From<PngError> for std::io::Error {
+    fn from(err: PngError) -> Self {
+        use std::io::{Error, ErrorKind};
+
+        match err {
+            PngError::IoError(err) => err,
+            PngError::UnexpectedEof => Error::new(ErrorKind::UnexpectedEof, err),
+            err => Error::new(ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+/// Chunk types registered in the PNG spec are named after their four-letter codes. Anything else, such as
+/// APNG or private chunks, is kept as `Unknown`.
+#[allow(non_camel_case_types)]
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum ChunkType {
     IHDR,
     PLTE,
     IDAT,
     IEND,
+    tRNS,
+    gAMA,
+    cHRM,
+    sRGB,
+    iCCP,
+    tEXt,
+    zTXt,
+    iTXt,
+    bKGD,
+    pHYs,
+    tIME,
+    sBIT,
+    sPLT,
+    hIST,
+    eXIf,
     Unknown([u8; 4]),
 }
 
+impl ChunkType {
+    /// Critical chunks have an uppercase first letter and must be understood to render the image.
+    pub fn is_critical(self) -> bool {
+        let bytes: [u8; 4] = self.into();
+        bytes[0] & 0x20 == 0
+    }
+
+    /// Private chunks have a lowercase second letter and are not registered with the spec.
+    pub fn is_private(self) -> bool {
+        let bytes: [u8; 4] = self.into();
+        bytes[1] & 0x20 != 0
+    }
+
+    /// The third letter is reserved and must be uppercase. Chunks where it isn't come from a future version of
+    /// the spec, and should be treated as unknown.
+    pub fn is_reserved_bit_valid(self) -> bool {
+        let bytes: [u8; 4] = self.into();
+        bytes[2] & 0x20 == 0
+    }
+
+    /// Safe-to-copy chunks have a lowercase last letter. Editors that don't understand an unknown chunk may
+    /// keep it if it's safe to copy, but must drop it otherwise once they modify critical chunks.
+    pub fn is_safe_to_copy(self) -> bool {
+        let bytes: [u8; 4] = self.into();
+        bytes[3] & 0x20 != 0
+    }
+}
+
+impl From<[u8; 4]> for ChunkType {
+    fn from(value: [u8; 4]) -> Self {
+        match &value {
+            b"IHDR" => ChunkType::IHDR,
+            b"PLTE" => ChunkType::PLTE,
+            b"IDAT" => ChunkType::IDAT,
+            b"IEND" => ChunkType::IEND,
+            b"tRNS" => ChunkType::tRNS,
+            b"gAMA" => ChunkType::gAMA,
+            b"cHRM" => ChunkType::cHRM,
+            b"sRGB" => ChunkType::sRGB,
+            b"iCCP" => ChunkType::iCCP,
+            b"tEXt" => ChunkType::tEXt,
+            b"zTXt" => ChunkType::zTXt,
+            b"iTXt" => ChunkType::iTXt,
+            b"bKGD" => ChunkType::bKGD,
+            b"pHYs" => ChunkType::pHYs,
+            b"tIME" => ChunkType::tIME,
+            b"sBIT" => ChunkType::sBIT,
+            b"sPLT" => ChunkType::sPLT,
+            b"hIST" => ChunkType::hIST,
+            b"eXIf" => ChunkType::eXIf,
+            _ => ChunkType::Unknown(value),
+        }
+    }
+}
+
+impl From<ChunkType> for [u8; 4] {
+    fn from(value: ChunkType) -> Self {
+        match value {
+            ChunkType::IHDR => *b"IHDR",
+            ChunkType::PLTE => *b"PLTE",
+            ChunkType::IDAT => *b"IDAT",
+            ChunkType::IEND => *b"IEND",
+            ChunkType::tRNS => *b"tRNS",
+            ChunkType::gAMA => *b"gAMA",
+            ChunkType::cHRM => *b"cHRM",
+            ChunkType::sRGB => *b"sRGB",
+            ChunkType::iCCP => *b"iCCP",
+            ChunkType::tEXt => *b"tEXt",
+            ChunkType::zTXt => *b"zTXt",
+            ChunkType::iTXt => *b"iTXt",
+            ChunkType::bKGD => *b"bKGD",
+            ChunkType::pHYs => *b"pHYs",
+            ChunkType::tIME => *b"tIME",
+            ChunkType::sBIT => *b"sBIT",
+            ChunkType::sPLT => *b"sPLT",
+            ChunkType::hIST => *b"hIST",
+            ChunkType::eXIf => *b"eXIf",
+            ChunkType::Unknown(bytes) => bytes,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum BitDepth {
     B1,
@@ -53,6 +297,13 @@ pub enum BitDepth {
 }
 
 impl BitDepth {
+    pub fn bits(&self) -> u8 {
+        (*self).into()
+    }
+
+    #[deprecated(
+        note = "panics for sub-byte depths, use `bits` or `PngHeader::bits_per_pixel` instead"
+    )]
     pub fn to_bytes(&self) -> usize {
         match self {
             BitDepth::B8 => 1,
@@ -62,6 +313,33 @@ impl BitDepth {
     }
 }
 
+impl TryFrom<u8> for BitDepth {
+    type Error = PngError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(BitDepth::B1),
+            2 => Ok(BitDepth::B2),
+            4 => Ok(BitDepth::B4),
+            8 => Ok(BitDepth::B8),
+            16 => Ok(BitDepth::B16),
+            unknown => Err(PngError::UnknownBitDepth(unknown)),
+        }
+    }
+}
+
+impl From<BitDepth> for u8 {
+    fn from(value: BitDepth) -> Self {
+        match value {
+            BitDepth::B1 => 1,
+            BitDepth::B2 => 2,
+            BitDepth::B4 => 4,
+            BitDepth::B8 => 8,
+            BitDepth::B16 => 16,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum ColourType {
     Greyscale,
@@ -77,45 +355,206 @@ impl ColourType {
             ColourType::Greyscale => 1,
             ColourType::Truecolour => 3,
             ColourType::IndexedColour => 1,
-            ColourType::GreyscaleWithAlpha => 1,
+            ColourType::GreyscaleWithAlpha => 2,
             ColourType::TruecolourWithAlpha => 4,
         }
     }
 }
 
+impl TryFrom<u8> for ColourType {
+    type Error = PngError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ColourType::Greyscale),
+            2 => Ok(ColourType::Truecolour),
+            3 => Ok(ColourType::IndexedColour),
+            4 => Ok(ColourType::GreyscaleWithAlpha),
+            6 => Ok(ColourType::TruecolourWithAlpha),
+            unknown => Err(PngError::UnknownColourType(unknown)),
+        }
+    }
+}
+
+impl From<ColourType> for u8 {
+    fn from(value: ColourType) -> Self {
+        match value {
+            ColourType::Greyscale => 0,
+            ColourType::Truecolour => 2,
+            ColourType::IndexedColour => 3,
+            ColourType::GreyscaleWithAlpha => 4,
+            ColourType::TruecolourWithAlpha => 6,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum InterlaceMethod {
     None,
     Adam7,
 }
 
+impl TryFrom<u8> for InterlaceMethod {
+    type Error = PngError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(InterlaceMethod::None),
+            1 => Ok(InterlaceMethod::Adam7),
+            unknown => Err(PngError::UnknownInterlaceMethod(unknown)),
+        }
+    }
+}
+
+impl From<InterlaceMethod> for u8 {
+    fn from(value: InterlaceMethod) -> Self {
+        match value {
+            InterlaceMethod::None => 0,
+            InterlaceMethod::Adam7 => 1,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct PngChunk {
     length: u32,
     pub chunk_type: ChunkType,
     pub(crate) data: Vec<u8>,
     crc: [u8; 4],
+    offset: Option<u64>,
+    /// The inflated profile of an iCCP chunk, filled in by [`PngFile::icc_profile`].
+    pub(crate) icc_profile: OnceCell<IccProfile>,
+}
+
+impl PngChunk {
+    pub(crate) fn new(chunk_type: ChunkType, data: Vec<u8>) -> Self {
+        PngChunk {
+            length: data.len() as u32,
+            chunk_type,
+            crc: chunk_crc(chunk_type.into(), &data).to_be_bytes(),
+            data,
+            offset: None,
+            icc_profile: OnceCell::new(),
+        }
+    }
+
+    /// Length of the chunk data as declared in the file.
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    /// Byte offset of the chunk's length field from the start of the parsed input, including the PNG
+    /// signature. `None` for chunks that weren't parsed, such as ones built by the encoder.
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+
+    /// The CRC stored in the file, which is not verified while parsing.
+    pub fn crc(&self) -> u32 {
+        u32::from_be_bytes(self.crc)
+    }
+
+    pub fn is_crc_valid(&self) -> bool {
+        self.crc() == chunk_crc(self.chunk_type.into(), &self.data)
+    }
+}
+
+impl<'a> From<&ChunkRef<'a>> for PngChunk {
+    fn from(chunk: &ChunkRef<'a>) -> Self {
+        PngChunk {
+            length: chunk.data.len() as u32,
+            chunk_type: chunk.chunk_type,
+            data: chunk.data.to_vec(),
+            crc: chunk.crc().to_be_bytes(),
+            offset: Some(chunk.offset()),
+            icc_profile: OnceCell::new(),
+        }
+    }
+}
+
+impl<D: Into<Vec<u8>>> From<RawChunk<D>> for PngChunk {
+    fn from(chunk: RawChunk<D>) -> Self {
+        PngChunk {
+            length: chunk.length,
+            chunk_type: chunk.chunk_type,
+            data: chunk.data.into(),
+            crc: chunk.crc,
+            offset: Some(chunk.offset),
+            icc_profile: OnceCell::new(),
+        }
+    }
+}
+
+/// Chunks are compared by content, so a parsed chunk equals one built from the same data.
+impl PartialEq for PngChunk {
+    fn eq(&self, other: &Self) -> bool {
+        self.length == other.length
+            && self.chunk_type == other.chunk_type
+            && self.data == other.data
+            && self.crc == other.crc
+    }
 }
 
-impl std::fmt::Debug for PngChunk {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Eq for PngChunk {}
+
+impl fmt::Debug for PngChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("PngChunk")
             .field("length", &self.length)
             .field("chunk_type", &self.chunk_type)
             .field("crc", &self.crc)
+            .field("offset", &self.offset)
             .finish()
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PngHeader {
     pub width: u32,
     pub height: u32,
     pub(crate) bit_depth: BitDepth,
     pub(crate) colour_type: ColourType,
     pub(crate) interlace_method: InterlaceMethod,
+    // Only 0 is valid for either, so these are kept for diagnostics rather than decoding.
+    pub(crate) compression_method: u8,
+    pub(crate) filter_method: u8,
 }
 
 impl PngHeader {
+    pub fn new(
+        width: u32,
+        height: u32,
+        bit_depth: BitDepth,
+        colour_type: ColourType,
+        interlace_method: InterlaceMethod,
+    ) -> Result<Self, PngError> {
+        check_bit_depth(colour_type, bit_depth)?;
+
+        Ok(PngHeader {
+            width,
+            height,
+            bit_depth,
+            colour_type,
+            interlace_method,
+            compression_method: 0,
+            filter_method: 0,
+        })
+    }
+
+    /// Serializes the header into an IHDR chunk with a matching CRC. The inverse of `PngHeader::try_from`.
+    pub fn to_chunk(&self) -> PngChunk {
+        let mut data = Vec::with_capacity(13);
+        data.extend_from_slice(&self.width.to_be_bytes());
+        data.extend_from_slice(&self.height.to_be_bytes());
+        data.push(self.bit_depth.into());
+        data.push(self.colour_type.into());
+        data.push(self.compression_method);
+        data.push(self.filter_method);
+        data.push(self.interlace_method.into());
+
+        PngChunk::new(ChunkType::IHDR, data)
+    }
+
     pub fn bit_depth(&self) -> BitDepth {
         self.bit_depth
     }
@@ -123,6 +562,47 @@ impl PngHeader {
     pub fn colour_type(&self) -> ColourType {
         self.colour_type
     }
+
+    pub fn interlace_method(&self) -> InterlaceMethod {
+        self.interlace_method
+    }
+
+    /// The compression method byte as stored in IHDR. Parsing rejects anything but 0, deflate.
+    pub fn compression_method(&self) -> u8 {
+        self.compression_method
+    }
+
+    /// The filter method byte as stored in IHDR. Parsing rejects anything but 0, the five adaptive filters.
+    pub fn filter_method(&self) -> u8 {
+        self.filter_method
+    }
+
+    pub fn is_interlaced(&self) -> bool {
+        self.interlace_method != InterlaceMethod::None
+    }
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.bit_depth.bits() as usize * self.colour_type.channel_count()
+    }
+
+    pub fn pixel_count(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+
+    /// Size in bytes of the buffer [`PngFile::decode_data`] returns, or `None` if it doesn't fit in `usize`.
+    /// Useful for rejecting huge images before decoding anything.
+    pub fn decoded_len(&self) -> Option<usize> {
+        (self.width as usize)
+            .checked_mul(self.bits_per_pixel())?
+            .div_ceil(8)
+            .checked_mul(self.height as usize)
+    }
+
+    /// Length in bytes of an unfiltered scanline of `width` pixels. Rows of sub-byte pixels are padded to a
+    /// whole byte.
+    pub(crate) fn scanline_length(&self, width: usize) -> usize {
+        (width * self.bits_per_pixel()).div_ceil(8)
+    }
 }
 
 impl<'a> TryFrom<&'a PngChunk> for PngHeader {
@@ -136,51 +616,27 @@ impl<'a> TryFrom<&'a PngChunk> for PngHeader {
             });
         }
 
-        let mut reader = Cursor::new(&value.data);
+        PngHeader::from_data(&value.data)
+    }
+}
+
+impl PngHeader {
+    /// Parses the data of an IHDR chunk.
+    pub(crate) fn from_data(data: &[u8]) -> Result<Self, PngError> {
+        let mut reader = SliceReader::new(data);
+
+        let width = reader.read_u32()?;
+        let height = reader.read_u32()?;
 
-        let width = reader.read_u32::<BigEndian>()?;
-        let height = reader.read_u32::<BigEndian>()?;
+        check_dimensions(width, height)?;
 
         let bit_depth = reader.read_u8()?;
         let colour_type = reader.read_u8()?;
 
-        let colour_type = match colour_type {
-            0 => ColourType::Greyscale,
-            2 => ColourType::Truecolour,
-            3 => ColourType::IndexedColour,
-            4 => ColourType::GreyscaleWithAlpha,
-            6 => ColourType::TruecolourWithAlpha,
-            unknown => return Err(PngError::UnknownColourType(unknown)),
-        };
-
-        let bit_depth = match bit_depth {
-            1 => BitDepth::B1,
-            2 => BitDepth::B2,
-            4 => BitDepth::B4,
-            8 => BitDepth::B8,
-            16 => BitDepth::B16,
-            unknown => return Err(PngError::UnknownBitDepth(unknown)),
-        };
+        let colour_type = ColourType::try_from(colour_type)?;
+        let bit_depth = BitDepth::try_from(bit_depth)?;
 
-        match (colour_type, bit_depth) {
-            (
-                ColourType::Greyscale,
-                BitDepth::B1 | BitDepth::B2 | BitDepth::B4 | BitDepth::B8 | BitDepth::B16,
-            )
-            | (ColourType::Truecolour, BitDepth::B8 | BitDepth::B16)
-            | (
-                ColourType::IndexedColour,
-                BitDepth::B1 | BitDepth::B2 | BitDepth::B4 | BitDepth::B8,
-            )
-            | (ColourType::GreyscaleWithAlpha, BitDepth::B8 | BitDepth::B16)
-            | (ColourType::TruecolourWithAlpha, BitDepth::B8 | BitDepth::B16) => (),
-            (colour_type, bit_depth) => {
-                return Err(PngError::InvalidBitDepthColourCombination {
-                    colour_type,
-                    bit_depth,
-                })
-            }
-        }
+        check_bit_depth(colour_type, bit_depth)?;
 
         let compression_method = reader.read_u8()?;
 
@@ -196,11 +652,7 @@ impl<'a> TryFrom<&'a PngChunk> for PngHeader {
 
         let interlace_method = reader.read_u8()?;
 
-        let interlace_method = match interlace_method {
-            0 => InterlaceMethod::None,
-            1 => InterlaceMethod::Adam7,
-            unknown => return Err(PngError::UnknownInterlaceMethod(unknown)),
-        };
+        let interlace_method = InterlaceMethod::try_from(interlace_method)?;
 
         Ok(PngHeader {
             width,
@@ -208,88 +660,1512 @@ impl<'a> TryFrom<&'a PngChunk> for PngHeader {
             bit_depth,
             colour_type,
             interlace_method,
+            compression_method,
+            filter_method,
         })
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Palette {
+    pub entries: Vec<[u8; 3]>,
+}
+
+impl Palette {
+    pub fn get(&self, index: u8) -> Option<[u8; 3]> {
+        self.entries.get(index as usize).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<'a> TryFrom<&'a PngChunk> for Palette {
+    type Error = PngError;
+
+    fn try_from(value: &'a PngChunk) -> Result<Self, Self::Error> {
+        if value.chunk_type != ChunkType::PLTE {
+            return Err(PngError::UnexpectedChunkType {
+                expected: ChunkType::PLTE,
+                was: value.chunk_type,
+            });
+        }
+
+        if value.data.is_empty()
+            || !value.data.len().is_multiple_of(3)
+            || value.data.len() > 256 * 3
+        {
+            return Err(PngError::MalformedChunk(ChunkType::PLTE));
+        }
+
+        let entries = value
+            .data
+            .chunks_exact(3)
+            .map(|entry| [entry[0], entry[1], entry[2]])
+            .collect();
+
+        Ok(Palette { entries })
+    }
+}
+
+/// Largest width or height the spec allows.
+const MAX_DIMENSION: u32 = 0x7fff_ffff;
+
+/// Largest chunk length the spec allows.
+pub(crate) const MAX_CHUNK_LENGTH: u32 = 0x7fff_ffff;
+
+/// Checks that neither dimension is zero or larger than the spec allows.
+pub(crate) fn check_dimensions(width: u32, height: u32) -> Result<(), PngError> {
+    if (1..=MAX_DIMENSION).contains(&width) && (1..=MAX_DIMENSION).contains(&height) {
+        Ok(())
+    } else {
+        Err(PngError::InvalidDimensions { width, height })
+    }
+}
+
+/// Checks that `bit_depth` is allowed for `colour_type`.
+pub(crate) fn check_bit_depth(
+    colour_type: ColourType,
+    bit_depth: BitDepth,
+) -> Result<(), PngError> {
+    match (colour_type, bit_depth) {
+        (
+            ColourType::Greyscale,
+            BitDepth::B1 | BitDepth::B2 | BitDepth::B4 | BitDepth::B8 | BitDepth::B16,
+        )
+        | (ColourType::Truecolour, BitDepth::B8 | BitDepth::B16)
+        | (ColourType::IndexedColour, BitDepth::B1 | BitDepth::B2 | BitDepth::B4 | BitDepth::B8)
+        | (ColourType::GreyscaleWithAlpha, BitDepth::B8 | BitDepth::B16)
+        | (ColourType::TruecolourWithAlpha, BitDepth::B8 | BitDepth::B16) => Ok(()),
+        (colour_type, bit_depth) => Err(PngError::InvalidBitDepthColourCombination {
+            colour_type,
+            bit_depth,
+        }),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PngFile {
     pub chunks: Vec<PngChunk>,
 }
 
+/// Limits for [`PngFile::from_reader_with`] and [`PngFile::from_bytes_with`]. The other parsing functions use
+/// the defaults.
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// Parsing fails with [`PngError::TooManyChunks`] after this many chunks. Even an empty chunk takes up
+    /// memory once parsed, so this bounds what a file made of millions of them can allocate.
+    pub max_chunks: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            max_chunks: 100_000,
+        }
+    }
+}
+
+impl ParseOptions {
+    /// The defaults: at most 100 000 chunks.
+    pub fn new() -> Self {
+        ParseOptions::default()
+    }
+
+    pub fn set_max_chunks(&mut self, limit: usize) -> &mut Self {
+        self.max_chunks = limit;
+        self
+    }
+}
+
+/// Checks that [`PngFile::validate_with`] makes on top of the structural ones.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidateOptions {
+    /// Rejects files whose gAMA or cHRM chunk disagrees with their sRGB chunk. Decoders are meant to prefer
+    /// sRGB, so such files usually render fine, but the mismatch suggests a broken conversion somewhere.
+    pub colour_chunk_conflicts: bool,
+}
+
+/// The result of a lenient parse: every chunk that could be read, and the problems found along the way.
+#[derive(Debug)]
+pub struct LenientPngFile {
+    pub png: PngFile,
+    /// Per-chunk problems in file order. If parsing stopped early, the reason is the last entry.
+    pub errors: Vec<PngError>,
+}
+
+/// Ancillary chunks that change how the image looks: transparency and the colour space chunks.
+pub(crate) const APPEARANCE_CHUNKS: [ChunkType; 6] = [
+    ChunkType::tRNS,
+    ChunkType::gAMA,
+    ChunkType::cHRM,
+    ChunkType::sRGB,
+    ChunkType::iCCP,
+    ChunkType::sBIT,
+];
+
+/// Whether stripping metadata keeps `chunk`: every critical chunk and the [`APPEARANCE_CHUNKS`].
+pub(crate) fn affects_appearance(chunk: &PngChunk) -> bool {
+    chunk.chunk_type.is_critical() || APPEARANCE_CHUNKS.contains(&chunk.chunk_type)
+}
+
+/// Chunks which the spec allows at most once per file.
+pub(crate) const SINGLE_INSTANCE_CHUNKS: [ChunkType; 14] = [
+    ChunkType::IHDR,
+    ChunkType::PLTE,
+    ChunkType::IEND,
+    ChunkType::cHRM,
+    ChunkType::gAMA,
+    ChunkType::iCCP,
+    ChunkType::sBIT,
+    ChunkType::sRGB,
+    ChunkType::bKGD,
+    ChunkType::hIST,
+    ChunkType::tRNS,
+    ChunkType::pHYs,
+    ChunkType::tIME,
+    crate::apng::ACTL,
+];
+
 impl PngFile {
+    /// The type, offset and declared data length of every chunk, in file order. See [`PngChunk::offset`].
+    pub fn chunk_layout(&self) -> impl Iterator<Item = (ChunkType, Option<u64>, u32)> + '_ {
+        self.chunks
+            .iter()
+            .map(|chunk| (chunk.chunk_type, chunk.offset(), chunk.length()))
+    }
+
+    #[deprecated(note = "panics for files without chunks, use `header_chunk` instead")]
     pub fn get_header_chunk(&self) -> &PngChunk {
         &self.chunks[0]
     }
 
+    /// The first chunk, which is IHDR in any valid file. `None` only when there are no chunks at all, as a
+    /// lenient parse can return.
+    pub fn header_chunk(&self) -> Option<&PngChunk> {
+        self.chunks.first()
+    }
+
     pub fn try_parse_header(&self) -> Result<PngHeader, PngError> {
-        PngHeader::try_from(self.get_header_chunk())
+        let chunk = self
+            .header_chunk()
+            .ok_or(PngError::MissingChunk(ChunkType::IHDR))?;
+        PngHeader::try_from(chunk)
     }
 
-    pub fn from_reader<R: std::io::Read>(reader: &mut R) -> Result<Self, PngError> {
-        let mut magic = [0u8; 8];
-        reader.read_exact(&mut magic)?;
+    /// Replaces the IHDR chunk with `header`, or inserts it at the start if the file has none. The image data
+    /// is left as is, so changing anything but metadata leaves it mismatched with the header.
+    pub fn replace_header(&mut self, header: PngHeader) {
+        let chunk = header.to_chunk();
 
-        if magic != MAGIC {
-            return Err(PngError::InvalidMagic);
+        match self.chunks.first_mut() {
+            Some(first) if first.chunk_type == ChunkType::IHDR => *first = chunk,
+            _ => self.chunks.insert(0, chunk),
         }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn from_reader<R: std::io::Read>(reader: &mut R) -> Result<Self, PngError> {
+        PngFile::from_reader_with(reader, &ParseOptions::default())
+    }
+
+    /// Like [`from_reader`](Self::from_reader), with the limits in `options`.
+    #[cfg(feature = "std")]
+    pub fn from_reader_with<R: std::io::Read>(
+        reader: &mut R,
+        options: &ParseOptions,
+    ) -> Result<Self, PngError> {
+        let chunks = parse_png(&mut crate::reader::IoSource::new(reader), options)?;
+        Ok(PngFile { chunks })
+    }
+
+    /// Parses every PNG in a stream of PNGs stored back to back, each with its own signature, until the reader
+    /// runs out.
+    #[cfg(feature = "std")]
+    pub fn read_all<R: std::io::Read>(reader: &mut R) -> Result<Vec<Self>, PngError> {
+        parse_png_sequence(&mut crate::reader::IoSource::new(reader))
+    }
+
+    /// Opens and parses the file at `path`. The file is buffered, since parsing makes several small reads per
+    /// chunk.
+    #[cfg(feature = "std")]
+    pub fn from_path<P: AsRef<std::path::Path>>(path: P) -> Result<Self, PngError> {
+        let file = std::fs::File::open(path)?;
+        PngFile::from_reader(&mut std::io::BufReader::new(file))
+    }
 
-        let mut chunks = Vec::new();
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PngError> {
+        PngFile::from_bytes_with(bytes, &ParseOptions::default())
+    }
+
+    /// Like [`from_bytes`](Self::from_bytes), with the limits in `options`.
+    pub fn from_bytes_with(bytes: &[u8], options: &ParseOptions) -> Result<Self, PngError> {
+        let chunks = parse_png(&mut SliceReader::new(bytes), options)?;
+        Ok(PngFile { chunks })
+    }
+
+    /// Like [`from_reader`](Self::from_reader), but fails with [`PngError::TrailingData`] if the reader has any
+    /// data left after IEND. This reads one byte past IEND when there is one.
+    #[cfg(feature = "std")]
+    pub fn from_reader_strict<R: std::io::Read>(reader: &mut R) -> Result<Self, PngError> {
+        parse_png_strict(&mut crate::reader::IoSource::new(reader))
+    }
+
+    /// Like [`from_bytes`](Self::from_bytes), but fails with [`PngError::TrailingData`] if anything follows IEND.
+    pub fn from_bytes_strict(bytes: &[u8]) -> Result<Self, PngError> {
+        parse_png_strict(&mut SliceReader::new(bytes))
+    }
+
+    /// Parses the PNG at the start of `bytes` and returns it with whatever follows its IEND chunk, such as
+    /// another PNG.
+    pub fn from_bytes_with_remainder(bytes: &[u8]) -> Result<(Self, &[u8]), PngError> {
+        let mut reader = SliceReader::new(bytes);
+        let chunks = parse_png(&mut reader, &ParseOptions::default())?;
+        Ok((PngFile { chunks }, reader.remaining()))
+    }
+
+    /// Parses as much of a damaged file as possible. Chunks with a bad CRC or a malformed header are kept and
+    /// their errors recorded, and parsing only stops at an IO error or the end of the input.
+    #[cfg(feature = "std")]
+    pub fn from_reader_lenient<R: std::io::Read>(reader: &mut R) -> LenientPngFile {
+        parse_png_lenient(&mut crate::reader::IoSource::new(reader))
+    }
+
+    pub fn from_bytes_lenient(bytes: &[u8]) -> LenientPngFile {
+        parse_png_lenient(&mut SliceReader::new(bytes))
+    }
+
+    /// Checks the chunk structure against the spec. Parsing is permissive, so this is where mangled files,
+    /// such as two PNGs concatenated together, are caught.
+    pub fn validate(&self) -> Result<(), PngError> {
+        self.validate_with(&ValidateOptions::default())
+    }
 
-        loop {
-            let chunk = parse_png_chunk(reader)?;
+    /// Like [`validate`](Self::validate), with the optional checks in `options`.
+    pub fn validate_with(&self, options: &ValidateOptions) -> Result<(), PngError> {
+        for (i, chunk) in self.chunks.iter().enumerate() {
             let chunk_type = chunk.chunk_type;
-            chunks.push(chunk);
 
-            if chunk_type == ChunkType::IEND {
-                break;
+            // Ancillary chunks can be safely ignored, but an unknown critical chunk may change how the image is
+            // meant to be rendered.
+            if let ChunkType::Unknown(bytes) = chunk_type {
+                if chunk_type.is_critical() {
+                    return Err(PngError::UnknownCriticalChunk(bytes));
+                }
+            }
+
+            if SINGLE_INSTANCE_CHUNKS.contains(&chunk_type)
+                && self.chunks[..i]
+                    .iter()
+                    .any(|previous| previous.chunk_type == chunk_type)
+            {
+                return Err(PngError::DuplicateChunk(chunk_type));
             }
         }
 
-        Ok(PngFile { chunks })
+        // Entries past what the bit depth can index are unreachable, which usually means the encoder wrote the
+        // palette for a different bit depth than the image data.
+        if let Some(palette) = self.palette()? {
+            let header = self.try_parse_header()?;
+            let max = 1 << header.bit_depth.bits();
+
+            if header.colour_type == ColourType::IndexedColour && palette.len() > max {
+                return Err(PngError::PaletteTooLargeForDepth {
+                    entries: palette.len(),
+                    max,
+                });
+            }
+        }
+
+        if options.colour_chunk_conflicts && self.rendering_intent()?.is_some() {
+            if self.gamma()?.is_some_and(|gamma| !gamma.matches_srgb()) {
+                return Err(PngError::ColourChunkConflict(ChunkType::gAMA));
+            }
+
+            if self
+                .chromaticities()?
+                .is_some_and(|chromaticities| !chromaticities.matches_srgb())
+            {
+                return Err(PngError::ColourChunkConflict(ChunkType::cHRM));
+            }
+        }
+
+        Ok(())
     }
 
-    fn image_data_chunks(&self) -> impl Iterator<Item = &PngChunk> {
+    /// Keeps only the chunks for which `predicate` returns true. Critical chunks are never dropped.
+    pub fn retain_chunks(&mut self, mut predicate: impl FnMut(&PngChunk) -> bool) {
         self.chunks
-            .iter()
-            .filter(|chunk| chunk.chunk_type == ChunkType::IDAT)
+            .retain(|chunk| chunk.chunk_type.is_critical() || predicate(chunk));
     }
 
-    pub fn decode_data(&self) -> Result<Vec<u8>, PngError> {
-        let mut buffer = Vec::new();
-        self.decode_data_to(&mut buffer)?;
-        Ok(buffer)
+    /// Drops every ancillary chunk except the ones needed to render the image correctly: tRNS and the colour
+    /// space chunks (gAMA, cHRM, sRGB, iCCP and sBIT).
+    pub fn strip_ancillary(&mut self) {
+        self.retain_chunks(affects_appearance);
     }
 
-    pub fn decode_data_to(&self, out: &mut Vec<u8>) -> Result<(), PngError> {
+    pub fn palette(&self) -> Result<Option<Palette>, PngError> {
+        self.find_chunk(ChunkType::PLTE)
+            .map(Palette::try_from)
+            .transpose()
+    }
+
+    /// Whether any pixel may be less than fully opaque, either through an alpha channel or a tRNS chunk. Only
+    /// looks at the chunks, so a file with an alpha channel that's opaque everywhere still counts.
+    pub fn has_transparency(&self) -> Result<bool, PngError> {
         let header = self.try_parse_header()?;
-        decode_data(&header, self.image_data_chunks(), out)
+
+        Ok(matches!(
+            header.colour_type,
+            ColourType::GreyscaleWithAlpha | ColourType::TruecolourWithAlpha
+        ) || self.find_chunk(ChunkType::tRNS).is_some())
     }
-}
 
-fn parse_png_chunk<R: std::io::Read>(reader: &mut R) -> Result<PngChunk, PngError> {
-    let length = reader.read_u32::<BigEndian>()?;
-    let mut chunk_type = [0u8; 4];
-    reader.read_exact(&mut chunk_type)?;
+    pub(crate) fn find_chunk(&self, chunk_type: ChunkType) -> Option<&PngChunk> {
+        self.chunks
+            .iter()
+            .find(|chunk| chunk.chunk_type == chunk_type)
+    }
 
-    let chunk_type = match &chunk_type {
-        b"IHDR" => ChunkType::IHDR,
-        b"PLTE" => ChunkType::PLTE,
-        b"IDAT" => ChunkType::IDAT,
-        b"IEND" => ChunkType::IEND,
-        otherwise => ChunkType::Unknown(*otherwise),
-    };
+    /// Returns the run of consecutive IDAT chunks, which must not be interrupted by other chunks.
+    pub(crate) fn image_data_chunks(&self) -> Result<&[PngChunk], PngError> {
+        image_data_run(&self.chunks)
+    }
 
-    let mut data = Vec::with_capacity(length as usize);
-    reader.take(length as u64).read_to_end(&mut data)?;
+    pub fn decode_data(&self) -> Result<Vec<u8>, PngError> {
+        self.decode_with(&DecodeOptions::default())
+    }
 
-    let mut crc = [0u8; 4];
-    reader.read_exact(&mut crc)?;
+    pub fn decode_data_to(&self, out: &mut Vec<u8>) -> Result<(), PngError> {
+        self.decode_with_to(&DecodeOptions::default(), out)
+    }
 
-    Ok(PngChunk {
-        length,
-        chunk_type,
+    pub fn decode_with(&self, options: &DecodeOptions) -> Result<Vec<u8>, PngError> {
+        let mut buffer = Vec::new();
+        self.decode_with_to(options, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    pub fn decode_with_to(
+        &self,
+        options: &DecodeOptions,
+        out: &mut Vec<u8>,
+    ) -> Result<(), PngError> {
+        let header = self.try_parse_header()?;
+        decode_data(&header, self.image_data_chunks()?.iter(), options, out)
+    }
+
+    /// The inflated image data with the filter type bytes and filtered scanlines left as they are, for
+    /// re-filtering or recompressing the image. [`decode_data`](Self::decode_data) is what reverses the filters.
+    pub fn filtered_stream(&self) -> Result<Vec<u8>, PngError> {
+        let header = self.try_parse_header()?;
+        filtered_stream(
+            &header,
+            self.image_data_chunks()?.iter(),
+            &DecodeOptions::default(),
+        )
+    }
+
+    /// Decodes the image into `out` as tiles of `tile_width` by `tile_height` pixels. See
+    /// [`decode_tiled`](crate::decoder::decode_tiled) for the layout.
+    pub fn decode_tiled(
+        &self,
+        tile_width: u32,
+        tile_height: u32,
+        out: &mut Vec<u8>,
+    ) -> Result<(), PngError> {
+        let header = self.try_parse_header()?;
+        decode_tiled(
+            &header,
+            self.image_data_chunks()?.iter(),
+            &DecodeOptions::default(),
+            tile_width,
+            tile_height,
+            out,
+        )
+    }
+
+    /// Decodes the image straight into `out`, one scanline at a time. See
+    /// [`decode_to_writer`](crate::decoder::decode_to_writer) for details.
+    #[cfg(feature = "std")]
+    pub fn decode_to_writer<W: std::io::Write>(&self, out: &mut W) -> Result<(), PngError> {
+        let header = self.try_parse_header()?;
+        crate::decoder::decode_to_writer(
+            &header,
+            self.image_data_chunks()?.iter(),
+            &DecodeOptions::default(),
+            out,
+        )
+    }
+
+    /// Decodes only the first `rows` scanlines into `out`. See
+    /// [`decode_first_rows`](crate::decoder::decode_first_rows) for details.
+    pub fn decode_first_rows(&self, rows: u32, out: &mut Vec<u8>) -> Result<(), PngError> {
+        let header = self.try_parse_header()?;
+        decode_first_rows(
+            &header,
+            self.image_data_chunks()?.iter(),
+            &DecodeOptions::default(),
+            rows,
+            out,
+        )
+    }
+
+    /// Decodes only the `width` by `height` region at `x`, `y` into `out`. See
+    /// [`decode_region`](crate::decoder::decode_region) for the layout.
+    pub fn decode_region(
+        &self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        out: &mut Vec<u8>,
+    ) -> Result<(), PngError> {
+        let header = self.try_parse_header()?;
+        decode_region(
+            &header,
+            self.image_data_chunks()?.iter(),
+            &DecodeOptions::default(),
+            x,
+            y,
+            width,
+            height,
+            out,
+        )
+    }
+
+    /// Decodes the image and converts it to 8-bit RGBA, expanding palettes and applying tRNS transparency.
+    pub fn to_rgba8(&self) -> Result<Vec<u8>, PngError> {
+        self.to_rgba8_with(&DecodeOptions::default())
+    }
+
+    pub fn to_rgba8_with(&self, options: &DecodeOptions) -> Result<Vec<u8>, PngError> {
+        let mut header = self.try_parse_header()?;
+        let decoded = self.decode_with(options)?;
+
+        // Decoding truncated image data may have produced fewer rows than the header has.
+        let scanline_length = header.scanline_length(header.width as usize);
+        if options.allow_truncated && scanline_length > 0 {
+            header.height = (decoded.len() / scanline_length) as u32;
+        }
+        to_rgba8(
+            &header,
+            &decoded,
+            self,
+            None,
+            options.palette_fallback,
+            Layout::Interleaved,
+        )
+    }
+
+    /// Like [`to_rgba8`](Self::to_rgba8), but keeps 16-bit samples at full precision. Samples of 8 bits or
+    /// fewer, including palette entries, are scaled up by bit replication, so 255 becomes 65535.
+    pub fn to_rgba16(&self) -> Result<Vec<u16>, PngError> {
+        let header = self.try_parse_header()?;
+        let decoded = self.decode_data()?;
+        to_rgba16(&header, &decoded, self)
+    }
+
+    /// Decodes only the alpha of each pixel, one byte per pixel, taking it from the alpha channel or from tRNS.
+    /// Returns `None` without decoding when [`has_transparency`](Self::has_transparency) says the image is
+    /// opaque.
+    pub fn alpha_mask(&self) -> Result<Option<Vec<u8>>, PngError> {
+        if !self.has_transparency()? {
+            return Ok(None);
+        }
+
+        let header = self.try_parse_header()?;
+        let decoded = self.decode_data()?;
+        alpha_mask(&header, &decoded, self).map(Some)
+    }
+
+    /// Decodes the image to one byte of luminance per pixel, weighting the colour channels by Rec. 709 and
+    /// dropping alpha.
+    pub fn to_greyscale8(&self) -> Result<Vec<u8>, PngError> {
+        Ok(rgba_to_luminance(&self.to_rgba8()?, false))
+    }
+
+    /// Like [`to_greyscale8`](Self::to_greyscale8), but keeps alpha as a second byte of each pixel.
+    pub fn to_greyscale_alpha8(&self) -> Result<Vec<u8>, PngError> {
+        Ok(rgba_to_luminance(&self.to_rgba8()?, true))
+    }
+
+    /// Like [`to_rgba8`](Self::to_rgba8), but with each channel in its own plane: the red samples of every
+    /// pixel, then the green, blue and alpha ones.
+    pub fn decode_planar(&self) -> Result<Vec<u8>, PngError> {
+        let header = self.try_parse_header()?;
+        let decoded = self.decode_data()?;
+        to_rgba8(&header, &decoded, self, None, None, Layout::Planar)
+    }
+
+    /// Like [`to_rgba8`](Self::to_rgba8), but expands indexed images with `palette` instead of the PLTE chunk.
+    /// Indices past the end of `palette` are an error. Other colour types ignore it.
+    pub fn to_rgba8_with_palette(&self, palette: &[[u8; 3]]) -> Result<Vec<u8>, PngError> {
+        let header = self.try_parse_header()?;
+        let decoded = self.decode_data()?;
+        to_rgba8(
+            &header,
+            &decoded,
+            self,
+            Some(palette),
+            None,
+            Layout::Interleaved,
+        )
+    }
+}
+
+/// Checks the signature and parses the chunks after it.
+pub(crate) fn parse_png<S, C>(source: &mut S, options: &ParseOptions) -> Result<Vec<C>, PngError>
+where
+    S: ChunkSource,
+    C: From<RawChunk<S::Data>>,
+{
+    let magic = source.read_array::<8>()?;
+
+    if magic != MAGIC {
+        return Err(PngError::InvalidMagic(magic));
+    }
+
+    parse_png_chunks(source, options)
+}
+
+/// Parses back-to-back PNGs until the input ends. Ending right after an IEND chunk is how the stream is meant
+/// to end, but ending anywhere else is an error.
+#[cfg(feature = "std")]
+fn parse_png_sequence<S: ChunkSource>(source: &mut S) -> Result<Vec<PngFile>, PngError> {
+    let mut pngs = Vec::new();
+
+    loop {
+        let magic = source.read_up_to(MAGIC.len())?;
+        let magic = magic.as_ref();
+
+        if magic.is_empty() {
+            return Ok(pngs);
+        }
+
+        if magic.len() < MAGIC.len() {
+            return Err(PngError::UnexpectedEof);
+        }
+
+        if magic != MAGIC {
+            return Err(PngError::InvalidMagic(magic.try_into().unwrap()));
+        }
+
+        let chunks = parse_png_chunks(source, &ParseOptions::default())?;
+        pngs.push(PngFile { chunks });
+    }
+}
+
+/// Parses the chunks following the signature, up to and including IEND.
+fn parse_png_chunks<S, C>(source: &mut S, options: &ParseOptions) -> Result<Vec<C>, PngError>
+where
+    S: ChunkSource,
+    C: From<RawChunk<S::Data>>,
+{
+    let mut chunks = Vec::new();
+
+    loop {
+        check_chunk_count(chunks.len(), options)?;
+
+        let chunk = parse_chunk(source)?;
+        let chunk_type = chunk.chunk_type;
+        chunks.push(C::from(chunk));
+
+        if chunk_type == ChunkType::IEND {
+            return Ok(chunks);
+        }
+    }
+}
+
+/// The chunk types of [`PngChunk`] and [`ChunkRef`], for code that works on either.
+pub(crate) trait HasChunkType {
+    fn chunk_type(&self) -> ChunkType;
+}
+
+impl HasChunkType for PngChunk {
+    fn chunk_type(&self) -> ChunkType {
+        self.chunk_type
+    }
+}
+
+/// Returns the run of consecutive IDAT chunks in `chunks`, which must not be interrupted by other chunks.
+pub(crate) fn image_data_run<C: HasChunkType>(chunks: &[C]) -> Result<&[C], PngError> {
+    let is_image_data = |chunk: &C| chunk.chunk_type() == ChunkType::IDAT;
+
+    let start = match chunks.iter().position(is_image_data) {
+        Some(start) => start,
+        None => return Ok(&[]),
+    };
+
+    let length = chunks[start..]
+        .iter()
+        .take_while(|chunk| is_image_data(chunk))
+        .count();
+
+    if chunks[start + length..].iter().any(is_image_data) {
+        return Err(PngError::NonContiguousIdat);
+    }
+
+    Ok(&chunks[start..start + length])
+}
+
+/// Fails if another chunk would go over the limit in `options`, given that `parsed` chunks have been parsed.
+fn check_chunk_count(parsed: usize, options: &ParseOptions) -> Result<(), PngError> {
+    if parsed >= options.max_chunks {
+        return Err(PngError::TooManyChunks {
+            limit: options.max_chunks,
+        });
+    }
+
+    Ok(())
+}
+
+fn parse_png_strict<S: ChunkSource>(source: &mut S) -> Result<PngFile, PngError> {
+    let chunks = parse_png(source, &ParseOptions::default())?;
+
+    if !source.is_at_end()? {
+        return Err(PngError::TrailingData);
+    }
+
+    Ok(PngFile { chunks })
+}
+
+fn parse_png_lenient<S: ChunkSource>(source: &mut S) -> LenientPngFile {
+    let options = ParseOptions::default();
+    let mut chunks = Vec::new();
+    let mut errors = Vec::new();
+
+    match source.read_array::<8>() {
+        Ok(magic) => {
+            // A damaged signature doesn't prevent reading the chunks after it.
+            if magic != MAGIC {
+                errors.push(PngError::InvalidMagic(magic));
+            }
+
+            loop {
+                if let Err(err) = check_chunk_count(chunks.len(), &options) {
+                    errors.push(err);
+                    break;
+                }
+
+                let chunk = match parse_chunk(source) {
+                    Ok(chunk) => PngChunk::from(chunk),
+                    Err(err) => {
+                        errors.push(err);
+                        break;
+                    }
+                };
+
+                if !chunk.is_crc_valid() {
+                    errors.push(PngError::CrcMismatch {
+                        chunk_type: chunk.chunk_type,
+                        stored: chunk.crc(),
+                        computed: chunk_crc(chunk.chunk_type.into(), &chunk.data),
+                    });
+                }
+
+                if chunk.chunk_type == ChunkType::IHDR {
+                    if let Err(err) = PngHeader::try_from(&chunk) {
+                        errors.push(err);
+                    }
+                }
+
+                let chunk_type = chunk.chunk_type;
+                chunks.push(chunk);
+
+                if chunk_type == ChunkType::IEND {
+                    break;
+                }
+            }
+        }
+        Err(err) => errors.push(err),
+    }
+
+    LenientPngFile {
+        png: PngFile { chunks },
+        errors,
+    }
+}
+
+/// A chunk as read from a [`ChunkSource`], with its data borrowed or owned depending on the source.
+pub(crate) struct RawChunk<D> {
+    pub offset: u64,
+    pub length: u32,
+    pub chunk_type: ChunkType,
+    pub data: D,
+    pub crc: [u8; 4],
+}
+
+pub(crate) fn parse_chunk<S: ChunkSource>(source: &mut S) -> Result<RawChunk<S::Data>, PngError> {
+    let offset = source.position();
+
+    // Length and type are read together to halve the number of reads per chunk.
+    let [l0, l1, l2, l3, t0, t1, t2, t3] = source.read_array::<8>()?;
+    let length = u32::from_be_bytes([l0, l1, l2, l3]);
+    let chunk_type = ChunkType::from([t0, t1, t2, t3]);
+
+    // Reading a bogus IEND length as data would swallow whatever follows the chunk as its data and CRC.
+    if chunk_type == ChunkType::IEND && length != 0 {
+        return Err(PngError::MalformedIend { length });
+    }
+
+    if length > MAX_CHUNK_LENGTH {
+        return Err(PngError::InvalidChunkLength { chunk_type, length });
+    }
+
+    let data = source.read_up_to(length as usize)?;
+
+    // Telling a cut-off chunk body apart from a missing CRC helps with partial downloads.
+    if data.as_ref().len() != length as usize {
+        return Err(PngError::TruncatedChunk {
+            chunk_type,
+            expected: length,
+            got: data.as_ref().len() as u32,
+        });
+    }
+
+    let crc = source.read_array::<4>()?;
+
+    Ok(RawChunk {
+        offset,
+        length,
+        chunk_type,
         data,
         crc,
     })
 }
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::test_util::{ihdr, parse, png_bytes, zlib};
+
+    fn chunk_types(png: &PngFile) -> Vec<ChunkType> {
+        png.chunks.iter().map(|chunk| chunk.chunk_type).collect()
+    }
+
+    #[test]
+    fn exposes_compression_and_filter_methods() {
+        let header = PngHeader::from_data(&ihdr(1, 1, 8, 0)).unwrap();
+
+        assert_eq!(header.compression_method(), 0);
+        assert_eq!(header.filter_method(), 0);
+        assert!(format!("{:?}", header).contains("compression_method: 0, filter_method: 0"));
+
+        for (offset, expected) in [
+            (10, PngError::UnknownCompressionMethod(1)),
+            (11, PngError::UnknownFilterMethod(1)),
+        ] {
+            let mut data = ihdr(1, 1, 8, 0);
+            data[offset] = 1;
+
+            assert_eq!(
+                PngHeader::from_data(&data).unwrap_err().to_string(),
+                expected.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn serializes_header() {
+        let png = PngFile::from_reader(&mut File::open("./code.png").unwrap()).unwrap();
+        let header = png.try_parse_header().unwrap();
+
+        assert_eq!(header.to_chunk(), *png.header_chunk().unwrap());
+        assert_eq!(PngHeader::try_from(&header.to_chunk()).unwrap(), header);
+    }
+
+    #[test]
+    fn replaces_header() {
+        let mut png = parse(&[
+            (b"IHDR", &ihdr(3, 2, 8, 0)),
+            (b"IDAT", &zlib(&[0, 1, 2, 3, 0, 4, 5, 6])),
+            (b"IEND", &[]),
+        ]);
+        let header = png.try_parse_header().unwrap();
+
+        png.replace_header(
+            PngHeader::new(
+                6,
+                1,
+                header.bit_depth(),
+                header.colour_type(),
+                InterlaceMethod::None,
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(png.chunks.len(), 3);
+        assert!(png.header_chunk().unwrap().is_crc_valid());
+        assert_eq!(png.try_parse_header().unwrap().width, 6);
+        assert!(matches!(
+            png.decode_data(),
+            Err(PngError::SurplusImageData { expected: 7 })
+        ));
+
+        assert!(matches!(
+            PngHeader::new(
+                1,
+                1,
+                BitDepth::B16,
+                ColourType::IndexedColour,
+                InterlaceMethod::None
+            ),
+            Err(PngError::InvalidBitDepthColourCombination { .. })
+        ));
+    }
+
+    #[test]
+    fn strips_ancillary_chunks() {
+        let mut png = PngFile::from_reader(&mut File::open("./code.png").unwrap()).unwrap();
+        let expected = png.to_rgba8().unwrap();
+
+        png.strip_ancillary();
+
+        assert_eq!(
+            chunk_types(&png),
+            vec![
+                ChunkType::IHDR,
+                ChunkType::sRGB,
+                ChunkType::gAMA,
+                ChunkType::IDAT,
+                ChunkType::IEND
+            ]
+        );
+
+        let mut written = Vec::new();
+        png.write_to(&mut written).unwrap();
+        let reparsed = PngFile::from_reader(&mut written.as_slice()).unwrap();
+        assert_eq!(reparsed.to_rgba8().unwrap(), expected);
+    }
+
+    #[test]
+    fn never_drops_critical_chunks() {
+        let mut png = PngFile::from_reader(&mut File::open("./code.png").unwrap()).unwrap();
+
+        png.retain_chunks(|_| false);
+
+        assert_eq!(
+            chunk_types(&png),
+            vec![ChunkType::IHDR, ChunkType::IDAT, ChunkType::IEND]
+        );
+    }
+
+    #[test]
+    fn computes_bits_per_pixel() {
+        for (bit_depth, colour_type, bits_per_pixel) in
+            [(1, 0, 1), (4, 3, 4), (8, 2, 24), (16, 4, 32), (16, 6, 64)]
+        {
+            let png = parse(&[
+                (b"IHDR", &ihdr(1, 1, bit_depth, colour_type)),
+                (b"IEND", &[]),
+            ]);
+            let header = png.try_parse_header().unwrap();
+
+            assert_eq!(header.bit_depth().bits(), bit_depth);
+            assert_eq!(header.bits_per_pixel(), bits_per_pixel);
+        }
+    }
+
+    #[test]
+    fn exposes_interlace_method() {
+        let png = parse(&[(b"IHDR", &ihdr(1, 1, 8, 0)), (b"IEND", &[])]);
+        let header = png.try_parse_header().unwrap();
+        assert_eq!(header.interlace_method(), InterlaceMethod::None);
+        assert!(!header.is_interlaced());
+
+        let mut interlaced = ihdr(1, 1, 8, 0);
+        interlaced[12] = 1;
+        let png = parse(&[(b"IHDR", &interlaced), (b"IEND", &[])]);
+        let header = png.try_parse_header().unwrap();
+        assert_eq!(header.interlace_method(), InterlaceMethod::Adam7);
+        assert!(header.is_interlaced());
+    }
+
+    #[test]
+    fn parses_palette() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 3)),
+            (b"PLTE", &[255, 0, 0, 0, 0, 255]),
+            (b"IEND", &[]),
+        ]);
+        let palette = png.palette().unwrap().unwrap();
+
+        assert_eq!(palette.entries, vec![[255, 0, 0], [0, 0, 255]]);
+        assert_eq!(palette.get(1), Some([0, 0, 255]));
+        assert_eq!(palette.get(2), None);
+
+        let no_palette = parse(&[(b"IHDR", &ihdr(1, 1, 8, 0)), (b"IEND", &[])]);
+        assert_eq!(no_palette.palette().unwrap(), None);
+
+        for data in [&[][..], &[1, 2, 3, 4], &[0; 257 * 3]] {
+            let png = parse(&[
+                (b"IHDR", &ihdr(1, 1, 8, 3)),
+                (b"PLTE", data),
+                (b"IEND", &[]),
+            ]);
+            assert!(matches!(
+                png.palette(),
+                Err(PngError::MalformedChunk(ChunkType::PLTE))
+            ));
+        }
+    }
+
+    #[test]
+    fn computes_decoded_size() {
+        for (width, height, bit_depth, colour_type, decoded_len) in [
+            (13, 11, 4, 3, 7 * 11),
+            (3, 2, 1, 0, 2),
+            (105, 105, 8, 6, 105 * 105 * 4),
+            (2, 3, 16, 2, 2 * 3 * 6),
+        ] {
+            let png = parse(&[
+                (b"IHDR", &ihdr(width, height, bit_depth, colour_type)),
+                (b"IEND", &[]),
+            ]);
+            let header = png.try_parse_header().unwrap();
+
+            assert_eq!(header.pixel_count(), width as u64 * height as u64);
+            assert_eq!(header.decoded_len(), Some(decoded_len));
+        }
+
+        let huge = parse(&[
+            (b"IHDR", &ihdr(0x7fff_ffff, 0x7fff_ffff, 16, 6)),
+            (b"IEND", &[]),
+        ]);
+        let header = huge.try_parse_header().unwrap();
+
+        assert_eq!(header.pixel_count(), 0x7fff_ffff * 0x7fff_ffff);
+        assert_eq!(header.decoded_len(), None);
+    }
+
+    #[test]
+    fn reports_truncated_input() {
+        let bytes = png_bytes(&[(b"IHDR", &ihdr(1, 1, 8, 0)), (b"IEND", &[])]);
+
+        assert!(matches!(
+            PngFile::from_bytes(&bytes[..bytes.len() - 2]),
+            Err(PngError::UnexpectedEof)
+        ));
+
+        // Ending inside the IHDR data, rather than in its CRC.
+        let cut = &bytes[..8 + 8 + 5];
+        for result in [PngFile::from_bytes(cut), PngFile::from_reader(&mut &*cut)] {
+            assert!(matches!(
+                result,
+                Err(PngError::TruncatedChunk {
+                    chunk_type: ChunkType::IHDR,
+                    expected: 13,
+                    got: 5
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_duplicate_single_instance_chunks() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"gAMA", &45455u32.to_be_bytes()),
+            (b"gAMA", &100000u32.to_be_bytes()),
+            (b"IDAT", &zlib(&[0, 0])),
+            (b"IEND", &[]),
+        ]);
+
+        assert!(matches!(
+            png.validate(),
+            Err(PngError::DuplicateChunk(ChunkType::gAMA))
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_critical_chunks() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"quUX", b"harmless"),
+            (b"IDAT", &zlib(&[0, 0])),
+            (b"IEND", &[]),
+        ]);
+        assert!(png.validate().is_ok());
+
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"QUUX", b"important"),
+            (b"IDAT", &zlib(&[0, 0])),
+            (b"IEND", &[]),
+        ]);
+        assert!(matches!(
+            png.validate(),
+            Err(PngError::UnknownCriticalChunk(chunk_type)) if &chunk_type == b"QUUX"
+        ));
+    }
+
+    #[test]
+    fn clones_independently() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"tEXt", b"Comment\0hello"),
+            (b"IDAT", &zlib(&[0, 0])),
+            (b"IEND", &[]),
+        ]);
+        let mut copy = png.clone();
+        assert_eq!(copy, png);
+        assert_eq!(
+            copy.try_parse_header().unwrap(),
+            png.try_parse_header().unwrap()
+        );
+
+        copy.strip_ancillary();
+        assert_ne!(copy, png);
+        assert_eq!(png.chunks.len(), 4);
+    }
+
+    #[test]
+    fn parses_from_path() {
+        let from_path = PngFile::from_path("./code.png").unwrap();
+        let from_reader = PngFile::from_reader(&mut File::open("./code.png").unwrap()).unwrap();
+        assert_eq!(from_path, from_reader);
+
+        assert!(matches!(
+            PngFile::from_path("./does-not-exist.png"),
+            Err(PngError::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn converts_into_io_errors() {
+        use std::io::{self, ErrorKind};
+
+        let read = |bytes: &[u8]| -> io::Result<PngFile> { Ok(PngFile::from_bytes(bytes)?) };
+
+        let not_found = io::Error::from(PngFile::from_path("./does-not-exist.png").unwrap_err());
+        assert_eq!(not_found.kind(), ErrorKind::NotFound);
+
+        assert_eq!(
+            read(&MAGIC[..4]).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+
+        let invalid = read(&[0; 8]).unwrap_err();
+        assert_eq!(invalid.kind(), ErrorKind::InvalidData);
+        assert!(matches!(
+            invalid.into_inner().unwrap().downcast_ref::<PngError>(),
+            Some(PngError::InvalidMagic(magic)) if *magic == [0; 8]
+        ));
+    }
+
+    #[test]
+    fn limits_chunk_count() {
+        let header = ihdr(1, 1, 8, 0);
+        let text: &[u8] = b"a\0";
+        let mut chunks: Vec<(&[u8; 4], &[u8])> = vec![(b"IHDR", &header)];
+        chunks.extend([(b"tEXt", text); 8]);
+        chunks.push((b"IEND", &[]));
+        let bytes = png_bytes(&chunks);
+
+        let mut options = ParseOptions::new();
+        options.set_max_chunks(10);
+        assert_eq!(
+            PngFile::from_bytes_with(&bytes, &options)
+                .unwrap()
+                .chunks
+                .len(),
+            10
+        );
+
+        options.set_max_chunks(9);
+        assert!(matches!(
+            PngFile::from_bytes_with(&bytes, &options),
+            Err(PngError::TooManyChunks { limit: 9 })
+        ));
+        assert!(matches!(
+            PngFile::from_reader_with(&mut bytes.as_slice(), &options),
+            Err(PngError::TooManyChunks { limit: 9 })
+        ));
+
+        // A flood of empty chunks stops at the default limit rather than parsing them all.
+        let mut flood = png_bytes(&[(b"IHDR", &ihdr(1, 1, 8, 0))]);
+        let empty = png_bytes(&[(b"zzZz", &[])]);
+        for _ in 0..100_000 {
+            flood.extend_from_slice(&empty[MAGIC.len()..]);
+        }
+        assert!(matches!(
+            PngFile::from_bytes(&flood),
+            Err(PngError::TooManyChunks { limit: 100_000 })
+        ));
+        assert!(matches!(
+            PngFile::from_bytes_lenient(&flood).errors.last(),
+            Some(PngError::TooManyChunks { .. })
+        ));
+    }
+
+    #[test]
+    fn reports_the_start_of_non_png_input() {
+        let jpeg = [0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, b'J', b'F', b'I', b'F'];
+        let err = PngFile::from_bytes(&jpeg).unwrap_err();
+
+        assert!(matches!(err, PngError::InvalidMagic(magic) if magic == jpeg[..8]));
+        assert_eq!(
+            err.to_string(),
+            "not a PNG (starts with FF D8 FF E0 00 10 4A 46)"
+        );
+    }
+
+    #[test]
+    fn handles_trailing_data() {
+        let first = png_bytes(&[(b"IHDR", &ihdr(1, 1, 8, 0)), (b"IEND", &[])]);
+        let second = png_bytes(&[(b"IHDR", &ihdr(2, 2, 8, 0)), (b"IEND", &[])]);
+        let concatenated = [first.as_slice(), &second].concat();
+
+        let (png, rest) = PngFile::from_bytes_with_remainder(&concatenated).unwrap();
+        assert_eq!(png.try_parse_header().unwrap().width, 1);
+        assert_eq!(rest, second);
+
+        let (png, rest) = PngFile::from_bytes_with_remainder(rest).unwrap();
+        assert_eq!(png.try_parse_header().unwrap().width, 2);
+        assert!(rest.is_empty());
+
+        assert!(PngFile::from_bytes(&concatenated).is_ok());
+        assert!(PngFile::from_bytes_strict(&first).is_ok());
+        assert!(matches!(
+            PngFile::from_bytes_strict(&concatenated),
+            Err(PngError::TrailingData)
+        ));
+        assert!(PngFile::from_reader_strict(&mut first.as_slice()).is_ok());
+        assert!(matches!(
+            PngFile::from_reader_strict(&mut [first.as_slice(), &[0]].concat().as_slice()),
+            Err(PngError::TrailingData)
+        ));
+    }
+
+    #[test]
+    fn rejects_non_empty_iend() {
+        let bytes = png_bytes(&[(b"IHDR", &ihdr(1, 1, 8, 0)), (b"IEND", &[0; 4])]);
+
+        assert!(matches!(
+            PngFile::from_bytes(&bytes),
+            Err(PngError::MalformedIend { length: 4 })
+        ));
+        assert!(matches!(
+            PngFile::from_bytes_lenient(&bytes).errors.as_slice(),
+            [PngError::MalformedIend { length: 4 }]
+        ));
+    }
+
+    #[test]
+    fn records_chunk_offsets() {
+        let bytes = std::fs::read("./code.png").unwrap();
+
+        for png in [
+            PngFile::from_bytes(&bytes).unwrap(),
+            PngFile::from_reader(&mut bytes.as_slice()).unwrap(),
+        ] {
+            // Chunks follow each other directly, each with 12 bytes of length, type and CRC around the data.
+            let mut expected_offset = MAGIC.len() as u64;
+            for (chunk_type, offset, length) in png.chunk_layout() {
+                assert_eq!(offset, Some(expected_offset));
+
+                let start = expected_offset as usize;
+                assert_eq!(bytes[start + 4..start + 8], <[u8; 4]>::from(chunk_type));
+                expected_offset += 12 + length as u64;
+            }
+            assert_eq!(expected_offset, bytes.len() as u64);
+        }
+
+        // Built chunks have no offset, but still compare equal to parsed ones.
+        let png = PngFile::from_bytes(&bytes).unwrap();
+        let header = png.try_parse_header().unwrap().to_chunk();
+        assert_eq!(header.offset(), None);
+        assert_eq!(header, *png.header_chunk().unwrap());
+    }
+
+    #[test]
+    fn reads_concatenated_pngs() {
+        let first = png_bytes(&[(b"IHDR", &ihdr(1, 1, 8, 0)), (b"IEND", &[])]);
+        let second = png_bytes(&[(b"IHDR", &ihdr(2, 2, 8, 0)), (b"IEND", &[])]);
+        let concatenated = [first.as_slice(), &second].concat();
+
+        let pngs = PngFile::read_all(&mut concatenated.as_slice()).unwrap();
+        let widths = pngs
+            .iter()
+            .map(|png| png.try_parse_header().unwrap().width)
+            .collect::<Vec<_>>();
+        assert_eq!(widths, [1, 2]);
+
+        assert!(PngFile::read_all(&mut [].as_slice()).unwrap().is_empty());
+
+        let truncated = &concatenated[..concatenated.len() - 1];
+        assert!(matches!(
+            PngFile::read_all(&mut &*truncated),
+            Err(PngError::UnexpectedEof)
+        ));
+        assert!(matches!(
+            PngFile::read_all(&mut [first.as_slice(), &MAGIC[..3]].concat().as_slice()),
+            Err(PngError::UnexpectedEof)
+        ));
+        assert!(matches!(
+            PngFile::read_all(&mut [first.as_slice(), &[0; 8]].concat().as_slice()),
+            Err(PngError::InvalidMagic(magic)) if magic == [0; 8]
+        ));
+    }
+
+    #[test]
+    fn detects_transparency() {
+        for (colour_type, transparency, expected) in [
+            (0, None, false),
+            (0, Some(&[0, 7][..]), true),
+            (2, None, false),
+            (4, None, true),
+            (6, None, true),
+        ] {
+            let header = ihdr(1, 1, 8, colour_type);
+            let mut chunks: Vec<(&[u8; 4], &[u8])> = vec![(b"IHDR", &header)];
+            chunks.extend(transparency.map(|data| (b"tRNS", data)));
+            chunks.push((b"IEND", &[]));
+
+            assert_eq!(parse(&chunks).has_transparency().unwrap(), expected);
+        }
+
+        let indexed = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 3)),
+            (b"PLTE", &[0, 0, 0]),
+            (b"tRNS", &[0]),
+            (b"IEND", &[]),
+        ]);
+        assert!(indexed.has_transparency().unwrap());
+    }
+
+    #[test]
+    fn reads_chunk_type_property_bits() {
+        let properties = |chunk_type: ChunkType| {
+            (
+                chunk_type.is_critical(),
+                chunk_type.is_private(),
+                chunk_type.is_reserved_bit_valid(),
+                chunk_type.is_safe_to_copy(),
+            )
+        };
+
+        assert_eq!(properties(ChunkType::IDAT), (true, false, true, false));
+        assert_eq!(properties(ChunkType::tEXt), (false, false, true, true));
+        assert_eq!(
+            properties(ChunkType::Unknown(*b"prVt")),
+            (false, true, true, true)
+        );
+        assert_eq!(
+            properties(ChunkType::Unknown(*b"pRvT")),
+            (false, false, false, false)
+        );
+    }
+
+    #[test]
+    fn recognises_registered_chunk_types() {
+        for (bytes, chunk_type) in [
+            (*b"tRNS", ChunkType::tRNS),
+            (*b"iTXt", ChunkType::iTXt),
+            (*b"eXIf", ChunkType::eXIf),
+            (*b"acTL", ChunkType::Unknown(*b"acTL")),
+        ] {
+            assert_eq!(ChunkType::from(bytes), chunk_type);
+            assert_eq!(<[u8; 4]>::from(chunk_type), bytes);
+        }
+    }
+
+    #[test]
+    fn rejects_palettes_too_large_for_bit_depth() {
+        let palette = |entries: usize| vec![0u8; entries * 3];
+        let image_data = zlib(&[0, 0]);
+
+        for (bit_depth, colour_type, entries, expected_max) in [
+            (2, 3, 4, None),
+            (2, 3, 5, Some(4)),
+            (1, 3, 3, Some(2)),
+            (8, 3, 256, None),
+            // Only indexed images index into the palette; truecolour ones just suggest it.
+            (8, 2, 256, None),
+        ] {
+            let header = ihdr(1, 1, bit_depth, colour_type);
+            let palette = palette(entries);
+            let png = parse(&[
+                (b"IHDR", &header),
+                (b"PLTE", &palette),
+                (b"IDAT", &image_data),
+                (b"IEND", &[]),
+            ]);
+
+            match expected_max {
+                Some(expected_max) => assert!(matches!(
+                    png.validate(),
+                    Err(PngError::PaletteTooLargeForDepth { entries: e, max })
+                        if e == entries && max == expected_max
+                )),
+                None => assert!(png.validate().is_ok()),
+            }
+        }
+    }
+
+    #[test]
+    fn detects_colour_chunk_conflicts() {
+        let options = ValidateOptions {
+            colour_chunk_conflicts: true,
+        };
+        let linear_chromaticities = [0u8; 32];
+        let header = ihdr(1, 1, 8, 0);
+        let image_data = zlib(&[0, 0]);
+
+        for (gamma, chromaticities, expected) in [
+            (45455u32, None, None),
+            (45454, None, None),
+            (100000, None, Some(ChunkType::gAMA)),
+            (45455, Some(&linear_chromaticities), Some(ChunkType::cHRM)),
+        ] {
+            let gamma = gamma.to_be_bytes();
+            let mut chunks: Vec<(&[u8; 4], &[u8])> =
+                vec![(b"IHDR", &header), (b"sRGB", &[0]), (b"gAMA", &gamma)];
+            chunks.extend(chromaticities.map(|data| (b"cHRM", &data[..])));
+            chunks.extend([(b"IDAT", &image_data[..]), (b"IEND", &[])]);
+            let png = parse(&chunks);
+
+            assert!(png.validate().is_ok());
+
+            match expected {
+                Some(conflicting) => assert!(matches!(
+                    png.validate_with(&options),
+                    Err(PngError::ColourChunkConflict(chunk_type)) if chunk_type == conflicting
+                )),
+                None => assert!(png.validate_with(&options).is_ok()),
+            }
+        }
+
+        // Without sRGB, any gamma is fine.
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"gAMA", &100000u32.to_be_bytes()),
+            (b"IDAT", &zlib(&[0, 0])),
+            (b"IEND", &[]),
+        ]);
+        assert!(png.validate_with(&options).is_ok());
+    }
+
+    #[test]
+    fn allows_repeated_multi_instance_chunks() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"tEXt", b"Comment\0one"),
+            (b"tEXt", b"Comment\0two"),
+            (b"IDAT", &zlib(&[0, 0])),
+            (b"IEND", &[]),
+        ]);
+
+        assert!(png.validate().is_ok());
+        assert!(PngFile::from_reader(&mut File::open("./FL.png").unwrap())
+            .unwrap()
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn collects_errors_in_lenient_mode() {
+        let mut bytes = png_bytes(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"tEXt", b"Comment\0damaged"),
+            (b"IDAT", &zlib(&[0, 0])),
+            (b"IEND", &[]),
+        ]);
+        // Flip a bit in the tEXt payload, and cut the file off in the middle of IEND.
+        bytes[8 + 25 + 8] ^= 1;
+        bytes.truncate(bytes.len() - 6);
+
+        let lenient = PngFile::from_bytes_lenient(&bytes);
+
+        assert_eq!(
+            chunk_types(&lenient.png),
+            vec![ChunkType::IHDR, ChunkType::tEXt, ChunkType::IDAT]
+        );
+        assert!(matches!(
+            lenient.errors.as_slice(),
+            [
+                PngError::CrcMismatch {
+                    chunk_type: ChunkType::tEXt,
+                    ..
+                },
+                PngError::UnexpectedEof
+            ]
+        ));
+        assert_eq!(lenient.png.decode_data().unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn accessors_report_missing_header_of_empty_lenient_result() {
+        let lenient = PngFile::from_bytes_lenient(&MAGIC);
+        let png = &lenient.png;
+        let missing_header = |result: Result<_, PngError>| {
+            matches!(result, Err(PngError::MissingChunk(ChunkType::IHDR)))
+        };
+
+        assert!(png.chunks.is_empty());
+        assert!(png.header_chunk().is_none());
+        assert!(missing_header(png.try_parse_header().map(|_| ())));
+        assert!(missing_header(png.has_transparency().map(|_| ())));
+        assert!(missing_header(png.decode_data().map(|_| ())));
+        assert!(missing_header(png.to_rgba8().map(|_| ())));
+        assert!(missing_header(png.alpha_mask().map(|_| ())));
+        assert!(missing_header(
+            crate::apng::ApngDecoder::new(png).map(|_| ())
+        ));
+        assert!(!png.decodability().can_decode());
+    }
+
+    #[test]
+    fn lenient_mode_accepts_valid_files() {
+        let lenient = PngFile::from_reader_lenient(&mut File::open("./FL.png").unwrap());
+
+        assert!(lenient.errors.is_empty());
+        assert_eq!(
+            lenient.png.chunks.last().unwrap().chunk_type,
+            ChunkType::IEND
+        );
+    }
+
+    #[test]
+    fn concatenates_consecutive_idat_chunks() {
+        let data = zlib(&[0, 1, 2, 0, 3, 4]);
+        let (first, second) = data.split_at(data.len() / 2);
+
+        let png = parse(&[
+            (b"IHDR", &ihdr(2, 2, 8, 0)),
+            (b"IDAT", first),
+            (b"IDAT", second),
+            (b"IEND", &[]),
+        ]);
+
+        assert_eq!(png.decode_data().unwrap(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn rejects_non_contiguous_idat_chunks() {
+        let data = zlib(&[0, 1, 2, 0, 3, 4]);
+        let (first, second) = data.split_at(data.len() / 2);
+
+        let png = parse(&[
+            (b"IHDR", &ihdr(2, 2, 8, 0)),
+            (b"IDAT", first),
+            (b"tEXt", b"Comment\0interloper"),
+            (b"IDAT", second),
+            (b"IEND", &[]),
+        ]);
+
+        assert!(matches!(
+            png.decode_data(),
+            Err(PngError::NonContiguousIdat)
+        ));
+    }
+}
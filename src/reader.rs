@@ -0,0 +1,135 @@
+use alloc::vec::Vec;
+
+use crate::png_parser::PngError;
+
+/// A cursor over a byte slice, used instead of `std::io` so that parsing works without `std`.
+pub(crate) struct SliceReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> SliceReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        SliceReader { data, position: 0 }
+    }
+
+    /// The bytes that haven't been read yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], PngError> {
+        if self.data.len() < length {
+            return Err(PngError::UnexpectedEof);
+        }
+
+        let (bytes, rest) = self.data.split_at(length);
+        self.data = rest;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], PngError> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.read_bytes(N)?);
+        Ok(array)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, PngError> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, PngError> {
+        Ok(u16::from_be_bytes(self.read_array()?))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, PngError> {
+        Ok(u32::from_be_bytes(self.read_array()?))
+    }
+}
+
+/// Where chunks are parsed from: a byte slice, or with the `std` feature any `std::io::Read`.
+pub(crate) trait ChunkSource {
+    /// Bytes as returned by [`read_up_to`](Self::read_up_to): borrowed from a slice, owned from a reader.
+    type Data: AsRef<[u8]> + Into<Vec<u8>>;
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], PngError>;
+
+    /// Reads `length` bytes, or fewer if the input ends first.
+    fn read_up_to(&mut self, length: usize) -> Result<Self::Data, PngError>;
+
+    /// Number of bytes read so far.
+    fn position(&self) -> u64;
+
+    /// Whether the input has ended. May consume a byte if it hasn't.
+    fn is_at_end(&mut self) -> Result<bool, PngError> {
+        Ok(self.read_up_to(1)?.as_ref().is_empty())
+    }
+}
+
+impl<'a> ChunkSource for SliceReader<'a> {
+    type Data = &'a [u8];
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], PngError> {
+        SliceReader::read_array(self)
+    }
+
+    fn read_up_to(&mut self, length: usize) -> Result<&'a [u8], PngError> {
+        let length = length.min(self.data.len());
+        self.read_bytes(length)
+    }
+
+    fn position(&self) -> u64 {
+        self.position as u64
+    }
+}
+
+#[cfg(feature = "std")]
+pub(crate) struct IoSource<'a, R> {
+    reader: &'a mut R,
+    position: u64,
+}
+
+#[cfg(feature = "std")]
+impl<'a, R> IoSource<'a, R> {
+    pub fn new(reader: &'a mut R) -> Self {
+        IoSource {
+            reader,
+            position: 0,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, R: std::io::Read> ChunkSource for IoSource<'a, R> {
+    type Data = Vec<u8>;
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], PngError> {
+        let mut array = [0u8; N];
+        // Reported the same way as running out of a slice, rather than as an IO error.
+        self.reader
+            .read_exact(&mut array)
+            .map_err(|err| match err.kind() {
+                std::io::ErrorKind::UnexpectedEof => PngError::UnexpectedEof,
+                _ => err.into(),
+            })?;
+        self.position += N as u64;
+        Ok(array)
+    }
+
+    fn read_up_to(&mut self, length: usize) -> Result<Vec<u8>, PngError> {
+        use std::io::Read;
+
+        // The length comes from the input, so the buffer only grows as data actually arrives.
+        let mut data = Vec::with_capacity(length.min(64 * 1024));
+        (&mut *self.reader)
+            .take(length as u64)
+            .read_to_end(&mut data)?;
+        self.position += data.len() as u64;
+        Ok(data)
+    }
+
+    fn position(&self) -> u64 {
+        self.position
+    }
+}
@@ -0,0 +1,100 @@
+//! Decoding into caller-provided RGBA buffers.
+
+use crate::png_parser::{PngError, PngFile};
+
+/// A destination for 8-bit RGBA pixels, such as a framebuffer or another library's image type.
+pub trait RgbaSink {
+    /// Called once before any rows are written.
+    fn set_dimensions(&mut self, width: u32, height: u32);
+
+    /// Writes row `y`, which holds four bytes per pixel. Rows are written from top to bottom.
+    fn write_row(&mut self, y: u32, row: &[u8]);
+}
+
+impl PngFile {
+    /// Converts the image to 8-bit RGBA like [`to_rgba8`](Self::to_rgba8) and writes it into `sink` row by row.
+    pub fn decode_into_sink<S: RgbaSink>(&self, sink: &mut S) -> Result<(), PngError> {
+        let header = self.try_parse_header()?;
+        let rgba = self.to_rgba8()?;
+
+        sink.set_dimensions(header.width, header.height);
+
+        // Zero-width images have no bytes to split into rows, but still have `height` empty rows.
+        let stride = header.width as usize * 4;
+        for y in 0..header.height {
+            let start = y as usize * stride;
+            sink.write_row(y, &rgba[start..start + stride]);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "image")]
+impl RgbaSink for image::RgbaImage {
+    fn set_dimensions(&mut self, width: u32, height: u32) {
+        *self = image::RgbaImage::new(width, height);
+    }
+
+    fn write_row(&mut self, y: u32, row: &[u8]) {
+        let start = y as usize * row.len();
+        (**self)[start..start + row.len()].copy_from_slice(row);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::test_util::{ihdr, parse, zlib};
+
+    struct Framebuffer {
+        width: u32,
+        rows: Vec<(u32, Vec<u8>)>,
+    }
+
+    impl RgbaSink for Framebuffer {
+        fn set_dimensions(&mut self, width: u32, _height: u32) {
+            self.width = width;
+        }
+
+        fn write_row(&mut self, y: u32, row: &[u8]) {
+            self.rows.push((y, row.to_vec()));
+        }
+    }
+
+    #[test]
+    fn writes_rows_into_sink() {
+        let png = parse(&[
+            (b"IHDR", &ihdr(2, 2, 8, 0)),
+            (b"IDAT", &zlib(&[0, 10, 20, 0, 30, 40])),
+            (b"IEND", &[]),
+        ]);
+
+        let mut framebuffer = Framebuffer {
+            width: 0,
+            rows: Vec::new(),
+        };
+        png.decode_into_sink(&mut framebuffer).unwrap();
+
+        assert_eq!(framebuffer.width, 2);
+        assert_eq!(
+            framebuffer.rows,
+            vec![
+                (0, vec![10, 10, 10, 255, 20, 20, 20, 255]),
+                (1, vec![30, 30, 30, 255, 40, 40, 40, 255]),
+            ]
+        );
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn fills_rgba_image() {
+        let png = PngFile::from_bytes(&std::fs::read("./code.png").unwrap()).unwrap();
+
+        let mut image = image::RgbaImage::new(0, 0);
+        png.decode_into_sink(&mut image).unwrap();
+
+        assert_eq!(image.dimensions(), (105, 105));
+        assert_eq!(image.into_raw(), png.to_rgba8().unwrap());
+    }
+}
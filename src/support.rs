@@ -0,0 +1,209 @@
+//! Checks whether this crate can decode a file, before trying to.
+
+use alloc::string::{String, ToString};
+
+use crate::png_parser::{
+    check_bit_depth, BitDepth, ChunkType, ColourType, InterlaceMethod, PngError, PngFile,
+};
+
+/// How well one property of an image is supported. The reasons are meant for people, not for matching on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Support {
+    Full,
+    /// The image decodes, but some APIs can't handle it.
+    Partial(String),
+    Unsupported(String),
+}
+
+impl Support {
+    fn from_result<T>(result: Result<T, PngError>) -> Self {
+        match result {
+            Ok(_) => Support::Full,
+            Err(err) => Support::Unsupported(err.to_string()),
+        }
+    }
+}
+
+/// Support for each field of the IHDR chunk, which between them decide how the image data is decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decodability {
+    pub colour_type: Support,
+    pub bit_depth: Support,
+    pub compression_method: Support,
+    pub filter_method: Support,
+    pub interlace_method: Support,
+}
+
+impl Decodability {
+    fn fields(&self) -> [&Support; 5] {
+        [
+            &self.colour_type,
+            &self.bit_depth,
+            &self.compression_method,
+            &self.filter_method,
+            &self.interlace_method,
+        ]
+    }
+
+    /// Whether [`PngFile::decode_data`] can decode the image, as far as the header tells. The image data can
+    /// still be broken.
+    pub fn can_decode(&self) -> bool {
+        !self
+            .fields()
+            .iter()
+            .any(|support| matches!(support, Support::Unsupported(_)))
+    }
+
+    /// Whether every API can handle the image.
+    pub fn is_fully_supported(&self) -> bool {
+        self.fields()
+            .iter()
+            .all(|support| **support == Support::Full)
+    }
+}
+
+impl PngFile {
+    /// Reports which parts of the header this crate supports. Unlike [`try_parse_header`](Self::try_parse_header)
+    /// this doesn't stop at the first problem, so each field is judged on its own.
+    pub fn decodability(&self) -> Decodability {
+        let data = match self.chunks.first() {
+            Some(chunk) if chunk.chunk_type == ChunkType::IHDR && chunk.data.len() == 13 => {
+                &chunk.data
+            }
+            first => {
+                let err = match first {
+                    Some(chunk) if chunk.chunk_type == ChunkType::IHDR => {
+                        PngError::MalformedChunk(ChunkType::IHDR)
+                    }
+                    _ => PngError::MissingChunk(ChunkType::IHDR),
+                };
+                let unsupported = Support::Unsupported(err.to_string());
+
+                return Decodability {
+                    colour_type: unsupported.clone(),
+                    bit_depth: unsupported.clone(),
+                    compression_method: unsupported.clone(),
+                    filter_method: unsupported.clone(),
+                    interlace_method: unsupported,
+                };
+            }
+        };
+
+        let colour_type = ColourType::try_from(data[9]);
+        // Which depths are allowed depends on the colour type, so without one only the value itself is checked.
+        let bit_depth = BitDepth::try_from(data[8]).and_then(|bit_depth| match colour_type {
+            Ok(colour_type) => check_bit_depth(colour_type, bit_depth),
+            Err(_) => Ok(()),
+        });
+
+        let compression_method = match data[10] {
+            0 => Ok(()),
+            unknown => Err(PngError::UnknownCompressionMethod(unknown)),
+        };
+        let filter_method = match data[11] {
+            0 => Ok(()),
+            unknown => Err(PngError::UnknownFilterMethod(unknown)),
+        };
+
+        let interlace_method = match InterlaceMethod::try_from(data[12]) {
+            Ok(InterlaceMethod::Adam7) => Support::Partial(
+                "interlaced images can't be decoded by region, and are buffered in full when streamed".into(),
+            ),
+            result => Support::from_result(result),
+        };
+
+        let bit_depth = match bit_depth {
+            Ok(()) if data[8] < 8 => Support::Partial(
+                "pixels below 8 bits don't fill whole bytes, so they can't be sampled without expanding".into(),
+            ),
+            result => Support::from_result(result),
+        };
+
+        Decodability {
+            colour_type: Support::from_result(colour_type),
+            bit_depth,
+            compression_method: Support::from_result(compression_method),
+            filter_method: Support::from_result(filter_method),
+            interlace_method,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::test_util::{ihdr, parse, zlib};
+
+    fn header_decodability(header: &[u8]) -> Decodability {
+        parse(&[(b"IHDR", header), (b"IDAT", &zlib(&[0, 0])), (b"IEND", &[])]).decodability()
+    }
+
+    #[test]
+    fn supports_every_standard_format() {
+        let formats = [(8, 0), (16, 0), (8, 2), (16, 2), (8, 3), (8, 4), (16, 6)];
+
+        for (bit_depth, colour_type) in formats {
+            let decodability = header_decodability(&ihdr(1, 1, bit_depth, colour_type));
+
+            assert!(decodability.is_fully_supported(), "{:?}", decodability);
+        }
+
+        for (bit_depth, colour_type) in [(1, 0), (4, 0), (2, 3)] {
+            let decodability = header_decodability(&ihdr(1, 1, bit_depth, colour_type));
+
+            assert!(decodability.can_decode());
+            assert!(
+                matches!(decodability.bit_depth, Support::Partial(_)),
+                "{:?}",
+                decodability
+            );
+        }
+
+        let mut interlaced = ihdr(1, 1, 8, 0);
+        interlaced[12] = 1;
+        let decodability = header_decodability(&interlaced);
+
+        assert!(decodability.can_decode());
+        assert!(!decodability.is_fully_supported());
+        assert!(matches!(decodability.interlace_method, Support::Partial(_)));
+    }
+
+    #[test]
+    fn judges_each_field_separately() {
+        let mut header = ihdr(1, 1, 16, 3);
+        header[11] = 1;
+        let decodability = header_decodability(&header);
+
+        assert!(!decodability.can_decode());
+        assert_eq!(decodability.colour_type, Support::Full);
+        assert!(matches!(
+            decodability.bit_depth,
+            Support::Unsupported(reason) if reason.contains("combination")
+        ));
+        assert_eq!(decodability.compression_method, Support::Full);
+        assert_eq!(
+            decodability.filter_method,
+            Support::Unsupported("invalid filter method 1".into())
+        );
+        assert_eq!(decodability.interlace_method, Support::Full);
+
+        let unknown_colour = header_decodability(&ihdr(1, 1, 8, 5));
+        assert_eq!(
+            unknown_colour.colour_type,
+            Support::Unsupported("invalid colour type 5".into())
+        );
+        assert_eq!(unknown_colour.bit_depth, Support::Full);
+    }
+
+    #[test]
+    fn rejects_missing_header() {
+        let png = parse(&[(b"IDAT", &zlib(&[0, 0])), (b"IEND", &[])]);
+        let decodability = png.decodability();
+
+        assert!(!decodability.can_decode());
+        assert_eq!(
+            decodability.interlace_method,
+            Support::Unsupported("missing IHDR chunk".into())
+        );
+    }
+}
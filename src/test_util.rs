@@ -0,0 +1,47 @@
+// Most tests need `std` for the fixture files and the encoder, so without it only some of these are used.
+#![cfg_attr(not(feature = "std"), allow(dead_code))]
+
+use alloc::vec::Vec;
+
+use crate::{
+    crc::chunk_crc,
+    png_parser::{PngFile, MAGIC},
+};
+
+pub fn png_bytes(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+
+    for (chunk_type, data) in chunks {
+        bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(*chunk_type);
+        bytes.extend_from_slice(data);
+        bytes.extend_from_slice(&chunk_crc(**chunk_type, data).to_be_bytes());
+    }
+
+    bytes
+}
+
+pub fn parse(chunks: &[(&[u8; 4], &[u8])]) -> PngFile {
+    let bytes = png_bytes(chunks);
+    PngFile::from_bytes(&bytes).unwrap()
+}
+
+pub fn ihdr(width: u32, height: u32, bit_depth: u8, colour_type: u8) -> Vec<u8> {
+    let mut data = Vec::with_capacity(13);
+    data.extend_from_slice(&width.to_be_bytes());
+    data.extend_from_slice(&height.to_be_bytes());
+    data.extend_from_slice(&[bit_depth, colour_type, 0, 0, 0]);
+    data
+}
+
+#[cfg(feature = "std")]
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    deflate::deflate_bytes_zlib(data)
+}
+
+/// Deterministic, not too compressible sample data.
+pub fn pattern(len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+        .collect()
+}
@@ -0,0 +1,374 @@
+//! Checks the structure of a PNG while reading it, without decoding the image.
+
+use std::io::{BufRead, BufReader, Read};
+
+use alloc::vec::Vec;
+
+use crate::{
+    crc::chunk_crc,
+    png_parser::{
+        parse_chunk, ChunkType, ColourType, Palette, PngChunk, PngError, PngHeader, MAGIC,
+        SINGLE_INSTANCE_CHUNKS,
+    },
+    reader::{ChunkSource, IoSource},
+};
+
+/// Chunks that must come before both PLTE and the image data.
+const BEFORE_PALETTE: [ChunkType; 5] = [
+    ChunkType::cHRM,
+    ChunkType::gAMA,
+    ChunkType::iCCP,
+    ChunkType::sBIT,
+    ChunkType::sRGB,
+];
+
+/// Chunks that must come after PLTE, if there is one, and before the image data.
+const AFTER_PALETTE: [ChunkType; 3] = [ChunkType::tRNS, ChunkType::bKGD, ChunkType::hIST];
+
+/// Chunks that only have to come before the image data.
+const BEFORE_IMAGE_DATA: [ChunkType; 4] = [
+    ChunkType::pHYs,
+    ChunkType::sPLT,
+    ChunkType::eXIf,
+    crate::apng::ACTL,
+];
+
+/// Reads a whole PNG from `reader` and returns every problem found, in file order, instead of stopping at the
+/// first. Checks the signature, the CRC of every chunk, chunk order and multiplicity, the header, the palette
+/// and transparency chunks against the header, and that IEND ends the file.
+///
+/// The image data is never inflated, and only one chunk is held in memory at a time, which makes this fast
+/// enough to lint large numbers of files. A problem that makes the rest of the input unreadable, such as a
+/// chunk cut short, is the last one reported.
+pub fn validate_stream<R: Read>(reader: &mut R) -> Vec<PngError> {
+    let mut validator = StreamValidator::default();
+    validator.run(&mut BufReader::new(reader));
+    validator.errors
+}
+
+#[derive(Default)]
+struct StreamValidator {
+    errors: Vec<PngError>,
+    /// Types of the chunks read so far, in file order.
+    seen: Vec<ChunkType>,
+    header: Option<PngHeader>,
+    palette_len: Option<usize>,
+}
+
+impl StreamValidator {
+    fn run<R: Read>(&mut self, reader: &mut BufReader<R>) {
+        match IoSource::new(reader).read_array::<8>() {
+            Ok(magic) if magic != MAGIC => self.errors.push(PngError::InvalidMagic(magic)),
+            Ok(_) => {}
+            Err(err) => return self.errors.push(err),
+        }
+
+        loop {
+            // Running out of input between chunks means IEND is missing, rather than that a chunk is cut short.
+            match reader.fill_buf() {
+                Ok([]) => return self.errors.push(PngError::MissingChunk(ChunkType::IEND)),
+                Ok(_) => {}
+                Err(err) => return self.errors.push(err.into()),
+            }
+
+            let chunk = match parse_chunk(&mut IoSource::new(reader)) {
+                Ok(chunk) => PngChunk::from(chunk),
+                Err(err) => return self.errors.push(err),
+            };
+
+            self.check_chunk(&chunk);
+            self.seen.push(chunk.chunk_type);
+
+            if chunk.chunk_type == ChunkType::IEND {
+                break;
+            }
+        }
+
+        if !self.seen.contains(&ChunkType::IDAT) {
+            self.errors.push(PngError::MissingImageData);
+        }
+
+        match reader.fill_buf() {
+            Ok([]) => {}
+            Ok(_) => self.errors.push(PngError::TrailingData),
+            Err(err) => self.errors.push(err.into()),
+        }
+    }
+
+    fn has_seen(&self, chunk_type: ChunkType) -> bool {
+        self.seen.contains(&chunk_type)
+    }
+
+    fn check_chunk(&mut self, chunk: &PngChunk) {
+        let chunk_type = chunk.chunk_type;
+
+        if !chunk.is_crc_valid() {
+            self.errors.push(PngError::CrcMismatch {
+                chunk_type,
+                stored: chunk.crc(),
+                computed: chunk_crc(chunk_type.into(), &chunk.data),
+            });
+        }
+
+        if self.seen.is_empty() && chunk_type != ChunkType::IHDR {
+            self.errors.push(PngError::UnexpectedChunkType {
+                expected: ChunkType::IHDR,
+                was: chunk_type,
+            });
+        }
+
+        if let ChunkType::Unknown(bytes) = chunk_type {
+            if chunk_type.is_critical() {
+                self.errors.push(PngError::UnknownCriticalChunk(bytes));
+            }
+        }
+
+        if SINGLE_INSTANCE_CHUNKS.contains(&chunk_type) && self.has_seen(chunk_type) {
+            // The first one is the one that counts, so later ones aren't checked any further.
+            return self.errors.push(PngError::DuplicateChunk(chunk_type));
+        }
+
+        let after_image_data = self.has_seen(ChunkType::IDAT);
+        let misplaced = match chunk_type {
+            ChunkType::PLTE => {
+                after_image_data || AFTER_PALETTE.iter().any(|&after| self.has_seen(after))
+            }
+            ChunkType::IDAT => self.seen.last() != Some(&ChunkType::IDAT) && after_image_data,
+            chunk_type if BEFORE_PALETTE.contains(&chunk_type) => {
+                after_image_data || self.has_seen(ChunkType::PLTE)
+            }
+            chunk_type if AFTER_PALETTE.contains(&chunk_type) => after_image_data,
+            chunk_type if BEFORE_IMAGE_DATA.contains(&chunk_type) => after_image_data,
+            _ => false,
+        };
+
+        if misplaced {
+            self.errors.push(match chunk_type {
+                ChunkType::IDAT => PngError::NonContiguousIdat,
+                chunk_type => PngError::MisplacedChunk(chunk_type),
+            });
+        }
+
+        match chunk_type {
+            ChunkType::IHDR => match PngHeader::try_from(chunk) {
+                Ok(header) => self.header = Some(header),
+                Err(err) => self.errors.push(err),
+            },
+            ChunkType::PLTE => self.check_palette(chunk),
+            ChunkType::tRNS => self.check_transparency(chunk),
+            // Indexed images need their palette by the time the image data starts.
+            ChunkType::IDAT
+                if !after_image_data
+                    && self.colour_type() == Some(ColourType::IndexedColour)
+                    && !self.has_seen(ChunkType::PLTE) =>
+            {
+                self.errors.push(PngError::MissingChunk(ChunkType::PLTE));
+            }
+            _ => {}
+        }
+    }
+
+    fn colour_type(&self) -> Option<ColourType> {
+        self.header.map(|header| header.colour_type())
+    }
+
+    fn check_palette(&mut self, chunk: &PngChunk) {
+        if matches!(
+            self.colour_type(),
+            Some(ColourType::Greyscale | ColourType::GreyscaleWithAlpha)
+        ) {
+            return self.errors.push(PngError::MisplacedChunk(ChunkType::PLTE));
+        }
+
+        let palette = match Palette::try_from(chunk) {
+            Ok(palette) => palette,
+            Err(err) => return self.errors.push(err),
+        };
+        self.palette_len = Some(palette.len());
+
+        if let Some(header) = self.header {
+            let max = 1 << header.bit_depth().bits();
+
+            if header.colour_type() == ColourType::IndexedColour && palette.len() > max {
+                self.errors.push(PngError::PaletteTooLargeForDepth {
+                    entries: palette.len(),
+                    max,
+                });
+            }
+        }
+    }
+
+    fn check_transparency(&mut self, chunk: &PngChunk) {
+        let length = chunk.data.len();
+
+        let valid = match self.colour_type() {
+            Some(ColourType::Greyscale) => length == 2,
+            Some(ColourType::Truecolour) => length == 6,
+            Some(ColourType::IndexedColour) => match self.palette_len {
+                Some(palette_len) => length <= palette_len,
+                None => return self.errors.push(PngError::MissingChunk(ChunkType::PLTE)),
+            },
+            Some(ColourType::GreyscaleWithAlpha | ColourType::TruecolourWithAlpha) => {
+                return self.errors.push(PngError::MisplacedChunk(ChunkType::tRNS));
+            }
+            // Without a valid header there's nothing to check against.
+            None => true,
+        };
+
+        if !valid {
+            self.errors.push(PngError::MalformedChunk(ChunkType::tRNS));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{ihdr, png_bytes, zlib};
+
+    fn validate(bytes: &[u8]) -> Vec<PngError> {
+        validate_stream(&mut &*bytes)
+    }
+
+    #[test]
+    fn accepts_valid_files() {
+        for path in ["./FL.png", "./code.png", "./indexed-4bit-interlaced.png"] {
+            let errors = validate(&std::fs::read(path).unwrap());
+
+            assert!(errors.is_empty(), "{}: {:?}", path, errors);
+        }
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let mut bytes = png_bytes(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"tRNS", &[0, 0, 0]),
+            (b"IDAT", &zlib(&[0, 0])),
+            (b"gAMA", &45455u32.to_be_bytes()),
+            (b"gAMA", &45455u32.to_be_bytes()),
+            (b"IEND", &[]),
+        ]);
+        // Corrupts the CRC of IHDR, which ends 8 + 8 + 13 + 4 bytes in.
+        bytes[32] ^= 1;
+        bytes.push(0);
+
+        let errors = validate(&bytes);
+
+        assert!(
+            matches!(
+                errors.as_slice(),
+                [
+                    PngError::CrcMismatch {
+                        chunk_type: ChunkType::IHDR,
+                        ..
+                    },
+                    PngError::MalformedChunk(ChunkType::tRNS),
+                    PngError::MisplacedChunk(ChunkType::gAMA),
+                    PngError::DuplicateChunk(ChunkType::gAMA),
+                    PngError::TrailingData,
+                ]
+            ),
+            "{:?}",
+            errors
+        );
+    }
+
+    #[test]
+    fn checks_palette_against_header() {
+        let image_data = zlib(&[0, 0]);
+
+        let grey_palette = png_bytes(&[
+            (b"IHDR", &ihdr(1, 1, 8, 0)),
+            (b"PLTE", &[0; 3]),
+            (b"IDAT", &image_data),
+            (b"IEND", &[]),
+        ]);
+        assert!(matches!(
+            validate(&grey_palette).as_slice(),
+            [PngError::MisplacedChunk(ChunkType::PLTE)]
+        ));
+
+        let missing_palette = png_bytes(&[
+            (b"IHDR", &ihdr(1, 1, 2, 3)),
+            (b"IDAT", &image_data),
+            (b"IEND", &[]),
+        ]);
+        assert!(matches!(
+            validate(&missing_palette).as_slice(),
+            [PngError::MissingChunk(ChunkType::PLTE)]
+        ));
+
+        let oversized = png_bytes(&[
+            (b"IHDR", &ihdr(1, 1, 1, 3)),
+            (b"PLTE", &[0; 9]),
+            (b"tRNS", &[0; 4]),
+            (b"IDAT", &image_data),
+            (b"IEND", &[]),
+        ]);
+        assert!(matches!(
+            validate(&oversized).as_slice(),
+            [
+                PngError::PaletteTooLargeForDepth { entries: 3, max: 2 },
+                PngError::MalformedChunk(ChunkType::tRNS),
+            ]
+        ));
+    }
+
+    #[test]
+    fn reports_unreadable_input_last() {
+        assert!(matches!(
+            validate(&[0xff, 0xd8]).as_slice(),
+            [PngError::UnexpectedEof]
+        ));
+
+        let unterminated = png_bytes(&[(b"IHDR", &ihdr(1, 1, 8, 0)), (b"IDAT", &zlib(&[0, 0]))]);
+        assert!(matches!(
+            validate(&unterminated).as_slice(),
+            [PngError::MissingChunk(ChunkType::IEND)]
+        ));
+
+        let mut not_png = unterminated.clone();
+        not_png[0] = 0;
+        // Cuts into the IDAT data, past its CRC.
+        not_png.truncate(not_png.len() - 5);
+        assert!(matches!(
+            validate(&not_png).as_slice(),
+            [
+                PngError::InvalidMagic(_),
+                PngError::TruncatedChunk {
+                    chunk_type: ChunkType::IDAT,
+                    ..
+                }
+            ]
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        let empty = png_bytes(&[
+            (b"IHDR", &ihdr(0, 5, 8, 0)),
+            (b"IDAT", &zlib(&[])),
+            (b"IEND", &[]),
+        ]);
+        assert!(matches!(
+            validate(&empty).as_slice(),
+            [PngError::InvalidDimensions {
+                width: 0,
+                height: 5
+            }]
+        ));
+
+        let mut huge_chunk = png_bytes(&[(b"IHDR", &ihdr(1, 1, 8, 0)), (b"IEND", &[])]);
+        // Overwrites the length of IEND with one past the largest allowed, and renames it.
+        huge_chunk[33..37].copy_from_slice(&0x8000_0000u32.to_be_bytes());
+        huge_chunk[37..41].copy_from_slice(b"IDAT");
+        assert!(matches!(
+            validate(&huge_chunk).as_slice(),
+            [PngError::InvalidChunkLength {
+                chunk_type: ChunkType::IDAT,
+                length: 0x8000_0000
+            }]
+        ));
+    }
+}